@@ -2,13 +2,15 @@ use std::sync::mpsc::{channel, Receiver, Sender};
 
 use log::trace;
 
+use crate::exitcode::ExitReason;
+
 /// States that can be reported back to a service subsystem.
 #[derive(Debug)]
 pub enum AppState {
   Starting(u32),
   Started,
   Stopping(u32),
-  Stopped
+  Stopped(ExitReason)
 }
 
 pub struct AppStateSender {
@@ -46,10 +48,10 @@ impl AppStateSender {
   }
 
   /// Called to notify the service module that the shutdown process has been
-  /// complete.
-  pub fn stopped(&self) {
+  /// complete, and why the application stopped.
+  pub fn stopped(&self, reason: ExitReason) {
     trace!("sending AppState::Stopped");
-    self.tx.send(AppState::Stopped).unwrap();
+    self.tx.send(AppState::Stopped(reason)).unwrap();
     trace!("AppState::Stopped sent");
   }
 }
@@ -1,9 +1,8 @@
-use std::path::PathBuf;
-use std::str::FromStr;
+use std::path::{Path, PathBuf};
 
 use qargparser as arg;
 
-use crate::loglevel::LogLevel;
+use crate::portable::{self, Rewrite};
 
 use crate::err::Error;
 
@@ -13,14 +12,349 @@ pub(crate) enum SvcAction {
   Uninstall
 }
 
+/// What the `config` command should do with the service's parameters.
+#[derive(Debug, Clone)]
+pub(crate) enum ConfigAction {
+  Get(String),
+  Set(String, String),
+  Unset(String),
+  List { json: bool }
+}
+
+/// Commands which take the form `verboten <command> NAME ...`.
+#[derive(Debug, Clone)]
+pub(crate) enum Cmd {
+  /// List the service's transcripts, or print the selected one.
+  Transcripts {
+    service_name: String,
+    which: Option<String>
+  },
+
+  /// Validate the service's configuration and report all problems.
+  CheckConfig { service_name: String },
+
+  /// Get, set, unset or list the service's parameters.
+  Config {
+    service_name: String,
+    action: ConfigAction
+  },
+
+  /// Change the options of an installed service.  Only the install options
+  /// of `ctx` are used.
+  Reconfigure {
+    service_name: String,
+    ctx: Context
+  },
+
+  /// Ask the running service to reload its configuration.
+  Reload { service_name: String },
+
+  /// Show what the service is doing.
+  Status { service_name: String, json: bool },
+
+  /// Print the service's configuration in a portable format.
+  Export { service_name: String },
+
+  /// Create or update a service from an exported configuration.
+  Import {
+    fname: PathBuf,
+    service_name: Option<String>,
+    rewrite: Option<Rewrite>
+  }
+}
+
 #[derive(Default, Debug, Clone)]
 pub(crate) struct Context {
   pub(crate) do_help: bool,
   pub(crate) do_version: bool,
   pub(crate) service_name: Option<String>,
   pub(crate) svcaction: Option<SvcAction>,
-  pub(crate) msvsmon: Option<PathBuf>,
-  pub(crate) loglevel: Option<LogLevel>
+  pub(crate) exec: Option<PathBuf>,
+  pub(crate) profile: Option<String>,
+
+  /// Kept as given, and validated along with the rest of the configuration.
+  pub(crate) loglevel: Option<String>,
+
+  /// Print what would be done instead of doing it.
+  pub(crate) dry_run: bool,
+
+  // Uninstall options.
+  pub(crate) stop_timeout: Option<String>,
+  pub(crate) force: bool,
+  pub(crate) keep_config: bool,
+  pub(crate) purge: bool,
+
+  // The remaining install options are kept as given, and validated along
+  // with the rest of the configuration.
+  pub(crate) port: Option<String>,
+  pub(crate) timeout: Option<String>,
+  pub(crate) start_type: Option<String>,
+  pub(crate) display_name: Option<String>,
+  pub(crate) description: Option<String>,
+  pub(crate) dependencies: Option<Vec<String>>
+}
+
+
+/// Usage of the commands, printed after the regular usage.
+const CMD_USAGE: &str = "\
+Commands:
+  transcripts NAME [latest|FILE]
+      List the transcripts of service NAME, or print the latest or the named
+      one.
+  config NAME get KEY
+  config NAME set KEY VALUE
+  config NAME unset KEY
+  config NAME list [--json]
+      Get, set, unset or list the service parameters of service NAME.
+  reconfigure NAME [--exec EXEC] [-p PROFILE] [-L LEVEL] [--port PORT]
+              [--timeout TIMEOUT] [--start-type TYPE] [--display-name NAME]
+              [--description TEXT] [--depend SERVICES] [--dry-run]
+      Change the options service NAME was installed with, leaving the ones
+      not given as they are.  With --dry-run only print what would change.
+  reload NAME
+      Ask the running service NAME to reload its configuration.
+  status NAME [--json]
+      Show the state of service NAME, its process ids, port, session and
+      restarts.
+  export NAME
+      Print the configuration of service NAME as TOML.
+  import FILE [--name NAME] [--rewrite-exec FROM=TO]
+      Create or update a service from a file written by export.  The
+      beginning of the Exec path can be rewritten from FROM to TO.
+  --check-config NAME
+      Check the configuration of service NAME and print all problems.  Exits
+      with a non-zero status if any were found.";
+
+
+/// Names of the commands.  The service manager launches a service as
+/// `verboten NAME`, so these can't be used as service names.
+const COMMANDS: &[&str] = &[
+  "transcripts",
+  "config",
+  "reconfigure",
+  "reload",
+  "status",
+  "export",
+  "import"
+];
+
+
+/// Fail if `service_name` would be taken for a command or an option when
+/// the service is launched.
+pub(crate) fn check_service_name(service_name: &str) -> Result<(), Error> {
+  if COMMANDS.contains(&service_name) {
+    return Err(Error::BadInput(format!(
+      "'{}' is a command, and can't be used as a service name",
+      service_name
+    )));
+  }
+  if service_name.is_empty() || service_name.starts_with('-') {
+    return Err(Error::BadInput(format!(
+      "Invalid service name '{}'",
+      service_name
+    )));
+  }
+  Ok(())
+}
+
+
+/// Parse the command line if it invokes one of the commands.
+///
+/// Returns `Ok(None)` if it doesn't, in which case the command line should
+/// be parsed using `parse()`.
+pub(crate) fn parse_cmd() -> Result<Option<Cmd>, Error> {
+  let args: Vec<String> = std::env::args().skip(1).collect();
+  parse_cmd_args(&args)
+}
+
+
+fn parse_cmd_args(args: &[String]) -> Result<Option<Cmd>, Error> {
+  let (cmd, rest) = match args.split_first() {
+    Some((cmd, rest)) => (cmd.as_str(), rest),
+    None => return Ok(None)
+  };
+
+  match cmd {
+    "transcripts" => match rest {
+      [name] => Ok(Some(Cmd::Transcripts {
+        service_name: name.clone(),
+        which: None
+      })),
+      [name, which] => Ok(Some(Cmd::Transcripts {
+        service_name: name.clone(),
+        which: Some(which.clone())
+      })),
+      _ => Err(Error::ArgParser(String::from(
+        "Usage: verboten transcripts NAME [latest|FILE]"
+      )))
+    },
+    "config" => {
+      let action = match rest.get(1..).unwrap_or(&[]) {
+        [get, key] if get == "get" => ConfigAction::Get(key.clone()),
+        [set, key, value] if set == "set" => {
+          ConfigAction::Set(key.clone(), value.clone())
+        }
+        [unset, key] if unset == "unset" => ConfigAction::Unset(key.clone()),
+        [list] if list == "list" => ConfigAction::List { json: false },
+        [list, json] if list == "list" && json == "--json" => {
+          ConfigAction::List { json: true }
+        }
+        _ => {
+          return Err(Error::ArgParser(String::from(
+            "Usage: verboten config NAME get KEY | set KEY VALUE | \
+             unset KEY | list [--json]"
+          )));
+        }
+      };
+      Ok(Some(Cmd::Config {
+        service_name: rest[0].clone(),
+        action
+      }))
+    }
+    "reconfigure" => parse_reconfigure(rest).map(Some),
+    "reload" => match rest {
+      [name] => Ok(Some(Cmd::Reload {
+        service_name: name.clone()
+      })),
+      _ => Err(Error::ArgParser(String::from("Usage: verboten reload NAME")))
+    },
+    "status" => match rest {
+      [name] => Ok(Some(Cmd::Status {
+        service_name: name.clone(),
+        json: false
+      })),
+      [name, json] if json == "--json" => Ok(Some(Cmd::Status {
+        service_name: name.clone(),
+        json: true
+      })),
+      _ => Err(Error::ArgParser(String::from(
+        "Usage: verboten status NAME [--json]"
+      )))
+    },
+    "export" => match rest {
+      [name] => Ok(Some(Cmd::Export {
+        service_name: name.clone()
+      })),
+      _ => Err(Error::ArgParser(String::from("Usage: verboten export NAME")))
+    },
+    "import" => {
+      let usage = || {
+        Error::ArgParser(String::from(
+          "Usage: verboten import FILE [--name NAME] [--rewrite-exec FROM=TO]"
+        ))
+      };
+      let (fname, mut opts) = match rest.split_first() {
+        Some((fname, opts)) => (PathBuf::from(fname), opts),
+        None => return Err(usage())
+      };
+      let mut service_name = None;
+      let mut rewrite = None;
+      while let [opt, value, tail @ ..] = opts {
+        match opt.as_str() {
+          "--name" => service_name = Some(value.clone()),
+          "--rewrite-exec" => rewrite = Some(portable::parse_rewrite(value)?),
+          _ => return Err(usage())
+        }
+        opts = tail;
+      }
+      if !opts.is_empty() {
+        return Err(usage());
+      }
+      Ok(Some(Cmd::Import {
+        fname,
+        service_name,
+        rewrite
+      }))
+    }
+    "--check-config" => match rest {
+      [name] => Ok(Some(Cmd::CheckConfig {
+        service_name: name.clone()
+      })),
+      _ => Err(Error::ArgParser(String::from(
+        "Usage: verboten --check-config NAME"
+      )))
+    },
+    _ => Ok(None)
+  }
+}
+
+
+/// Parse the arguments of the `reconfigure` command.
+fn parse_reconfigure(rest: &[String]) -> Result<Cmd, Error> {
+  let usage = || {
+    Error::ArgParser(String::from(
+      "Usage: verboten reconfigure NAME [--exec EXEC] [-p PROFILE] \
+       [-L LEVEL] [--port PORT] [--timeout TIMEOUT] [--start-type TYPE] \
+       [--display-name NAME] [--description TEXT] [--depend SERVICES] \
+       [--dry-run]"
+    ))
+  };
+  let (service_name, mut opts) = rest.split_first().ok_or_else(usage)?;
+
+  let mut ctx = Context {
+    service_name: Some(service_name.clone()),
+    ..Default::default()
+  };
+  while let [opt, tail @ ..] = opts {
+    if opt == "--dry-run" {
+      ctx.dry_run = true;
+      opts = tail;
+      continue;
+    }
+    let (value, tail) = tail.split_first().ok_or_else(usage)?;
+    match opt.as_str() {
+      "-e" | "--exec" => ctx.exec = Some(absolute_exec(Path::new(value))?),
+      "-p" | "--profile" => ctx.profile = Some(value.clone()),
+      "-L" | "--log-level" => ctx.loglevel = Some(value.clone()),
+      "--port" => ctx.port = Some(value.clone()),
+      "--timeout" => ctx.timeout = Some(value.clone()),
+      "--start-type" => ctx.start_type = Some(value.clone()),
+      "--display-name" => ctx.display_name = Some(value.clone()),
+      "--description" => ctx.description = Some(value.clone()),
+      "--depend" => ctx.dependencies = Some(split_list(value)),
+      _ => return Err(usage())
+    }
+    opts = tail;
+  }
+
+  Ok(Cmd::Reconfigure {
+    service_name: service_name.clone(),
+    ctx
+  })
+}
+
+
+/// Absolute path of the debug server executable `exec`, which must exist.
+fn absolute_exec(exec: &Path) -> Result<PathBuf, Error> {
+  if !exec.exists() {
+    return Err(Error::BadInput(format!(
+      "Debug server executable not found at {:?}",
+      exec
+    )));
+  }
+  let exec = std::fs::canonicalize(exec).map_err(|_| {
+    Error::BadInput(String::from(
+      "Unable to get the absolute path of the debug server."
+    ))
+  })?;
+
+  // Windows' canonical paths are verbatim paths, which not everything can
+  // handle.
+  const PREFIX: &str = r#"\\?\"#;
+  match exec.to_str().and_then(|s| s.strip_prefix(PREFIX)) {
+    Some(stripped) => Ok(PathBuf::from(stripped)),
+    None => Ok(exec)
+  }
+}
+
+
+/// Split a comma-separated list, ignoring empty entries.
+fn split_list(s: &str) -> Vec<String> {
+  s.split(',')
+    .map(str::trim)
+    .filter(|s| !s.is_empty())
+    .map(String::from)
+    .collect()
 }
 
 
@@ -37,7 +371,7 @@ pub(crate) fn parse() -> Result<Context, Error> {
       .sopt('h')
       .lopt("help")
       .exit(true)
-      .help(&["Show this help."])
+      .help(["Show this help."])
       .build(|_spec, ctx: &mut Context, _args| {
         ctx.do_help = true;
       })
@@ -47,7 +381,7 @@ pub(crate) fn parse() -> Result<Context, Error> {
       .sopt('V')
       .exit(true)
       .lopt("version")
-      .help(&["Show version and exit."])
+      .help(["Show version and exit."])
       .build(|_spec, ctx: &mut Context, _args| {
         ctx.do_version = true;
       })
@@ -56,63 +390,177 @@ pub(crate) fn parse() -> Result<Context, Error> {
     arg::Builder::new()
       .sopt('L')
       .lopt("log-level")
-      .help(&[
+      .help([
         "Maximum level of log records.",
         "Values: off, error (default), warn, info, debug, trace"
       ])
-      .nargs(arg::Nargs::Count(1), &["LEVEL"])
+      .nargs(arg::Nargs::Count(1), ["LEVEL"])
       .build(|_spec, ctx: &mut Context, args| {
-        ctx.loglevel = Some(LogLevel::from_str(&args[0]).unwrap());
+        ctx.loglevel = Some(args[0].clone());
       })
   )?;
   prsr.add(
     arg::Builder::new()
       .sopt('i')
       .lopt("install")
-      .nargs(arg::Nargs::Count(1), &["EXEC"])
+      .nargs(arg::Nargs::Count(1), ["EXEC"])
       .help(
-        &[
+        [
           "Install service.  The EXEC argument must be the absolute path and \
-           filename of msvsmon.exe."
+           filename of the debug server executable (msvsmon.exe for the \
+           default profile)."
         ]
       )
       .build(|_spec, ctx: &mut Context, args| {
         ctx.svcaction = Some(SvcAction::Install);
-
-        let exec = PathBuf::from(&args[0]);
-        if !exec.exists() {
-          panic!("msvsmon.exe not found at {:?}", exec);
-        }
-        let exec = match std::fs::canonicalize(exec) {
-          Ok(exec) => {
-            const PREFIX: &str = r#"\\?\"#;
-            let exec_str = exec.to_str().unwrap();
-            if exec_str.starts_with(&PREFIX) {
-              PathBuf::from(exec_str.strip_prefix(PREFIX).unwrap())
-            } else {
-              exec
-            }
-          }
-          Err(_) => panic!("Unable to get the absolute path of msvsmon.")
-        };
-
-        ctx.msvsmon = Some(exec);
+        ctx.exec = Some(PathBuf::from(&args[0]));
+      })
+  )?;
+  prsr.add(
+    arg::Builder::new()
+      .sopt('p')
+      .lopt("profile")
+      .help([
+        "Debug server profile to install the service with.",
+        "Values: msvsmon (default), gdbserver, lldb-server, debugpy, dlv"
+      ])
+      .nargs(arg::Nargs::Count(1), ["PROFILE"])
+      .build(|_spec, ctx: &mut Context, args| {
+        ctx.profile = Some(args[0].clone());
+      })
+  )?;
+  prsr.add(
+    arg::Builder::new()
+      .lopt("port")
+      .help(["Port the debug server listens on (default: the profile's)."])
+      .nargs(arg::Nargs::Count(1), ["PORT"])
+      .build(|_spec, ctx: &mut Context, args| {
+        ctx.port = Some(args[0].clone());
+      })
+  )?;
+  prsr.add(
+    arg::Builder::new()
+      .lopt("timeout")
+      .help([
+        "How long a debug session lasts (default: 1day).",
+        "Values: a duration such as 8h, or none"
+      ])
+      .nargs(arg::Nargs::Count(1), ["TIMEOUT"])
+      .build(|_spec, ctx: &mut Context, args| {
+        ctx.timeout = Some(args[0].clone());
+      })
+  )?;
+  prsr.add(
+    arg::Builder::new()
+      .lopt("start-type")
+      .help([
+        "When the service is started.",
+        "Values: auto (default), manual, delayed (Windows only)"
+      ])
+      .nargs(arg::Nargs::Count(1), ["TYPE"])
+      .build(|_spec, ctx: &mut Context, args| {
+        ctx.start_type = Some(args[0].clone());
+      })
+  )?;
+  prsr.add(
+    arg::Builder::new()
+      .lopt("display-name")
+      .help(["Display name of the service."])
+      .nargs(arg::Nargs::Count(1), ["NAME"])
+      .build(|_spec, ctx: &mut Context, args| {
+        ctx.display_name = Some(args[0].clone());
+      })
+  )?;
+  prsr.add(
+    arg::Builder::new()
+      .lopt("description")
+      .help(["Description of the service."])
+      .nargs(arg::Nargs::Count(1), ["TEXT"])
+      .build(|_spec, ctx: &mut Context, args| {
+        ctx.description = Some(args[0].clone());
+      })
+  )?;
+  prsr.add(
+    arg::Builder::new()
+      .lopt("depend")
+      .help([
+        "Comma-separated list of services the service depends on, in \
+         addition to the network."
+      ])
+      .nargs(arg::Nargs::Count(1), ["SERVICES"])
+      .build(|_spec, ctx: &mut Context, args| {
+        ctx.dependencies = Some(split_list(&args[0]));
       })
   )?;
   prsr.add(
     arg::Builder::new()
       .sopt('u')
       .lopt("uninstall")
-      .help(&["Uninstall service with name NAME and exit."])
+      .help(["Uninstall service with name NAME and exit."])
       .build(|_spec, ctx: &mut Context, _args| {
         ctx.svcaction = Some(SvcAction::Uninstall);
       })
   )?;
+  prsr.add(
+    arg::Builder::new()
+      .lopt("stop-timeout")
+      .help([
+        "How long to wait for the service to stop when uninstalling it \
+         (default: 30s)."
+      ])
+      .nargs(arg::Nargs::Count(1), ["TIMEOUT"])
+      .build(|_spec, ctx: &mut Context, args| {
+        ctx.stop_timeout = Some(args[0].clone());
+      })
+  )?;
+  prsr.add(
+    arg::Builder::new()
+      .lopt("force")
+      .help([
+        "Kill the service and all of its processes if it doesn't stop in \
+         time when uninstalling it, rather than giving up."
+      ])
+      .build(|_spec, ctx: &mut Context, _args| {
+        ctx.force = true;
+      })
+  )?;
+  prsr.add(
+    arg::Builder::new()
+      .lopt("keep-config")
+      .help([
+        "Keep the service parameters when uninstalling, for when the \
+         service is installed again."
+      ])
+      .build(|_spec, ctx: &mut Context, _args| {
+        ctx.keep_config = true;
+      })
+  )?;
+  prsr.add(
+    arg::Builder::new()
+      .lopt("purge")
+      .help([
+        "Also remove the service's transcripts, state files and firewall \
+         rules when uninstalling."
+      ])
+      .build(|_spec, ctx: &mut Context, _args| {
+        ctx.purge = true;
+      })
+  )?;
+  prsr.add(
+    arg::Builder::new()
+      .lopt("dry-run")
+      .help([
+        "Print what installing or uninstalling would do, without doing it."
+      ])
+      .build(|_spec, ctx: &mut Context, _args| {
+        ctx.dry_run = true;
+      })
+  )?;
   prsr.add(
     arg::Builder::new()
       .required(true)
-      .nargs(arg::Nargs::Count(1), &["NAME"])
-      .help(&["Use service name NAME."])
+      .nargs(arg::Nargs::Count(1), ["NAME"])
+      .help(["Use service name NAME."])
       .build(|_spec, ctx: &mut Context, args| {
         ctx.service_name = Some(args[0].clone());
       })
@@ -122,17 +570,54 @@ pub(crate) fn parse() -> Result<Context, Error> {
 
   if prsr.get_ctx().do_help {
     prsr.usage(&mut std::io::stdout());
+    println!("\n{}", CMD_USAGE);
     return Ok(prsr.into_ctx());
   }
 
   if prsr.get_ctx().do_version {
-    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+    const VERSION: &str = env!("CARGO_PKG_VERSION");
     println!("verboten {}", VERSION);
     return Ok(prsr.into_ctx());
   }
 
+  // The option handlers can't fail, so the debug server executable is
+  // checked once parsing is done.
+  let mut ctx = prsr.into_ctx();
+  if let Some(exec) = ctx.exec.take() {
+    ctx.exec = Some(absolute_exec(&exec)?);
+  }
 
-  Ok(prsr.into_ctx())
+  Ok(ctx)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|s| s.to_string()).collect()
+  }
+
+  #[test]
+  fn commands_are_reserved() {
+    for cmd in COMMANDS {
+      assert!(check_service_name(cmd).is_err(), "{}", cmd);
+      // Anything parsed as a command must be reserved.
+      let res = parse_cmd_args(&args(&[cmd, "x"]));
+      assert!(!matches!(res, Ok(None)), "{}", cmd);
+    }
+    assert!(check_service_name("").is_err());
+    assert!(check_service_name("--check-config").is_err());
+  }
+
+  #[test]
+  fn service_names() {
+    for name in ["msvsmon", "gdb-server", "Status", "statuses"] {
+      assert!(check_service_name(name).is_ok(), "{}", name);
+      assert!(matches!(parse_cmd_args(&args(&[name])), Ok(None)));
+    }
+  }
 }
 
 // vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,570 @@
+//! The supervised debug server process (tree).
+//!
+//! The debug server is put in a process group of its own on unix-like
+//! platforms, and in a job object on Windows, so that the whole process tree
+//! it spawns can be stopped together.
+
+use std::io;
+use std::process::{Child, Command, ExitStatus};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use log::trace;
+
+use crate::event::{Clock, Event, EventSender};
+use crate::outlog::{self, Stream};
+use crate::transcript::Transcript;
+
+
+pub(crate) struct ServerProcess {
+  child: Child,
+  /// Exit status of the debug server itself, once it has been waited for.
+  #[cfg_attr(windows, allow(dead_code))]
+  status: Option<ExitStatus>,
+  /// Threads forwarding the debug server's output.
+  output: Vec<thread::JoinHandle<()>>,
+  #[cfg(windows)]
+  job: job::Job
+}
+
+impl ServerProcess {
+  /// Spawn the debug server process.
+  #[cfg(unix)]
+  pub(crate) fn spawn(cmd: &mut Command) -> io::Result<Self> {
+    use std::os::unix::process::CommandExt;
+
+    // Processes which are orphaned by the debug server are reparented to us
+    // rather than to init, so we can reap them.
+    #[cfg(target_os = "linux")]
+    unsafe {
+      libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0);
+    }
+
+    let child = cmd.process_group(0).spawn()?;
+
+    Ok(ServerProcess {
+      child,
+      status: None,
+      output: Vec::new()
+    })
+  }
+
+  /// Spawn the debug server process.
+  ///
+  /// The debug server is started suspended and only resumed once it has
+  /// been assigned to the job object, so that every process it spawns is
+  /// part of the job.
+  ///
+  /// The debug server shares our console, in a process group of its own so
+  /// that console control events sent to it don't reach us.  A service
+  /// starts out without a console, so it is given one first.
+  #[cfg(windows)]
+  pub(crate) fn spawn(cmd: &mut Command) -> io::Result<Self> {
+    use std::os::windows::io::AsRawHandle;
+    use std::os::windows::process::CommandExt;
+    use winapi::um::consoleapi::AllocConsole;
+    use winapi::um::winbase::{CREATE_NEW_PROCESS_GROUP, CREATE_SUSPENDED};
+
+    let job = job::Job::new()?;
+
+    // Fails if there already is a console, which is just as good.
+    unsafe {
+      AllocConsole();
+    }
+    cmd.creation_flags(CREATE_NEW_PROCESS_GROUP | CREATE_SUSPENDED);
+
+    let mut child = cmd.spawn()?;
+    let res = job
+      .assign(child.as_raw_handle())
+      .and_then(|_| resume(child.id()));
+    if let Err(e) = res {
+      let _ = child.kill();
+      let _ = child.wait();
+      return Err(e);
+    }
+
+    Ok(ServerProcess {
+      child,
+      status: None,
+      output: Vec::new(),
+      job
+    })
+  }
+
+  pub(crate) fn id(&self) -> u32 {
+    self.child.id()
+  }
+
+  /// Log the output of the debug server, and write it to `transcript`, if
+  /// it was spawned with piped stdout and/or stderr.
+  pub(crate) fn forward_output(
+    &mut self,
+    name: &str,
+    transcript: Option<Arc<Transcript>>
+  ) -> io::Result<()> {
+    let pid = self.id();
+    if let Some(out) = self.child.stdout.take() {
+      self.output.push(outlog::forward(
+        name,
+        pid,
+        Stream::Stdout,
+        out,
+        transcript.clone()
+      )?);
+    }
+    if let Some(err) = self.child.stderr.take() {
+      self.output.push(outlog::forward(
+        name,
+        pid,
+        Stream::Stderr,
+        err,
+        transcript
+      )?);
+    }
+    Ok(())
+  }
+
+  /// Wait for up to `timeout`, as measured by `clock`, for all of the debug
+  /// server's output to have been forwarded.
+  ///
+  /// The output streams are only closed once every process holding them is
+  /// gone, which may never happen if one has escaped the process tree.
+  /// Returns `false` if the output is still open.
+  pub(crate) fn drain_output(
+    &mut self,
+    clock: &dyn Clock,
+    timeout: Duration
+  ) -> bool {
+    let until = clock.now() + timeout;
+    while self.output.iter().any(|h| !h.is_finished()) {
+      if clock.now() >= until {
+        return false;
+      }
+      clock.sleep(Duration::from_millis(10));
+    }
+    for h in self.output.drain(..) {
+      let _ = h.join();
+    }
+    true
+  }
+
+  pub(crate) fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+    let res = self.child.try_wait()?;
+    if res.is_some() {
+      self.status = res;
+    }
+    Ok(res)
+  }
+
+  pub(crate) fn wait(&mut self) -> io::Result<ExitStatus> {
+    let status = self.child.wait()?;
+    self.status = Some(status);
+    Ok(status)
+  }
+
+  /// Send an `Event::ChildExited` on `tx` once the debug server process has
+  /// exited.
+  ///
+  /// The process is not reaped; that is left to `try_wait()`.
+  #[cfg(unix)]
+  pub(crate) fn notify_exit(&self, tx: EventSender) -> io::Result<()> {
+    let pid = self.id();
+    thread::Builder::new()
+      .name(format!("exit-waiter-{}", pid))
+      .spawn(move || {
+        loop {
+          let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+          let res = unsafe {
+            libc::waitid(
+              libc::P_PID,
+              pid as libc::id_t,
+              &mut info,
+              libc::WEXITED | libc::WNOWAIT
+            )
+          };
+          if res != 0
+            && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted
+          {
+            continue;
+          }
+          // Any other error means the process has already been reaped,
+          // which is as good as having exited.
+          break;
+        }
+        trace!("process {} has exited", pid);
+        tx.send(Event::ChildExited(pid));
+      })?;
+    Ok(())
+  }
+
+  /// Send an `Event::ChildExited` on `tx` once the debug server process has
+  /// exited.
+  #[cfg(windows)]
+  pub(crate) fn notify_exit(&self, tx: EventSender) -> io::Result<()> {
+    use std::os::windows::io::AsRawHandle;
+    use std::ptr;
+    use winapi::um::handleapi::{CloseHandle, DuplicateHandle};
+    use winapi::um::processthreadsapi::GetCurrentProcess;
+    use winapi::um::synchapi::WaitForSingleObject;
+    use winapi::um::winbase::INFINITE;
+    use winapi::um::winnt::{HANDLE, SYNCHRONIZE};
+
+    // The waiter gets a handle of its own, so it doesn't matter if the
+    // process object is dropped while it is waiting.
+    let mut handle: HANDLE = ptr::null_mut();
+    let res = unsafe {
+      DuplicateHandle(
+        GetCurrentProcess(),
+        self.child.as_raw_handle() as HANDLE,
+        GetCurrentProcess(),
+        &mut handle,
+        SYNCHRONIZE,
+        0,
+        0
+      )
+    };
+    if res == 0 {
+      return Err(io::Error::last_os_error());
+    }
+
+    struct Waitable(HANDLE);
+    unsafe impl Send for Waitable {}
+    let waitable = Waitable(handle);
+
+    let pid = self.id();
+    thread::Builder::new()
+      .name(format!("exit-waiter-{}", pid))
+      .spawn(move || {
+        let waitable = waitable;
+        unsafe {
+          WaitForSingleObject(waitable.0, INFINITE);
+          CloseHandle(waitable.0);
+        }
+        trace!("process {} has exited", pid);
+        tx.send(Event::ChildExited(pid));
+      })?;
+    Ok(())
+  }
+
+  /// Ask the process tree to terminate.
+  ///
+  /// On unix-like platforms this sends `SIGTERM` to the process group.
+  #[cfg(unix)]
+  pub(crate) fn request_stop(&self) -> io::Result<()> {
+    trace!("sending SIGTERM to process group {}", self.id());
+    killpg(self.id(), libc::SIGTERM)
+  }
+
+  /// Ask the process tree to terminate.
+  ///
+  /// On Windows this posts `WM_CLOSE` to the top-level windows of all
+  /// processes in the job, and sends a `CTRL_BREAK_EVENT` to the debug
+  /// server's process group for console programs.  A program with neither,
+  /// such as `msvsmon /silent`, can't be asked, and is killed once the grace
+  /// period is over.
+  #[cfg(windows)]
+  pub(crate) fn request_stop(&self) -> io::Result<()> {
+    use winapi::um::wincon::{GenerateConsoleCtrlEvent, CTRL_BREAK_EVENT};
+
+    let mut closed = 0;
+    for pid in self.job.pids()? {
+      closed += winclose::post_close(pid);
+    }
+    if closed > 0 {
+      trace!("posted WM_CLOSE to {} window(s)", closed);
+    }
+
+    let pid = self.id();
+    trace!("sending CTRL_BREAK_EVENT to process group {}", pid);
+    if unsafe { GenerateConsoleCtrlEvent(CTRL_BREAK_EVENT, pid) } == 0
+      && closed == 0
+    {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(())
+  }
+
+  /// Forcefully kill the whole process tree.  A tree which is already gone
+  /// is not an error.
+  #[cfg(unix)]
+  pub(crate) fn kill(&mut self) -> io::Result<()> {
+    trace!("sending SIGKILL to process group {}", self.id());
+    killpg(self.id(), libc::SIGKILL)
+  }
+
+  /// Forcefully kill the whole process tree.  A tree which is already gone
+  /// is not an error.
+  #[cfg(windows)]
+  pub(crate) fn kill(&mut self) -> io::Result<()> {
+    trace!("terminating job of {}", self.id());
+    self.job.terminate()
+  }
+
+  /// Returns `true` if any process in the tree is still alive.
+  ///
+  /// Leftover processes are only reaped once the debug server itself has
+  /// been waited for.
+  #[cfg(unix)]
+  pub(crate) fn is_tree_alive(&mut self) -> bool {
+    let pgid = self.id() as libc::pid_t;
+    if self.status.is_some() {
+      // Reap any group members which have been reparented to us.
+      loop {
+        let mut st = 0;
+        let res =
+          unsafe { libc::waitpid(-pgid, &mut st as *mut _, libc::WNOHANG) };
+        if res <= 0 {
+          break;
+        }
+        trace!("reaped leftover process {}", res);
+      }
+    }
+    unsafe { libc::kill(-pgid, 0) == 0 }
+  }
+
+  /// Returns `true` if any process in the tree is still alive.
+  #[cfg(windows)]
+  pub(crate) fn is_tree_alive(&mut self) -> bool {
+    match self.job.active_processes() {
+      Ok(n) => n > 0,
+      Err(_) => false
+    }
+  }
+}
+
+
+/// Send a signal to a process group.  A group which no longer exists is not
+/// considered an error.
+#[cfg(unix)]
+fn killpg(pgid: u32, sig: libc::c_int) -> io::Result<()> {
+  if unsafe { libc::killpg(pgid as libc::pid_t, sig) } != 0 {
+    let e = io::Error::last_os_error();
+    if e.raw_os_error() != Some(libc::ESRCH) {
+      return Err(e);
+    }
+  }
+  Ok(())
+}
+
+
+/// Resume the threads of the suspended process `pid`.
+#[cfg(windows)]
+fn resume(pid: u32) -> io::Result<()> {
+  use std::mem;
+  use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+  use winapi::um::processthreadsapi::{OpenThread, ResumeThread};
+  use winapi::um::tlhelp32::{
+    CreateToolhelp32Snapshot, Thread32First, Thread32Next, TH32CS_SNAPTHREAD,
+    THREADENTRY32
+  };
+  use winapi::um::winnt::THREAD_SUSPEND_RESUME;
+
+  let snapshot = unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0) };
+  if snapshot == INVALID_HANDLE_VALUE {
+    return Err(io::Error::last_os_error());
+  }
+
+  let mut resumed = 0;
+  let mut res = Ok(());
+  let mut entry: THREADENTRY32 = unsafe { mem::zeroed() };
+  entry.dwSize = mem::size_of_val(&entry) as u32;
+  let mut more = unsafe { Thread32First(snapshot, &mut entry) } != 0;
+  while more && res.is_ok() {
+    if entry.th32OwnerProcessID == pid {
+      let thread =
+        unsafe { OpenThread(THREAD_SUSPEND_RESUME, 0, entry.th32ThreadID) };
+      if thread.is_null() || unsafe { ResumeThread(thread) } == u32::MAX {
+        res = Err(io::Error::last_os_error());
+      } else {
+        resumed += 1;
+      }
+      if !thread.is_null() {
+        unsafe {
+          CloseHandle(thread);
+        }
+      }
+    }
+    more = unsafe { Thread32Next(snapshot, &mut entry) } != 0;
+  }
+  unsafe {
+    CloseHandle(snapshot);
+  }
+
+  res?;
+  if resumed == 0 {
+    return Err(io::Error::new(
+      io::ErrorKind::NotFound,
+      format!("no threads of process {} to resume", pid)
+    ));
+  }
+  trace!("resumed {} thread(s) of process {}", resumed, pid);
+  Ok(())
+}
+
+
+#[cfg(windows)]
+mod job {
+  use std::io;
+  use std::mem;
+  use std::os::windows::io::RawHandle;
+  use std::ptr;
+
+  use winapi::shared::basetsd::ULONG_PTR;
+  use winapi::shared::minwindef::{DWORD, LPVOID};
+  use winapi::um::handleapi::CloseHandle;
+  use winapi::um::jobapi2::{
+    AssignProcessToJobObject, CreateJobObjectW, QueryInformationJobObject,
+    SetInformationJobObject, TerminateJobObject
+  };
+  use winapi::um::winnt::{
+    JobObjectBasicAccountingInformation, JobObjectBasicProcessIdList,
+    JobObjectExtendedLimitInformation, HANDLE,
+    JOBOBJECT_BASIC_ACCOUNTING_INFORMATION,
+    JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE
+  };
+
+  const MAX_PIDS: usize = 256;
+
+  #[repr(C)]
+  struct PidList {
+    _assigned: DWORD,
+    listed: DWORD,
+    pids: [ULONG_PTR; MAX_PIDS]
+  }
+
+  /// A job object which kills all its processes when it is closed.
+  pub(super) struct Job {
+    handle: HANDLE
+  }
+
+  // The handle is only used through the job object API, which is
+  // thread-safe.
+  unsafe impl Send for Job {}
+
+  impl Job {
+    pub(super) fn new() -> io::Result<Self> {
+      let handle = unsafe { CreateJobObjectW(ptr::null_mut(), ptr::null()) };
+      if handle.is_null() {
+        return Err(io::Error::last_os_error());
+      }
+      let job = Job { handle };
+
+      let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION =
+        unsafe { mem::zeroed() };
+      info.BasicLimitInformation.LimitFlags =
+        JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+      let res = unsafe {
+        SetInformationJobObject(
+          job.handle,
+          JobObjectExtendedLimitInformation,
+          &mut info as *mut _ as LPVOID,
+          mem::size_of_val(&info) as DWORD
+        )
+      };
+      if res == 0 {
+        return Err(io::Error::last_os_error());
+      }
+
+      Ok(job)
+    }
+
+    pub(super) fn assign(&self, process: RawHandle) -> io::Result<()> {
+      if unsafe { AssignProcessToJobObject(self.handle, process as HANDLE) }
+        == 0
+      {
+        return Err(io::Error::last_os_error());
+      }
+      Ok(())
+    }
+
+    pub(super) fn terminate(&self) -> io::Result<()> {
+      if unsafe { TerminateJobObject(self.handle, 1) } == 0 {
+        return Err(io::Error::last_os_error());
+      }
+      Ok(())
+    }
+
+    pub(super) fn active_processes(&self) -> io::Result<u32> {
+      let mut info: JOBOBJECT_BASIC_ACCOUNTING_INFORMATION =
+        unsafe { mem::zeroed() };
+      let res = unsafe {
+        QueryInformationJobObject(
+          self.handle,
+          JobObjectBasicAccountingInformation,
+          &mut info as *mut _ as LPVOID,
+          mem::size_of_val(&info) as DWORD,
+          ptr::null_mut()
+        )
+      };
+      if res == 0 {
+        return Err(io::Error::last_os_error());
+      }
+      Ok(info.ActiveProcesses)
+    }
+
+    pub(super) fn pids(&self) -> io::Result<Vec<u32>> {
+      let mut list: PidList = unsafe { mem::zeroed() };
+      let res = unsafe {
+        QueryInformationJobObject(
+          self.handle,
+          JobObjectBasicProcessIdList,
+          &mut list as *mut _ as LPVOID,
+          mem::size_of_val(&list) as DWORD,
+          ptr::null_mut()
+        )
+      };
+      if res == 0 {
+        return Err(io::Error::last_os_error());
+      }
+      let n = std::cmp::min(list.listed as usize, MAX_PIDS);
+      Ok(list.pids[..n].iter().map(|pid| *pid as u32).collect())
+    }
+  }
+
+  impl Drop for Job {
+    fn drop(&mut self) {
+      unsafe {
+        CloseHandle(self.handle);
+      }
+    }
+  }
+}
+
+
+#[cfg(windows)]
+mod winclose {
+  use winapi::shared::minwindef::{BOOL, DWORD, LPARAM, TRUE};
+  use winapi::shared::windef::HWND;
+  use winapi::um::winuser::{
+    EnumWindows, GetWindowThreadProcessId, PostMessageW, WM_CLOSE
+  };
+
+  struct Search {
+    pid: DWORD,
+    count: usize
+  }
+
+  unsafe extern "system" fn enum_proc(hwnd: HWND, lparam: LPARAM) -> BOOL {
+    let search = &mut *(lparam as *mut Search);
+    let mut pid: DWORD = 0;
+    GetWindowThreadProcessId(hwnd, &mut pid);
+    if pid == search.pid && PostMessageW(hwnd, WM_CLOSE, 0, 0) != 0 {
+      search.count += 1;
+    }
+    TRUE
+  }
+
+  /// Post `WM_CLOSE` to all top-level windows owned by process `pid`.
+  ///
+  /// Returns the number of windows the message was posted to.
+  pub(super) fn post_close(pid: u32) -> usize {
+    let mut search = Search { pid, count: 0 };
+    unsafe {
+      EnumWindows(Some(enum_proc), &mut search as *mut Search as LPARAM);
+    }
+    search.count
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
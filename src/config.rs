@@ -0,0 +1,770 @@
+//! Typed service configuration.
+//!
+//! The configuration of a service is built from layered sources, where each
+//! source overrides the values of the ones before it:
+//!
+//! 1. Built-in defaults.
+//! 2. The service's configuration file, `<service name>.toml` in the
+//!    configuration root directory.
+//! 3. The service parameters (the registry on Windows, the service's
+//!    parameters file on Linux).
+//! 4. Environment variables named `VERBOTEN_` followed by the parameter name
+//!    in upper snake case (`VERBOTEN_LOG_LEVEL` for `LogLevel`).
+//! 5. The command line.
+//!
+//! The source of each value is recorded, so it can be reported where a value
+//! came from.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use crate::args;
+use crate::err::Error;
+use crate::loglevel::LogLevel;
+use crate::params::{FileFormat, FileStore, MemoryStore, ParamStore};
+use crate::paths;
+use crate::profile::{self, Auth};
+use crate::restart::Policy;
+
+
+/// Names of all configuration parameters.
+pub(crate) const KEYS: &[&str] = &[
+  "Profile",
+  "Exec",
+  "Port",
+  "Timeout",
+  "Auth",
+  "LogLevel",
+  "RestartPolicy",
+  "RestartDelay",
+  "RestartMaxDelay",
+  "RestartLimit",
+  "RestartWindow",
+  "StopGrace",
+  "ReadyTimeout",
+  "HealthInterval",
+  "HealthTimeout",
+  "HealthFailures",
+  "TranscriptDir",
+  "TranscriptKeep",
+  "TranscriptMaxAge"
+];
+
+
+/// Where a configuration value came from.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Source {
+  Default,
+  File(PathBuf),
+  Params,
+  Env(String),
+  Cli
+}
+
+impl fmt::Display for Source {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Source::Default => write!(f, "default"),
+      Source::File(path) => write!(f, "config file {:?}", path),
+      Source::Params => write!(f, "service parameters"),
+      Source::Env(name) => write!(f, "environment variable {}", name),
+      Source::Cli => write!(f, "command line")
+    }
+  }
+}
+
+
+/// How serious a configuration problem is.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Severity {
+  /// The offending parameter is ignored.
+  Warning,
+
+  /// The service refuses to start.
+  Error
+}
+
+
+/// A problem found while validating a service's configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Problem {
+  pub(crate) severity: Severity,
+
+  /// Where the offending value came from, if it came from a single source.
+  pub(crate) source: Option<Source>,
+
+  pub(crate) msg: String
+}
+
+impl Problem {
+  fn error(source: Option<Source>, e: Error) -> Self {
+    let msg = match e {
+      Error::BadFormat(s) | Error::BadInput(s) => s,
+      e => e.to_string()
+    };
+    Problem {
+      severity: Severity::Error,
+      source,
+      msg
+    }
+  }
+
+  /// The message, followed by the source if there is one.
+  pub(crate) fn describe(&self) -> String {
+    match self.source {
+      Some(ref source) => format!("{} ({})", self.msg, source),
+      None => self.msg.clone()
+    }
+  }
+}
+
+impl fmt::Display for Problem {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.severity {
+      Severity::Warning => write!(f, "warning: {}", self.describe()),
+      Severity::Error => write!(f, "error: {}", self.describe())
+    }
+  }
+}
+
+
+#[derive(Debug, Clone)]
+pub(crate) struct Config {
+  /// Name of the debug server profile.
+  pub(crate) profile: String,
+
+  /// Debug server executable.  If not set the profile's executable is used.
+  pub(crate) exec: Option<PathBuf>,
+
+  /// Port the debug server listens on.  If not set the profile's default
+  /// port is used.
+  pub(crate) port: Option<u16>,
+
+  /// Session timeout.  `None` means no timeout.
+  pub(crate) timeout: Option<Duration>,
+
+  pub(crate) auth: Auth,
+  pub(crate) log_level: LogLevel,
+
+  pub(crate) restart_policy: Policy,
+  pub(crate) restart_delay: Duration,
+  pub(crate) restart_max_delay: Duration,
+  pub(crate) restart_limit: u32,
+  pub(crate) restart_window: Duration,
+
+  pub(crate) stop_grace: Duration,
+  pub(crate) ready_timeout: Duration,
+
+  /// Time between health checks.  `None` disables health checks.
+  pub(crate) health_interval: Option<Duration>,
+  pub(crate) health_timeout: Duration,
+  pub(crate) health_failures: u32,
+
+  /// Transcript directory.  If not set a per-service directory under the
+  /// transcript root is used.
+  pub(crate) transcript_dir: Option<PathBuf>,
+  pub(crate) transcript_keep: usize,
+  pub(crate) transcript_max_age: Duration,
+
+  origins: BTreeMap<&'static str, Source>,
+
+  /// Problems which did not prevent the configuration from being loaded.
+  warnings: Vec<Problem>
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Config {
+      profile: String::from(profile::DEFAULT_PROFILE),
+      exec: None,
+      port: None,
+      timeout: Some(Duration::from_secs(24 * 60 * 60)),
+      auth: Auth::default(),
+      log_level: LogLevel::default(),
+      restart_policy: Policy::default(),
+      restart_delay: Duration::from_secs(1),
+      restart_max_delay: Duration::from_secs(60),
+      restart_limit: 5,
+      restart_window: Duration::from_secs(10 * 60),
+      stop_grace: Duration::from_secs(10),
+      ready_timeout: Duration::from_secs(30),
+      health_interval: None,
+      health_timeout: Duration::from_secs(5),
+      health_failures: 3,
+      transcript_dir: None,
+      transcript_keep: 20,
+      transcript_max_age: Duration::from_secs(30 * 24 * 60 * 60),
+      origins: BTreeMap::new(),
+      warnings: Vec::new()
+    }
+  }
+}
+
+impl Config {
+  /// Build the configuration of the service `service_name` from its
+  /// service parameters in `params` and the command line `cli`.
+  ///
+  /// Fails if any parameter is invalid.  Unknown parameters are ignored, and
+  /// are reported by `log_warnings()`.
+  pub(crate) fn load(
+    service_name: &str,
+    params: &dyn ParamStore,
+    cli: &args::Context
+  ) -> Result<Self, Error> {
+    let (mut cfg, problems) = Config::check(service_name, params, cli);
+
+    let (errors, warnings): (Vec<_>, Vec<_>) = problems
+      .into_iter()
+      .partition(|p| p.severity == Severity::Error);
+    if !errors.is_empty() {
+      let errors: Vec<String> = errors.iter().map(|p| p.describe()).collect();
+      return Err(Error::BadFormat(errors.join("; ")));
+    }
+    cfg.warnings = warnings;
+
+    Ok(cfg)
+  }
+
+  /// Build the configuration of the service `service_name` like `load()`
+  /// does, but collect all problems instead of failing at the first error.
+  ///
+  /// The returned configuration has the offending values left at what the
+  /// sources before them set.
+  pub(crate) fn check(
+    service_name: &str,
+    params: &dyn ParamStore,
+    cli: &args::Context
+  ) -> (Self, Vec<Problem>) {
+    let mut cfg = Config::default();
+    let mut problems = Vec::new();
+
+    let fname = file_path(service_name);
+    let file = FileStore::new(fname.clone(), FileFormat::Toml);
+    cfg.apply_store(&file, |_| Source::File(fname.clone()), &mut problems);
+
+    cfg.apply_store(params, |_| Source::Params, &mut problems);
+
+    let env = env_params();
+    cfg.apply_store(&env, |key| Source::Env(env_name(key)), &mut problems);
+
+    if let Err(e) = cfg.apply_cli(cli) {
+      problems.push(Problem::error(Some(Source::Cli), e));
+    }
+
+    cfg.validate(&mut problems);
+
+    (cfg, problems)
+  }
+
+  /// Apply the values given on the command line.
+  pub(crate) fn apply_cli(
+    &mut self,
+    cli: &args::Context
+  ) -> Result<(), Error> {
+    if let Some(ref profile) = cli.profile {
+      self.set("Profile", profile, Source::Cli)?;
+    }
+    if let Some(ref exec) = cli.exec {
+      self.exec = Some(exec.clone());
+      self.origins.insert("Exec", Source::Cli);
+    }
+    if let Some(ref ll) = cli.loglevel {
+      self.set("LogLevel", ll, Source::Cli)?;
+    }
+    if let Some(ref port) = cli.port {
+      self.set("Port", port, Source::Cli)?;
+    }
+    if let Some(ref timeout) = cli.timeout {
+      self.set("Timeout", timeout, Source::Cli)?;
+    }
+    Ok(())
+  }
+
+  /// Apply all the parameters in `store`, recording the source of each as
+  /// returned by `source`.
+  fn apply_store<F>(
+    &mut self,
+    store: &dyn ParamStore,
+    source: F,
+    problems: &mut Vec<Problem>
+  ) where
+    F: Fn(&str) -> Source
+  {
+    let params = match store.list() {
+      Ok(params) => params,
+      Err(e) => {
+        problems.push(Problem::error(None, e));
+        return;
+      }
+    };
+
+    for (key, value) in params {
+      match self.set(&key, &value, source(&key)) {
+        Ok(true) => {}
+        Ok(false) => problems.push(Problem {
+          severity: Severity::Warning,
+          source: Some(source(&key)),
+          msg: unknown_key(&key)
+        }),
+        Err(e) => problems.push(Problem::error(Some(source(&key)), e))
+      }
+    }
+  }
+
+  /// Check the constraints which involve more than a single value.
+  fn validate(&self, problems: &mut Vec<Problem>) {
+    let mut error = |key: &str, msg: String| {
+      problems.push(Problem {
+        severity: Severity::Error,
+        source: Some(self.source(key)),
+        msg
+      });
+    };
+
+    match profile::lookup(&self.profile) {
+      Ok(profile) => {
+        if self.exec.is_none() && profile.exec().is_none() {
+          error(
+            "Exec",
+            format!(
+              "Exec is not set, and the {} profile has no default",
+              self.profile
+            )
+          );
+        }
+      }
+      Err(e) => error("Profile", Problem::error(None, e).msg)
+    }
+    if let Some(ref exec) = self.exec {
+      if !exec.is_file() {
+        error("Exec", format!("Exec {:?} does not exist", exec));
+      }
+    }
+    if self.port == Some(0) {
+      error("Port", String::from("Port must not be 0"));
+    }
+    if self.restart_delay > self.restart_max_delay {
+      error(
+        "RestartDelay",
+        format!(
+          "RestartDelay ({}) is longer than RestartMaxDelay ({})",
+          humantime::format_duration(self.restart_delay),
+          humantime::format_duration(self.restart_max_delay)
+        )
+      );
+    }
+    if self.ready_timeout == Duration::from_secs(0) {
+      error("ReadyTimeout", String::from("ReadyTimeout must not be 0"));
+    }
+    if self.health_timeout == Duration::from_secs(0) {
+      error("HealthTimeout", String::from("HealthTimeout must not be 0"));
+    }
+  }
+
+  /// Set the parameter `key` from its string representation.
+  ///
+  /// Returns `false` if there is no such parameter.
+  pub(crate) fn set(
+    &mut self,
+    key: &str,
+    value: &str,
+    source: Source
+  ) -> Result<bool, Error> {
+    let key = match KEYS.iter().find(|k| **k == key) {
+      Some(key) => *key,
+      None => return Ok(false)
+    };
+
+    match key {
+      "Profile" => self.profile = value.to_string(),
+      "Exec" => self.exec = Some(PathBuf::from(value)),
+      "Port" => self.port = Some(parse_num(key, value)?),
+      "Timeout" => self.timeout = parse_opt_duration(key, value)?,
+      "Auth" => self.auth = value.parse::<Auth>()?,
+      "LogLevel" => self.log_level = value.parse::<LogLevel>()?,
+      "RestartPolicy" => self.restart_policy = value.parse::<Policy>()?,
+      "RestartDelay" => self.restart_delay = parse_duration(key, value)?,
+      "RestartMaxDelay" => {
+        self.restart_max_delay = parse_duration(key, value)?
+      }
+      "RestartLimit" => self.restart_limit = parse_num(key, value)?,
+      "RestartWindow" => self.restart_window = parse_duration(key, value)?,
+      "StopGrace" => self.stop_grace = parse_duration(key, value)?,
+      "ReadyTimeout" => self.ready_timeout = parse_duration(key, value)?,
+      "HealthInterval" => {
+        self.health_interval = parse_opt_duration(key, value)?
+      }
+      "HealthTimeout" => self.health_timeout = parse_duration(key, value)?,
+      "HealthFailures" => {
+        let n: u32 = parse_num(key, value)?;
+        if n == 0 {
+          return Err(Error::BadFormat(String::from(
+            "HealthFailures must be at least 1"
+          )));
+        }
+        self.health_failures = n;
+      }
+      "TranscriptDir" => self.transcript_dir = Some(PathBuf::from(value)),
+      "TranscriptKeep" => self.transcript_keep = parse_num(key, value)?,
+      "TranscriptMaxAge" => {
+        self.transcript_max_age = parse_duration(key, value)?
+      }
+      _ => unreachable!("parameter {} not handled", key)
+    }
+    self.origins.insert(key, source);
+
+    Ok(true)
+  }
+
+  /// Get the string representation of the parameter `key`.
+  ///
+  /// Returns `None` for parameters which are not set, and for unknown
+  /// parameters.
+  pub(crate) fn get(&self, key: &str) -> Option<String> {
+    let dur = |d: &Duration| humantime::format_duration(*d).to_string();
+    let opt_dur = |d: &Option<Duration>| match d {
+      Some(d) => dur(d),
+      None => String::from("none")
+    };
+
+    match key {
+      "Profile" => Some(self.profile.clone()),
+      "Exec" => self.exec.as_ref().map(|p| p.display().to_string()),
+      "Port" => self.port.map(|p| p.to_string()),
+      "Timeout" => Some(opt_dur(&self.timeout)),
+      "Auth" => Some(self.auth.to_string()),
+      "LogLevel" => Some(self.log_level.to_string()),
+      "RestartPolicy" => Some(self.restart_policy.to_string()),
+      "RestartDelay" => Some(dur(&self.restart_delay)),
+      "RestartMaxDelay" => Some(dur(&self.restart_max_delay)),
+      "RestartLimit" => Some(self.restart_limit.to_string()),
+      "RestartWindow" => Some(dur(&self.restart_window)),
+      "StopGrace" => Some(dur(&self.stop_grace)),
+      "ReadyTimeout" => Some(dur(&self.ready_timeout)),
+      "HealthInterval" => Some(opt_dur(&self.health_interval)),
+      "HealthTimeout" => Some(dur(&self.health_timeout)),
+      "HealthFailures" => Some(self.health_failures.to_string()),
+      "TranscriptDir" => {
+        self.transcript_dir.as_ref().map(|p| p.display().to_string())
+      }
+      "TranscriptKeep" => Some(self.transcript_keep.to_string()),
+      "TranscriptMaxAge" => Some(dur(&self.transcript_max_age)),
+      _ => None
+    }
+  }
+
+  /// All parameters which have been set from some source other than the
+  /// defaults, with their string representations.
+  pub(crate) fn explicit(&self) -> Vec<(&'static str, String)> {
+    KEYS
+      .iter()
+      .filter(|key| self.source(key) != Source::Default)
+      .filter_map(|key| self.get(key).map(|value| (*key, value)))
+      .collect()
+  }
+
+  /// Where the value of the parameter `key` came from.
+  pub(crate) fn source(&self, key: &str) -> Source {
+    self.origins.get(key).cloned().unwrap_or(Source::Default)
+  }
+
+  /// Log the problems which did not prevent the configuration from being
+  /// loaded.
+  pub(crate) fn log_warnings(&self) {
+    for p in &self.warnings {
+      warn!("{}", p.describe());
+    }
+  }
+
+  /// Log every parameter's value and where it came from.
+  pub(crate) fn log_sources(&self) {
+    for key in KEYS {
+      match self.get(key) {
+        Some(value) => debug!("{} = {} ({})", key, value, self.source(key)),
+        None => debug!("{} is not set", key)
+      }
+    }
+  }
+}
+
+
+/// Look up the parameter `key`, failing if it is unknown.
+pub(crate) fn lookup_key(key: &str) -> Result<&'static str, Error> {
+  match KEYS.iter().find(|k| **k == key) {
+    Some(key) => Ok(*key),
+    None => Err(Error::BadInput(unknown_key(key)))
+  }
+}
+
+
+/// Describe the unknown parameter `key`, suggesting the parameter it was
+/// probably meant to be if it only differs in case.
+fn unknown_key(key: &str) -> String {
+  let mut msg = format!("Unknown parameter '{}'", key);
+  if let Some(known) = KEYS.iter().find(|k| k.eq_ignore_ascii_case(key)) {
+    msg.push_str(&format!("; did you mean '{}'?", known));
+  }
+  msg
+}
+
+
+/// Make sure that applying `change` to the parameters in `store` does not
+/// make the service's configuration invalid.
+///
+/// Problems which were there before the change are not held against it.
+pub(crate) fn check_change<F>(
+  service_name: &str,
+  store: &dyn ParamStore,
+  change: F
+) -> Result<(), Error>
+where
+  F: FnOnce(&mut MemoryStore) -> Result<(), Error>
+{
+  let mut params = MemoryStore::new();
+  for (k, v) in store.list()? {
+    params.set(&k, &v)?;
+  }
+
+  let cli = args::Context::default();
+  let errors = |params: &dyn ParamStore| -> Vec<Problem> {
+    Config::check(service_name, params, &cli)
+      .1
+      .into_iter()
+      .filter(|p| p.severity == Severity::Error)
+      .collect()
+  };
+
+  let before = errors(&params);
+  change(&mut params)?;
+  let added: Vec<String> = errors(&params)
+    .into_iter()
+    .filter(|p| !before.contains(p))
+    .map(|p| p.describe())
+    .collect();
+
+  if added.is_empty() {
+    Ok(())
+  } else {
+    Err(Error::BadInput(format!(
+      "Refusing to make the configuration invalid; {}",
+      added.join("; ")
+    )))
+  }
+}
+
+
+/// Path of the configuration file of the service `service_name`.
+pub(crate) fn file_path(service_name: &str) -> PathBuf {
+  paths::config_root().join(format!("{}.toml", service_name))
+}
+
+
+/// Parameters set through environment variables.
+#[cfg(not(test))]
+fn env_params() -> MemoryStore {
+  MemoryStore::from_env(KEYS, env_name)
+}
+
+/// Parameters set through environment variables.
+///
+/// Unit tests ignore the environment so that the results don't depend on
+/// whatever `VERBOTEN_*` variables happen to be set where they run.
+#[cfg(test)]
+fn env_params() -> MemoryStore {
+  MemoryStore::new()
+}
+
+
+/// Name of the environment variable which sets the parameter `key`.
+pub(crate) fn env_name(key: &str) -> String {
+  let mut name = String::from("VERBOTEN");
+  for c in key.chars() {
+    if c.is_ascii_uppercase() {
+      name.push('_');
+    }
+    name.push(c.to_ascii_uppercase());
+  }
+  name
+}
+
+
+fn parse_num<T>(key: &str, s: &str) -> Result<T, Error>
+where
+  T: FromStr,
+  T::Err: fmt::Display
+{
+  s.parse::<T>()
+    .map_err(|e| Error::BadFormat(format!("Invalid {} '{}'; {}", key, s, e)))
+}
+
+fn parse_duration(key: &str, s: &str) -> Result<Duration, Error> {
+  match s.parse::<humantime::Duration>() {
+    Ok(d) => Ok(d.into()),
+    Err(e) => {
+      Err(Error::BadFormat(format!("Invalid {} '{}'; {}", key, s, e)))
+    }
+  }
+}
+
+/// Parse a duration which can be disabled by setting it to `none` or zero.
+fn parse_opt_duration(key: &str, s: &str) -> Result<Option<Duration>, Error> {
+  if s == "none" {
+    return Ok(None);
+  }
+  let d = parse_duration(key, s)?;
+  if d == Duration::from_secs(0) {
+    Ok(None)
+  } else {
+    Ok(Some(d))
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A service which has no configuration file.
+  const SVC: &str = "verboten-unit-test";
+
+  /// Parameters making up a valid configuration.
+  fn valid() -> MemoryStore {
+    let mut params = MemoryStore::new();
+    let exec = std::env::current_exe().unwrap();
+    params.set("Exec", exec.to_str().unwrap()).unwrap();
+    params
+  }
+
+  fn errors(problems: &[Problem]) -> Vec<&Problem> {
+    problems
+      .iter()
+      .filter(|p| p.severity == Severity::Error)
+      .collect()
+  }
+
+  #[test]
+  fn defaults() {
+    let cfg = Config::load(SVC, &valid(), &args::Context::default()).unwrap();
+    assert_eq!(cfg.profile, profile::DEFAULT_PROFILE);
+    assert_eq!(cfg.timeout, Some(Duration::from_secs(24 * 60 * 60)));
+    assert_eq!(cfg.restart_policy, Policy::Never);
+    assert_eq!(cfg.source("Timeout"), Source::Default);
+    assert_eq!(cfg.source("Exec"), Source::Params);
+  }
+
+  #[test]
+  fn cli_overrides_params() {
+    let mut params = valid();
+    params.set("Port", "1000").unwrap();
+    params.set("Timeout", "1h").unwrap();
+    let cli = args::Context {
+      port: Some(String::from("2000")),
+      ..Default::default()
+    };
+    let cfg = Config::load(SVC, &params, &cli).unwrap();
+    assert_eq!(cfg.port, Some(2000));
+    assert_eq!(cfg.source("Port"), Source::Cli);
+    assert_eq!(cfg.timeout, Some(Duration::from_secs(60 * 60)));
+    assert_eq!(cfg.source("Timeout"), Source::Params);
+    assert_eq!(
+      cfg.explicit(),
+      [
+        ("Exec", cfg.get("Exec").unwrap()),
+        ("Port", String::from("2000")),
+        ("Timeout", String::from("1h"))
+      ]
+    );
+  }
+
+  #[test]
+  fn invalid_cli_value() {
+    let cli = args::Context {
+      loglevel: Some(String::from("loud")),
+      ..Default::default()
+    };
+    let (_cfg, problems) = Config::check(SVC, &valid(), &cli);
+    assert_eq!(errors(&problems).len(), 1);
+    assert_eq!(problems[0].source, Some(Source::Cli));
+    assert!(Config::load(SVC, &valid(), &cli).is_err());
+  }
+
+  #[test]
+  fn disabled_durations() {
+    let mut params = valid();
+    params.set("Timeout", "none").unwrap();
+    params.set("HealthInterval", "0s").unwrap();
+    let cfg = Config::load(SVC, &params, &args::Context::default()).unwrap();
+    assert_eq!(cfg.timeout, None);
+    assert_eq!(cfg.health_interval, None);
+    assert_eq!(cfg.get("Timeout").unwrap(), "none");
+  }
+
+  #[test]
+  fn invalid_values() {
+    let mut params = valid();
+    params.set("Port", "http").unwrap();
+    params.set("RestartDelay", "2min").unwrap();
+    params.set("HealthFailures", "0").unwrap();
+    let (_cfg, problems) =
+      Config::check(SVC, &params, &args::Context::default());
+    let errors = errors(&problems);
+    assert_eq!(errors.len(), 3, "{:?}", errors);
+    assert!(errors.iter().all(|p| p.source == Some(Source::Params)));
+    assert!(Config::load(SVC, &params, &args::Context::default()).is_err());
+  }
+
+  #[test]
+  fn missing_exec() {
+    let (_cfg, problems) =
+      Config::check(SVC, &MemoryStore::new(), &args::Context::default());
+    let errors = errors(&problems);
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].msg.starts_with("Exec is not set"));
+  }
+
+  #[test]
+  fn unknown_parameter() {
+    let mut params = valid();
+    params.set("loglevel", "debug").unwrap();
+    let (_cfg, problems) =
+      Config::check(SVC, &params, &args::Context::default());
+    assert_eq!(problems.len(), 1);
+    assert_eq!(problems[0].severity, Severity::Warning);
+    assert_eq!(
+      problems[0].msg,
+      "Unknown parameter 'loglevel'; did you mean 'LogLevel'?"
+    );
+  }
+
+  #[test]
+  fn get_set_round_trip() {
+    let mut cfg = Config::default();
+    for key in KEYS {
+      if let Some(value) = cfg.get(key) {
+        assert!(cfg.set(key, &value, Source::Params).unwrap(), "{}", key);
+        assert_eq!(cfg.get(key).unwrap(), value, "{}", key);
+      }
+    }
+    assert!(!cfg.set("Nope", "1", Source::Params).unwrap());
+  }
+
+  #[test]
+  fn env_names() {
+    assert_eq!(env_name("LogLevel"), "VERBOTEN_LOG_LEVEL");
+    assert_eq!(env_name("Port"), "VERBOTEN_PORT");
+  }
+
+  #[test]
+  fn change_check() {
+    let params = valid();
+    assert!(check_change(SVC, &params, |p| p.set("Port", "1")).is_ok());
+    assert!(check_change(SVC, &params, |p| p.set("Port", "0")).is_err());
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,148 @@
+//! The `config` command, which gets and changes a service's parameters.
+
+use crate::args::ConfigAction;
+use crate::config::{self, Config, Source};
+use crate::err::Error;
+use crate::params::ParamStore;
+
+
+/// Perform `action` on the parameters in `store` of the service
+/// `service_name`.
+pub(crate) fn run(
+  service_name: &str,
+  store: &mut dyn ParamStore,
+  action: ConfigAction
+) -> Result<(), Error> {
+  match action {
+    ConfigAction::Get(key) => {
+      let key = config::lookup_key(&key)?;
+      match store.get(key)? {
+        Some(value) => println!("{}", value),
+        None => {
+          return Err(Error::BadInput(format!(
+            "Parameter '{}' is not set in {}",
+            key,
+            store.location()
+          )));
+        }
+      }
+    }
+    ConfigAction::Set(key, value) => {
+      let key = config::lookup_key(&key)?;
+      Config::default().set(key, &value, Source::Params)?;
+      config::check_change(service_name, store, |params| {
+        params.set(key, &value)
+      })?;
+      store.set(key, &value)?;
+    }
+    ConfigAction::Unset(key) => {
+      let key = config::lookup_key(&key)?;
+      config::check_change(service_name, store, |params| params.unset(key))?;
+      store.unset(key)?;
+    }
+    ConfigAction::List { json } => {
+      let params = store.list()?;
+      if json {
+        let obj: serde_json::Map<String, serde_json::Value> = params
+          .into_iter()
+          .map(|(k, v)| (k, serde_json::Value::String(v)))
+          .collect();
+        let buf = serde_json::to_string_pretty(&obj).map_err(|e| {
+          Error::BadFormat(format!("Unable to serialize parameters; {}", e))
+        })?;
+        println!("{}", buf);
+      } else {
+        for (k, v) in params {
+          println!("{}={}", k, v);
+        }
+      }
+    }
+  }
+  Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::params::MemoryStore;
+
+  const SVC: &str = "verboten-unit-test";
+
+  /// Parameters making up a valid configuration.
+  fn valid() -> MemoryStore {
+    let mut params = MemoryStore::new();
+    let exec = std::env::current_exe().unwrap();
+    params.set("Exec", exec.to_str().unwrap()).unwrap();
+    params.set("Port", "4711").unwrap();
+    params
+  }
+
+  fn set(key: &str, value: &str) -> ConfigAction {
+    ConfigAction::Set(key.to_string(), value.to_string())
+  }
+
+  fn unset(key: &str) -> ConfigAction {
+    ConfigAction::Unset(key.to_string())
+  }
+
+  /// Run `action`, which must fail, and check that it didn't change the
+  /// parameters.
+  fn rejected(action: ConfigAction) -> String {
+    let mut store = valid();
+    let before = store.list().unwrap();
+    let e = match run(SVC, &mut store, action) {
+      Err(Error::BadInput(e)) | Err(Error::BadFormat(e)) => e,
+      res => panic!("{:?}", res)
+    };
+    assert_eq!(store.list().unwrap(), before);
+    e
+  }
+
+  #[test]
+  fn set_and_unset() {
+    let mut store = valid();
+    run(SVC, &mut store, set("Timeout", "2h")).unwrap();
+    run(SVC, &mut store, set("Port", "4712")).unwrap();
+    assert_eq!(store.get("Timeout").unwrap().as_deref(), Some("2h"));
+    assert_eq!(store.get("Port").unwrap().as_deref(), Some("4712"));
+
+    run(SVC, &mut store, unset("Port")).unwrap();
+    assert_eq!(store.get("Port").unwrap(), None);
+
+    run(SVC, &mut store, ConfigAction::Get(String::from("Timeout"))).unwrap();
+    run(SVC, &mut store, ConfigAction::List { json: true }).unwrap();
+  }
+
+  #[test]
+  fn unknown_keys() {
+    let e = rejected(set("port", "4712"));
+    assert_eq!(e, "Unknown parameter 'port'; did you mean 'Port'?");
+    rejected(unset("Nope"));
+    rejected(ConfigAction::Get(String::from("Nope")));
+  }
+
+  #[test]
+  fn invalid_values() {
+    rejected(set("Port", "http"));
+    rejected(set("Timeout", "soon"));
+    rejected(set("RestartPolicy", "sometimes"));
+  }
+
+  #[test]
+  fn invalid_changes() {
+    // Valid values on their own, which make the configuration invalid.
+    rejected(set("Profile", "no-such-profile"));
+    rejected(set("HealthFailures", "0"));
+    rejected(unset("Exec"));
+  }
+
+  #[test]
+  fn get_unset() {
+    let e = rejected(ConfigAction::Get(String::from("Timeout")));
+    assert!(e.starts_with("Parameter 'Timeout' is not set"), "{}", e);
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
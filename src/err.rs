@@ -10,6 +10,8 @@ pub enum Error {
   BadInput(String),
   ArgParser(String),
   Service(String),
+  Startup(String),
+  CrashLoop(String),
   EventLog(String),
   RegistryKey(String)
 }
@@ -28,18 +30,21 @@ impl<T> From<ap::ErrKind<T>> for Error {
   }
 }
 
+#[cfg(windows)]
 impl From<windows_service::Error> for Error {
   fn from(err: windows_service::Error) -> Self {
     Error::Service(err.to_string())
   }
 }
 
+#[cfg(windows)]
 impl From<eventlog::Error> for Error {
   fn from(err: eventlog::Error) -> Self {
     Error::EventLog(err.to_string())
   }
 }
 
+#[cfg(windows)]
 impl From<registry::key::Error> for Error {
   fn from(err: registry::key::Error) -> Self {
     Error::RegistryKey(err.to_string())
@@ -49,12 +54,14 @@ impl From<registry::key::Error> for Error {
 
 impl fmt::Display for Error {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    match &*self {
+    match self {
       Error::IO(s) => write!(f, "I/O error; {}", s),
       Error::BadFormat(s) => write!(f, "Bad format error; {}", s),
       Error::BadInput(s) => write!(f, "Bad input error; {}", s),
       Error::ArgParser(s) => write!(f, "ArgParser error; {}", s),
       Error::Service(s) => write!(f, "Service error; {}", s),
+      Error::Startup(s) => write!(f, "Startup error; {}", s),
+      Error::CrashLoop(s) => write!(f, "Crash loop error; {}", s),
       Error::EventLog(s) => write!(f, "EventLog error; {}", s),
       Error::RegistryKey(s) => write!(f, "Registry Key error; {}", s)
     }
@@ -0,0 +1,146 @@
+//! Events driving the supervisor loop.
+//!
+//! Everything the supervisor needs to react to (stop requests, the debug
+//! server exiting, health check results, reloaded configurations) is
+//! delivered as an event on a single channel.  Timers are implemented by
+//! waiting for the next event with a deadline.
+
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+
+
+/// Source of the current time for the supervisor's timers, and the means
+/// of letting it pass.
+pub(crate) trait Clock {
+  fn now(&self) -> Instant;
+
+  /// Let `d` pass.
+  fn sleep(&self, d: Duration);
+
+  /// Wait for the next event on `rx` until `deadline` has passed.
+  fn recv_until(
+    &self,
+    rx: &Receiver<Event>,
+    deadline: Instant
+  ) -> Result<Event, RecvTimeoutError>;
+}
+
+/// The real, monotonic, clock.
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+  fn now(&self) -> Instant {
+    Instant::now()
+  }
+
+  fn sleep(&self, d: Duration) {
+    thread::sleep(d);
+  }
+
+  fn recv_until(
+    &self,
+    rx: &Receiver<Event>,
+    deadline: Instant
+  ) -> Result<Event, RecvTimeoutError> {
+    rx.recv_timeout(deadline.saturating_duration_since(self.now()))
+  }
+}
+
+
+pub(crate) enum Event {
+  /// The service has been asked to stop.
+  Stop,
+
+  /// The process with the given process id has exited.  It has not been
+  /// reaped yet.
+  ChildExited(u32),
+
+  /// Result of a health check of the debug server with the given process
+  /// id.
+  Health(u32, Result<(), String>),
+
+  /// The service's configuration has been reloaded.
+  Reload(Box<Config>)
+}
+
+/// Result of waiting for an event.
+pub(crate) enum Wait {
+  Event(Event),
+
+  /// The deadline passed before any event arrived.
+  Deadline,
+
+  /// All senders are gone, so no more events will arrive.
+  Disconnected
+}
+
+
+#[derive(Clone)]
+pub(crate) struct EventSender {
+  tx: Sender<Event>
+}
+
+impl EventSender {
+  /// Ask the supervisor to stop.
+  pub(crate) fn stop(&self) {
+    self.send(Event::Stop);
+  }
+
+  /// Hand a reloaded configuration to the supervisor.
+  pub(crate) fn reload(&self, cfg: Config) {
+    self.send(Event::Reload(Box::new(cfg)));
+  }
+
+  /// Send an event.  Events sent after the supervisor has terminated are
+  /// silently dropped.
+  pub(crate) fn send(&self, ev: Event) {
+    let _ = self.tx.send(ev);
+  }
+}
+
+
+pub(crate) struct EventReceiver {
+  rx: Receiver<Event>
+}
+
+impl EventReceiver {
+  /// Wait for the next event, or until `deadline` (as measured by `clock`)
+  /// has passed.  Without a deadline wait indefinitely.
+  pub(crate) fn wait(
+    &self,
+    clock: &dyn Clock,
+    deadline: Option<Instant>
+  ) -> Wait {
+    match deadline {
+      Some(deadline) => {
+        if clock.now() >= deadline {
+          return Wait::Deadline;
+        }
+        match clock.recv_until(&self.rx, deadline) {
+          Ok(ev) => Wait::Event(ev),
+          Err(RecvTimeoutError::Timeout) => Wait::Deadline,
+          Err(RecvTimeoutError::Disconnected) => Wait::Disconnected
+        }
+      }
+      None => match self.rx.recv() {
+        Ok(ev) => Wait::Event(ev),
+        Err(_) => Wait::Disconnected
+      }
+    }
+  }
+}
+
+
+pub(crate) fn event_channel() -> (EventSender, EventReceiver) {
+  let (tx, rx) = channel();
+
+  let sender = EventSender { tx };
+  let receiver = EventReceiver { rx };
+
+  (sender, receiver)
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
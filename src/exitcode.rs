@@ -0,0 +1,182 @@
+//! Reasons for the service to stop, and the exit codes they map to.
+//!
+//! On Windows the exit code is reported to the service control manager as a
+//! service-specific exit code; on Linux it becomes the exit status of the
+//! service process.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::process::ExitStatus;
+
+use crate::err::Error;
+
+/// Exit codes above this are the debug server's own exit code offset by this
+/// value.
+const CHILD_BASE: u32 = 100;
+
+/// Exit code for a debug server exit code which doesn't fit between
+/// `CHILD_BASE` and this, so all exit codes fit in eight bits.
+const CHILD_OUT_OF_RANGE: u32 = 255;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ExitReason {
+  /// The service was stopped, the session ended or the debug server exited
+  /// successfully.
+  Clean,
+
+  /// An error which doesn't fall into any of the other categories.
+  Failure,
+
+  /// The service parameters are invalid.
+  BadConfig,
+
+  /// The debug server could not be started, or did not become ready.
+  StartFailed,
+
+  /// The debug server was restarted too many times.
+  CrashLoop,
+
+  /// The debug server stopped responding to health checks.
+  Unresponsive,
+
+  /// The debug server exited with the given non-zero exit code.  On unix-like
+  /// platforms a debug server killed by a signal gets 128 plus the signal
+  /// number, like in the shell.
+  ServerFailed(i32)
+}
+
+impl ExitReason {
+  /// Exit reason for a debug server which exited on its own with `status`.
+  pub(crate) fn from_status(status: &ExitStatus) -> Self {
+    if status.success() {
+      return ExitReason::Clean;
+    }
+    if let Some(code) = status.code() {
+      return ExitReason::ServerFailed(code);
+    }
+    #[cfg(unix)]
+    {
+      use std::os::unix::process::ExitStatusExt;
+      if let Some(sig) = status.signal() {
+        return ExitReason::ServerFailed(128 + sig);
+      }
+    }
+    ExitReason::Failure
+  }
+
+  /// Exit reason for an error which made the supervisor give up.
+  pub(crate) fn from_error(err: &Error) -> Self {
+    match err {
+      Error::BadFormat(_) | Error::BadInput(_) => ExitReason::BadConfig,
+      Error::Startup(_) | Error::IO(_) => ExitReason::StartFailed,
+      Error::CrashLoop(_) => ExitReason::CrashLoop,
+      _ => ExitReason::Failure
+    }
+  }
+
+  /// The exit code.
+  ///
+  /// | Code    | Reason                                               |
+  /// |---------|------------------------------------------------------|
+  /// | 0       | Clean                                                |
+  /// | 1       | Other failure                                        |
+  /// | 2       | Bad configuration                                    |
+  /// | 3       | Failed to start                                      |
+  /// | 4       | Crash loop                                           |
+  /// | 5       | Unresponsive                                         |
+  /// | 100+N   | The debug server exited with exit code N (0 to 154)  |
+  /// | 255     | The debug server exited with any other exit code     |
+  pub(crate) fn code(&self) -> u32 {
+    match self {
+      ExitReason::Clean => 0,
+      ExitReason::Failure => 1,
+      ExitReason::BadConfig => 2,
+      ExitReason::StartFailed => 3,
+      ExitReason::CrashLoop => 4,
+      ExitReason::Unresponsive => 5,
+      ExitReason::ServerFailed(code) => match u32::try_from(*code) {
+        Ok(n) if n < CHILD_OUT_OF_RANGE - CHILD_BASE => CHILD_BASE + n,
+        _ => CHILD_OUT_OF_RANGE
+      }
+    }
+  }
+
+  /// The exit code as a process exit status.
+  pub(crate) fn exit_status(&self) -> i32 {
+    self.code() as i32
+  }
+}
+
+impl fmt::Display for ExitReason {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ExitReason::Clean => write!(f, "clean"),
+      ExitReason::Failure => write!(f, "failure"),
+      ExitReason::BadConfig => write!(f, "bad configuration"),
+      ExitReason::StartFailed => write!(f, "failed to start"),
+      ExitReason::CrashLoop => write!(f, "crash loop"),
+      ExitReason::Unresponsive => write!(f, "debug server unresponsive"),
+      ExitReason::ServerFailed(code) => {
+        write!(f, "debug server exited with code {}", code)
+      }
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn fixed_codes() {
+    assert_eq!(ExitReason::Clean.code(), 0);
+    assert_eq!(ExitReason::Failure.code(), 1);
+    assert_eq!(ExitReason::BadConfig.code(), 2);
+    assert_eq!(ExitReason::StartFailed.code(), 3);
+    assert_eq!(ExitReason::CrashLoop.code(), 4);
+    assert_eq!(ExitReason::Unresponsive.code(), 5);
+  }
+
+  #[test]
+  fn server_codes() {
+    assert_eq!(ExitReason::ServerFailed(1).code(), 101);
+    assert_eq!(ExitReason::ServerFailed(154).code(), 254);
+    // SIGKILL, as reported for a killed debug server.
+    assert_eq!(ExitReason::ServerFailed(128 + 9).code(), 237);
+  }
+
+  #[test]
+  fn out_of_range_server_codes() {
+    for code in &[155, 255, 128 + 64, 1000, -1, -1073741819] {
+      assert_eq!(ExitReason::ServerFailed(*code).code(), 255, "{}", code);
+    }
+  }
+
+  #[test]
+  fn exit_status_matches_code() {
+    for code in 0..=255 {
+      let reason = ExitReason::ServerFailed(code);
+      assert_eq!(reason.exit_status(), reason.code() as i32);
+      assert!(reason.exit_status() <= 255);
+    }
+  }
+
+  #[test]
+  #[cfg(unix)]
+  fn from_status() {
+    use std::os::unix::process::ExitStatusExt;
+    let exited = ExitStatus::from_raw(3 << 8);
+    assert_eq!(ExitReason::from_status(&exited), ExitReason::ServerFailed(3));
+    let killed = ExitStatus::from_raw(9);
+    assert_eq!(
+      ExitReason::from_status(&killed),
+      ExitReason::ServerFailed(137)
+    );
+    let clean = ExitStatus::from_raw(0);
+    assert_eq!(ExitReason::from_status(&clean), ExitReason::Clean);
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,102 @@
+//! Liveness health checks of a running debug server.
+
+use std::time::Duration;
+
+use crate::config::Config;
+
+
+#[derive(Debug, Clone)]
+pub(crate) struct HealthConfig {
+  /// Time between health checks.  `None` disables health checks.
+  pub(crate) interval: Option<Duration>,
+
+  /// How long a single health check may take before it is considered to
+  /// have failed.
+  pub(crate) timeout: Duration,
+
+  /// Number of consecutive failed health checks after which the debug server
+  /// is considered to be unresponsive.
+  pub(crate) max_failures: u32
+}
+
+impl HealthConfig {
+  pub(crate) fn from_config(cfg: &Config) -> Self {
+    HealthConfig {
+      interval: cfg.health_interval,
+      timeout: cfg.health_timeout,
+      max_failures: cfg.health_failures
+    }
+  }
+}
+
+
+/// Counts consecutive health check failures.
+pub(crate) struct HealthTracker {
+  max_failures: u32,
+  failures: u32
+}
+
+impl HealthTracker {
+  pub(crate) fn new(cfg: &HealthConfig) -> Self {
+    HealthTracker {
+      max_failures: cfg.max_failures,
+      failures: 0
+    }
+  }
+
+  /// Record the result of a health check.
+  ///
+  /// Returns `true` if the debug server should be considered unresponsive.
+  pub(crate) fn record(&mut self, healthy: bool) -> bool {
+    if healthy {
+      self.failures = 0;
+    } else {
+      self.failures += 1;
+    }
+    self.failures >= self.max_failures
+  }
+
+  /// Number of consecutive failed health checks.
+  pub(crate) fn failures(&self) -> u32 {
+    self.failures
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn new_tracker(max_failures: u32) -> HealthTracker {
+    HealthTracker::new(&HealthConfig {
+      interval: Some(Duration::from_secs(10)),
+      timeout: Duration::from_secs(5),
+      max_failures
+    })
+  }
+
+  #[test]
+  fn failure_threshold() {
+    let mut tracker = new_tracker(3);
+    assert!(!tracker.record(false));
+    assert!(!tracker.record(false));
+    assert!(tracker.record(false));
+    assert_eq!(tracker.failures(), 3);
+
+    let mut tracker = new_tracker(1);
+    assert!(!tracker.record(true));
+    assert!(tracker.record(false));
+  }
+
+  #[test]
+  fn success_resets_failures() {
+    let mut tracker = new_tracker(2);
+    assert!(!tracker.record(false));
+    assert!(!tracker.record(true));
+    assert_eq!(tracker.failures(), 0);
+    assert!(!tracker.record(false));
+    assert!(tracker.record(false));
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
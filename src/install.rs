@@ -0,0 +1,565 @@
+//! Installation of a service as a sequence of reversible steps.
+//!
+//! The steps themselves are carried out by a platform specific [`Backend`].
+//! If a step fails, the steps which have already been carried out are undone
+//! in reverse order, so a failed installation doesn't leave a half-installed
+//! service behind.
+//!
+//! A dry run goes through the same steps using a backend of its own, which
+//! only prints what each step would do.
+
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::args;
+use crate::config::Source;
+use crate::err::Error;
+use crate::params::{FileFormat, FileStore, MemoryStore, ParamStore};
+use crate::profile;
+use crate::service;
+use crate::svcdef::{self, ServiceDef};
+use crate::uninstall;
+
+
+/// Everything needed to install a service, validated up front.
+pub(crate) struct Plan {
+  pub(crate) service_name: String,
+  pub(crate) profile_name: String,
+
+  /// The debug server executable.
+  pub(crate) exec: PathBuf,
+
+  /// Arguments of the profile's computer preparation command, which is run
+  /// using the debug server executable.
+  pub(crate) prep_args: Option<Vec<String>>,
+
+  /// The service executable (this program) and its arguments.
+  pub(crate) binary: PathBuf,
+  pub(crate) launch_args: Vec<String>,
+
+  pub(crate) def: ServiceDef,
+
+  /// How long the debug server is given to terminate when the service is
+  /// stopped, which the systemd unit's stop timeout is based on.
+  #[cfg(unix)]
+  pub(crate) stop_grace: std::time::Duration,
+
+  /// The service parameters to store; only what has been given on the
+  /// command line and what was kept when the service was last uninstalled,
+  /// everything else is left at its default.
+  pub(crate) params: Vec<(String, String)>,
+
+  /// File holding the kept service parameters, if there is one.
+  pub(crate) kept: Option<PathBuf>
+}
+
+impl Plan {
+  /// Validate the install options in `ctx` and plan the installation of the
+  /// service `service_name`.
+  ///
+  /// Fails if the service already exists, as rolling back a failed
+  /// installation would otherwise remove parts of the existing service.
+  pub(crate) fn new(
+    service_name: &str,
+    ctx: &args::Context
+  ) -> Result<Self, Error> {
+    args::check_service_name(service_name)?;
+    if service::exists(service_name)? {
+      return Err(Error::Service(format!(
+        "Service '{}' already exists; uninstall it first or use reconfigure",
+        service_name
+      )));
+    }
+
+    let kept = Some(uninstall::kept_path(service_name)).filter(|f| f.exists());
+    Plan::with_kept(service_name, kept, ctx)
+  }
+
+  /// Like `new()`, but restoring the service parameters kept in `kept`.
+  fn with_kept(
+    service_name: &str,
+    kept: Option<PathBuf>,
+    ctx: &args::Context
+  ) -> Result<Self, Error> {
+    let exec = match &ctx.exec {
+      Some(exec) => exec.clone(),
+      None => {
+        // The command line parser should have made sure that this is set, so
+        // this should never happen.
+        panic!("Missing exec");
+      }
+    };
+    if exec.to_str().is_none() {
+      return Err(Error::BadInput(format!(
+        "Exec path {:?} is not valid UTF-8",
+        exec
+      )));
+    }
+
+    let mut params = match kept {
+      Some(ref fname) => {
+        FileStore::new(fname.clone(), FileFormat::Ini).list()?
+      }
+      None => Vec::new()
+    };
+    let mut store = MemoryStore::new();
+    for (k, v) in &params {
+      store.set(k, v)?;
+    }
+
+    let (cfg, def) = svcdef::check_install(service_name, &store, ctx)?;
+    let profile = profile::lookup(&cfg.profile)?;
+
+    // What has been given on the command line takes precedence over what
+    // was kept.
+    let given = cfg
+      .explicit()
+      .into_iter()
+      .filter(|(key, _)| cfg.source(key) == Source::Cli);
+    for (key, value) in given {
+      match params.iter_mut().find(|(k, _)| k == key) {
+        Some(param) => param.1 = value,
+        None => params.push((key.to_string(), value))
+      }
+    }
+
+    Ok(Plan {
+      service_name: service_name.to_string(),
+      profile_name: cfg.profile.clone(),
+      exec,
+      prep_args: profile.prep_args(),
+      binary: std::env::current_exe()?,
+      launch_args: vec![service_name.to_string()],
+      def,
+      #[cfg(unix)]
+      stop_grace: cfg.stop_grace,
+      params,
+      kept
+    })
+  }
+}
+
+
+/// The steps of installing a service, in the order they are carried out.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Step {
+  /// Run the profile's computer preparation command.  This can not be
+  /// undone, and failures are not fatal.
+  Prepare,
+
+  /// Register the service as a log source.
+  RegisterSource,
+
+  /// Register the service with the service manager.
+  CreateService,
+
+  /// Store the service parameters.
+  WriteParams
+}
+
+const STEPS: &[Step] = &[
+  Step::Prepare,
+  Step::RegisterSource,
+  Step::CreateService,
+  Step::WriteParams
+];
+
+impl Step {
+  /// Carry out the step.  Returns whether there is anything to undo.
+  fn run(
+    self,
+    backend: &mut dyn Backend,
+    plan: &Plan
+  ) -> Result<bool, Error> {
+    match self {
+      Step::Prepare => {
+        backend.prepare(plan);
+        Ok(false)
+      }
+      Step::RegisterSource => backend.register_source(plan),
+      Step::CreateService => backend.create_service(plan).map(|_| true),
+      Step::WriteParams => backend.write_params(plan).map(|_| true)
+    }
+  }
+
+  fn undo(self, backend: &mut dyn Backend, plan: &Plan) -> Result<(), Error> {
+    match self {
+      Step::Prepare => Ok(()),
+      Step::RegisterSource => backend.deregister_source(plan),
+      Step::CreateService => backend.delete_service(plan),
+      Step::WriteParams => backend.remove_params(plan)
+    }
+  }
+}
+
+impl fmt::Display for Step {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let s = match self {
+      Step::Prepare => "computer preparation",
+      Step::RegisterSource => "log source registration",
+      Step::CreateService => "service registration",
+      Step::WriteParams => "service parameters"
+    };
+    write!(f, "{}", s)
+  }
+}
+
+
+/// The platform specific parts of installing a service.  Each step which can
+/// fail has a corresponding method undoing it.
+pub(crate) trait Backend {
+  /// Run the computer preparation command, if there is one.  Failures are
+  /// reported but are not considered fatal.
+  fn prepare(&mut self, plan: &Plan);
+
+  /// Register the service as a log source.  Returns `false` if the
+  /// platform has no log sources to register.
+  fn register_source(&mut self, plan: &Plan) -> Result<bool, Error>;
+  fn deregister_source(&mut self, plan: &Plan) -> Result<(), Error>;
+
+  /// Register the service.  If this fails part way through, whatever has
+  /// been done must be undone before returning.
+  fn create_service(&mut self, plan: &Plan) -> Result<(), Error>;
+  fn delete_service(&mut self, plan: &Plan) -> Result<(), Error>;
+
+  fn write_params(&mut self, plan: &Plan) -> Result<(), Error>;
+  fn remove_params(&mut self, plan: &Plan) -> Result<(), Error>;
+}
+
+
+/// Install a service according to `plan` using `backend`, rolling back the
+/// steps already carried out if one of them fails.  A dry run only prints
+/// what would be done, leaving `backend` alone.
+pub(crate) fn install(
+  backend: &mut dyn Backend,
+  plan: &Plan,
+  dry_run: bool
+) -> Result<(), Error> {
+  if dry_run {
+    print_steps(plan);
+    return Ok(());
+  }
+
+  println!(
+    "==> Installing as service {} using {} profile with {:?} ..",
+    plan.service_name, plan.profile_name, plan.exec
+  );
+  println!("==> Service exec path: {:?}", plan.binary);
+
+  let mut done = Vec::new();
+  for step in STEPS {
+    match step.run(backend, plan) {
+      Ok(true) => done.push(*step),
+      Ok(false) => {}
+      Err(e) => {
+        eprintln!("==> Installation failed at {}", step);
+        rollback(backend, plan, &done);
+        return Err(e);
+      }
+    }
+  }
+
+  if let Some(ref fname) = plan.kept {
+    println!("==> Removing kept service parameters {:?} ..", fname);
+    if let Err(e) = std::fs::remove_file(fname) {
+      eprintln!("==> Unable to remove {:?}; {}", fname, e);
+    }
+  }
+
+  println!("==> Service installation successful");
+
+  Ok(())
+}
+
+
+/// Undo the steps `done` in reverse order.  Failures are reported, but
+/// don't stop the remaining steps from being undone.
+fn rollback(backend: &mut dyn Backend, plan: &Plan, done: &[Step]) {
+  for step in done.iter().rev() {
+    println!("==> Rolling back {} ..", step);
+    if let Err(e) = step.undo(backend, plan) {
+      eprintln!("==> Unable to roll back {}; {}", step, e);
+    }
+  }
+}
+
+
+
+/// Print everything installing a service according to `plan` would do,
+/// without doing any of it.
+fn print_steps(plan: &Plan) {
+  println!(
+    "==> Dry run; not installing service {} using {} profile with {:?}",
+    plan.service_name, plan.profile_name, plan.exec
+  );
+  for step in STEPS {
+    // Nothing the dry run backend does can fail.
+    let _ = step.run(&mut DryRun, plan);
+  }
+}
+
+
+/// Backend printing what each step would do.
+struct DryRun;
+
+impl Backend for DryRun {
+  fn prepare(&mut self, plan: &Plan) {
+    if let Some(ref eargs) = plan.prep_args {
+      println!("==> Would prepare computer by running:");
+      println!("  {:?} {}", plan.exec, eargs.join(" "));
+    }
+  }
+
+  fn register_source(&mut self, plan: &Plan) -> Result<bool, Error> {
+    if cfg!(windows) {
+      println!("==> Would register event log source '{}'", plan.service_name);
+    }
+    Ok(cfg!(windows))
+  }
+
+  fn deregister_source(&mut self, _plan: &Plan) -> Result<(), Error> {
+    Ok(())
+  }
+
+  fn create_service(&mut self, plan: &Plan) -> Result<(), Error> {
+    let def = &plan.def;
+    println!("==> Would register service '{}':", plan.service_name);
+    println!("  display name: {}", def.display_name);
+    println!("  description: {}", def.description);
+    println!("  start type: {}", def.start_type);
+    println!("  binary path: {:?}", plan.binary);
+    println!("  launch arguments: {}", plan.launch_args.join(" "));
+    println!("  account: {}", service::ACCOUNT);
+    println!(
+      "  dependencies: {}",
+      service::dependencies(&def.dependencies).join(", ")
+    );
+    Ok(())
+  }
+
+  fn delete_service(&mut self, _plan: &Plan) -> Result<(), Error> {
+    Ok(())
+  }
+
+  fn write_params(&mut self, plan: &Plan) -> Result<(), Error> {
+    if plan.params.is_empty() {
+      println!("==> Would not write any service parameters");
+      return Ok(());
+    }
+    if let Some(ref fname) = plan.kept {
+      println!("==> Would restore service parameters kept in {:?}", fname);
+    }
+    println!("==> Would write service parameters:");
+    for (k, v) in &plan.params {
+      println!("  {}={}", k, v);
+    }
+    Ok(())
+  }
+
+  fn remove_params(&mut self, _plan: &Plan) -> Result<(), Error> {
+    Ok(())
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A service which has no configuration file.
+  const SVC: &str = "verboten-unit-test";
+
+  /// Parameters kept in a file of their own, removed when dropped.
+  struct Kept(PathBuf);
+
+  impl Kept {
+    fn new(name: &str, params: &[(&str, &str)]) -> Self {
+      let path = std::env::temp_dir().join(format!(
+        "verboten-install-{}-{}.kept.conf",
+        std::process::id(),
+        name
+      ));
+      let mut store = FileStore::new(path.clone(), FileFormat::Ini);
+      for (k, v) in params {
+        store.set(k, v).unwrap();
+      }
+      Kept(path)
+    }
+  }
+
+  impl Drop for Kept {
+    fn drop(&mut self) {
+      let _ = std::fs::remove_file(&self.0);
+    }
+  }
+
+  fn install_ctx() -> args::Context {
+    args::Context {
+      exec: Some(std::env::current_exe().unwrap()),
+      ..Default::default()
+    }
+  }
+
+  fn param<'a>(plan: &'a Plan, key: &str) -> Option<&'a str> {
+    let param = plan.params.iter().find(|(k, _)| k == key);
+    param.map(|(_, v)| v.as_str())
+  }
+
+  #[test]
+  fn kept_profile() {
+    let kept = Kept::new("profile", &[("Profile", "gdbserver")]);
+    let plan =
+      Plan::with_kept(SVC, Some(kept.0.clone()), &install_ctx()).unwrap();
+    let name = svcdef::display_name(SVC, "gdbserver");
+    assert_eq!(plan.profile_name, "gdbserver");
+    assert!(plan.prep_args.is_none());
+    assert_eq!(plan.def.display_name, name);
+    assert_eq!(param(&plan, "Profile"), Some("gdbserver"));
+  }
+
+  #[test]
+  fn cli_overrides_kept() {
+    let kept = Kept::new("cli", &[("Profile", "gdbserver"), ("Port", "1000")]);
+    let ctx = args::Context {
+      profile: Some(String::from("msvsmon")),
+      ..install_ctx()
+    };
+    let plan = Plan::with_kept(SVC, Some(kept.0.clone()), &ctx).unwrap();
+    assert_eq!(plan.profile_name, "msvsmon");
+    assert!(plan.prep_args.is_some());
+    assert_eq!(param(&plan, "Profile"), Some("msvsmon"));
+    assert_eq!(param(&plan, "Port"), Some("1000"));
+  }
+
+  /// Backend recording what is done, and failing at the step `fail`.
+  struct Mock {
+    fail: &'static str,
+    calls: Vec<&'static str>
+  }
+
+  impl Mock {
+    fn call(&mut self, name: &'static str) -> Result<(), Error> {
+      self.calls.push(name);
+      if name == self.fail {
+        return Err(Error::Service(format!("{} failed", name)));
+      }
+      Ok(())
+    }
+  }
+
+  impl Backend for Mock {
+    fn prepare(&mut self, _plan: &Plan) {
+      self.calls.push("prepare");
+    }
+
+    fn register_source(&mut self, _plan: &Plan) -> Result<bool, Error> {
+      self.call("register_source").map(|_| true)
+    }
+
+    fn deregister_source(&mut self, _plan: &Plan) -> Result<(), Error> {
+      self.call("deregister_source")
+    }
+
+    fn create_service(&mut self, _plan: &Plan) -> Result<(), Error> {
+      self.call("create_service")
+    }
+
+    fn delete_service(&mut self, _plan: &Plan) -> Result<(), Error> {
+      self.call("delete_service")
+    }
+
+    fn write_params(&mut self, _plan: &Plan) -> Result<(), Error> {
+      self.call("write_params")
+    }
+
+    fn remove_params(&mut self, _plan: &Plan) -> Result<(), Error> {
+      self.call("remove_params")
+    }
+  }
+
+  fn run(fail: &'static str) -> (bool, Vec<&'static str>) {
+    let plan = Plan::with_kept(SVC, None, &install_ctx()).unwrap();
+    let mut mock = Mock {
+      fail,
+      calls: Vec::new()
+    };
+    let ok = install(&mut mock, &plan, false).is_ok();
+    (ok, mock.calls)
+  }
+
+  #[test]
+  fn all_steps() {
+    let (ok, calls) = run("");
+    assert!(ok);
+    assert_eq!(
+      calls,
+      [
+        "prepare",
+        "register_source",
+        "create_service",
+        "write_params"
+      ]
+    );
+  }
+
+  #[test]
+  fn rollback_in_reverse_order() {
+    let (ok, calls) = run("register_source");
+    assert!(!ok);
+    assert_eq!(calls, ["prepare", "register_source"]);
+
+    let (ok, calls) = run("create_service");
+    assert!(!ok);
+    assert_eq!(
+      calls,
+      [
+        "prepare",
+        "register_source",
+        "create_service",
+        "deregister_source"
+      ]
+    );
+
+    let (ok, calls) = run("write_params");
+    assert!(!ok);
+    assert_eq!(
+      calls,
+      [
+        "prepare",
+        "register_source",
+        "create_service",
+        "write_params",
+        "delete_service",
+        "deregister_source"
+      ]
+    );
+  }
+
+  #[test]
+  fn dry_run_leaves_backend_alone() {
+    let plan = Plan::with_kept(SVC, None, &install_ctx()).unwrap();
+    let mut mock = Mock {
+      fail: "",
+      calls: Vec::new()
+    };
+    install(&mut mock, &plan, true).unwrap();
+    assert!(mock.calls.is_empty());
+  }
+
+  #[test]
+  fn rollback_continues_after_failure() {
+    let plan = Plan::with_kept(SVC, None, &install_ctx()).unwrap();
+    let mut mock = Mock {
+      fail: "delete_service",
+      calls: Vec::new()
+    };
+    rollback(
+      &mut mock,
+      &plan,
+      &[Step::Prepare, Step::RegisterSource, Step::CreateService]
+    );
+    assert_eq!(mock.calls, ["delete_service", "deregister_source"]);
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,153 @@
+//! A service wrapper for debug servers.
+//!
+//! The supervisor core (configuration, service parameters, profiles, the
+//! supervision loop, restart policy and exit codes) is platform neutral and
+//! builds and is tested on any platform.  The service manager frontends,
+//! `winsvc` for the Windows service control manager and `linuxsvc` for
+//! systemd, are only built for their own targets, and the platform neutral
+//! parts use whichever one is built through the `service` alias.
+
+mod appstate;
+mod args;
+mod child;
+mod config;
+mod configcmd;
+mod err;
+mod event;
+mod exitcode;
+mod health;
+mod install;
+#[cfg(target_os = "linux")]
+mod linuxsvc;
+mod loglevel;
+mod outlog;
+mod params;
+mod paths;
+mod portable;
+mod probe;
+mod profile;
+mod profiledef;
+mod reconfigure;
+#[cfg(windows)]
+mod regstore;
+mod restart;
+#[cfg(windows)]
+mod scm;
+#[cfg(target_os = "linux")]
+mod sdnotify;
+#[cfg(target_os = "linux")]
+mod sigwatch;
+mod status;
+#[cfg(target_os = "linux")]
+mod stderrlog;
+mod supervisor;
+mod svcdef;
+mod transcript;
+mod uninstall;
+#[cfg(windows)]
+mod winsvc;
+
+#[cfg(target_os = "linux")]
+use linuxsvc as service;
+#[cfg(windows)]
+use winsvc as service;
+
+#[cfg(not(any(windows, target_os = "linux")))]
+compile_error!("verboten is only supported on Windows and Linux");
+
+/// Run verboten with the process' command line: either one of the
+/// commands, installing or uninstalling a service, or running as the
+/// service.
+pub fn run() -> Result<(), Box<dyn std::error::Error>> {
+  if let Some(cmd) = args::parse_cmd()? {
+    match cmd {
+      args::Cmd::Transcripts {
+        service_name,
+        which
+      } => {
+        let store = service::param_store(&service_name)?;
+        let cli = args::Context::default();
+        let cfg = config::Config::load(&service_name, &store, &cli)?;
+        let tcfg =
+          transcript::TranscriptConfig::from_config(&service_name, &cfg);
+        transcript::show(&tcfg.dir, which.as_deref())?;
+      }
+      args::Cmd::CheckConfig { service_name } => {
+        let store = service::param_store(&service_name)?;
+        let cli = args::Context::default();
+        let (_cfg, problems) =
+          config::Config::check(&service_name, &store, &cli);
+        if problems.is_empty() {
+          println!("Configuration of service '{}' is valid", service_name);
+        } else {
+          for p in &problems {
+            println!("{}", p);
+          }
+          std::process::exit(exitcode::ExitReason::BadConfig.exit_status());
+        }
+      }
+      args::Cmd::Config {
+        service_name,
+        action
+      } => {
+        let mut store = service::param_store(&service_name)?;
+        configcmd::run(&service_name, &mut store, action)?;
+      }
+      args::Cmd::Reconfigure { service_name, ctx } => {
+        reconfigure::reconfigure(&service_name, &ctx)?;
+      }
+      args::Cmd::Reload { service_name } => {
+        service::reload(&service_name)?;
+      }
+      args::Cmd::Status { service_name, json } => {
+        status::show(&service_name, json)?;
+      }
+      args::Cmd::Export { service_name } => {
+        portable::export(&service_name)?;
+      }
+      args::Cmd::Import {
+        fname,
+        service_name,
+        rewrite
+      } => {
+        portable::import(&fname, service_name.as_deref(), rewrite.as_ref())?;
+      }
+    }
+    return Ok(());
+  }
+
+  let ctx = args::parse()?;
+  if ctx.do_help || ctx.do_version {
+    return Ok(());
+  }
+
+  let service_name = match &ctx.service_name {
+    Some(nm) => nm,
+    None => {
+      // The command line parser should have made certain that we have the
+      // service name at this point.
+      panic!("Missing service name");
+    }
+  };
+
+  match ctx.svcaction {
+    Some(args::SvcAction::Install) => {
+      service::install(service_name, &ctx)?;
+      return Ok(());
+    }
+    Some(args::SvcAction::Uninstall) => {
+      uninstall::uninstall(service_name, &ctx)?;
+      return Ok(());
+    }
+    _ => {}
+  }
+
+  let reason = service::run(service_name, &ctx)?;
+  if reason != exitcode::ExitReason::Clean {
+    std::process::exit(reason.exit_status());
+  }
+
+  Ok(())
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
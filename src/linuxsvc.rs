@@ -0,0 +1,665 @@
+//! Linux (systemd) service frontend.
+//!
+//! Service parameters are stored in `/etc/verboten/<name>.conf` as
+//! `Key=Value` lines, mirroring the `Parameters` registry subkey used on
+//! Windows.  The service itself is a `Type=notify` systemd unit, and state
+//! changes are reported back to systemd using sd_notify.
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use log::{error, info, trace, warn};
+
+use crate::appstate::AppState;
+use crate::args;
+use crate::config::Config;
+use crate::err::Error;
+use crate::event::{self, SystemClock};
+use crate::exitcode::ExitReason;
+use crate::install::{self, Backend, Plan};
+use crate::loglevel::LogLevel;
+use crate::params::{FileFormat, FileStore, ParamStore};
+use crate::profile;
+use crate::reconfigure::Change;
+use crate::sdnotify::Notifier;
+use crate::sigwatch;
+use crate::stderrlog;
+use crate::supervisor::{self, ServerCtx};
+use crate::svcdef::{ServiceDef, StartType};
+
+const PARAMS_DIR: &str = "/etc/verboten";
+const UNIT_DIR: &str = "/etc/systemd/system";
+const NETWORK_TARGET: &str = "network-online.target";
+
+/// Time on top of `StopGrace` systemd gives the service to stop, for killing
+/// the debug server once the grace period is over and shutting down.
+const STOP_MARGIN: Duration = Duration::from_secs(20);
+
+/// Account services run as.
+pub(crate) const ACCOUNT: &str = "root";
+
+
+/// Run the service.  Returns the reason it stopped, which the caller is
+/// expected to turn into the process exit status.
+///
+/// `cli` holds the options the service was started with, which override the
+/// service's configuration.
+pub(crate) fn run(
+  service_name: &str,
+  cli: &args::Context
+) -> Result<ExitReason, Error> {
+  let cfg = param_store(service_name)
+    .and_then(|store| Config::load(service_name, &store, cli));
+
+  // Without a configuration, log at the default level to report the error.
+  let ll = match cfg {
+    Ok(ref cfg) => cfg.log_level.clone(),
+    Err(_) => LogLevel::default()
+  };
+  stderrlog::init(log::LevelFilter::from(&ll))?;
+
+  info!("starting service");
+
+  // Create channel for supervisor events, and have termination signals
+  // request a stop and SIGHUP reload the configuration.
+  let (ev_tx, ev_rx) = event::event_channel();
+
+  let stop_tx = ev_tx.clone();
+  let reload_tx = ev_tx.clone();
+  let name = service_name.to_string();
+  let reload_cli = cli.clone();
+  sigwatch::watch(
+    move || {
+      stop_tx.stop();
+    },
+    move || {
+      info!("reloading configuration");
+      match param_store(&name)
+        .and_then(|store| Config::load(&name, &store, &reload_cli))
+      {
+        Ok(cfg) => {
+          cfg.log_warnings();
+          reload_tx.reload(cfg);
+        }
+        Err(e) => error!("Unable to reload configuration; {}", e)
+      }
+    }
+  )?;
+
+  let notifier = Notifier::from_env()?;
+  if notifier.is_none() {
+    trace!("NOTIFY_SOCKET not set -- not reporting state to systemd");
+  }
+
+  let ctx = match cfg.and_then(|cfg| {
+    cfg.log_warnings();
+    cfg.log_sources();
+    ServerCtx::new(service_name, &cfg)
+  }) {
+    Ok(ctx) => ctx,
+    Err(e) => {
+      error!("{}", e);
+      if let Some(ref n) = notifier {
+        let _ = n.report(&AppState::Stopped(ExitReason::BadConfig));
+      }
+      return Ok(ExitReason::BadConfig);
+    }
+  };
+
+  let clock = SystemClock;
+  let reason = supervisor::supervise(ctx, clock, ev_tx, ev_rx, |state| {
+    match state {
+      AppState::Starting(checkpoint) => {
+        trace!("service starting checkpoint {}", checkpoint);
+      }
+      AppState::Started => {
+        trace!("service is running");
+      }
+      AppState::Stopping(checkpoint) => {
+        trace!("service stopping checkpoint {}", checkpoint);
+      }
+      AppState::Stopped(reason) => {
+        trace!("service has stopped ({})", reason);
+      }
+    }
+    if let Some(ref n) = notifier {
+      if let Err(e) = n.report(state) {
+        warn!("Unable to notify systemd; {}", e);
+      }
+    }
+  });
+
+  info!("service terminated ({})", reason);
+
+  Ok(reason)
+}
+
+
+pub(crate) fn install(
+  service_name: &str,
+  ctx: &args::Context
+) -> Result<(), Error> {
+  let plan = Plan::new(service_name, ctx)?;
+  install::install(&mut Systemd, &plan, ctx.dry_run)
+}
+
+
+/// Installs services as systemd units.
+struct Systemd;
+
+impl Backend for Systemd {
+  fn prepare(&mut self, plan: &Plan) {
+    if let Some(ref eargs) = plan.prep_args {
+      profile::prepare(&plan.exec, eargs);
+    }
+  }
+
+  /// Log records go to the journal, which needs no registration.
+  fn register_source(&mut self, _plan: &Plan) -> Result<bool, Error> {
+    Ok(false)
+  }
+
+  fn deregister_source(&mut self, _plan: &Plan) -> Result<(), Error> {
+    Ok(())
+  }
+
+  fn create_service(&mut self, plan: &Plan) -> Result<(), Error> {
+    let unit = unit_path(&plan.service_name);
+    println!("==> Writing systemd unit {:?} ..", unit);
+    fs::write(&unit, unit_file(plan)?)?;
+
+    let res = systemctl(&["daemon-reload"]).and_then(|_| {
+      if plan.def.start_type == StartType::Auto {
+        println!("==> Enabling service '{}' ..", plan.service_name);
+        systemctl(&["enable", &unit_name(&plan.service_name)])?;
+      }
+      Ok(())
+    });
+    if res.is_err() {
+      let _ = self.delete_service(plan);
+    }
+    res
+  }
+
+  fn delete_service(&mut self, plan: &Plan) -> Result<(), Error> {
+    let unit = unit_path(&plan.service_name);
+    if plan.def.start_type == StartType::Auto {
+      // The unit may not have gotten as far as being enabled.
+      let _ = systemctl(&["disable", &unit_name(&plan.service_name)]);
+    }
+    fs::remove_file(&unit)?;
+    systemctl(&["daemon-reload"])
+  }
+
+  fn write_params(&mut self, plan: &Plan) -> Result<(), Error> {
+    let mut store = create_param_store(&plan.service_name)?;
+    println!("==> Writing service parameters to {} ..", store.location());
+    for (k, v) in &plan.params {
+      store.set(k, v)?;
+    }
+    Ok(())
+  }
+
+  fn remove_params(&mut self, plan: &Plan) -> Result<(), Error> {
+    fs::remove_file(params_path(&plan.service_name))?;
+    Ok(())
+  }
+}
+
+
+/// Whether the service `service_name` is installed.  Service parameters
+/// without a unit are counted as well, as installing over them would
+/// replace them.
+pub(crate) fn exists(service_name: &str) -> Result<bool, Error> {
+  Ok(unit_path(service_name).exists() || params_path(service_name).exists())
+}
+
+
+/// Whether the service `service_name` is running, or is in the process of
+/// starting or stopping.
+pub(crate) fn is_running(service_name: &str) -> Result<bool, Error> {
+  if !unit_path(service_name).exists() {
+    return Ok(false);
+  }
+  let props = show(service_name, &["ActiveState"])?;
+  Ok(!matches!(props[0].as_str(), "inactive" | "failed"))
+}
+
+
+/// The state of the service `service_name` according to systemd, and the
+/// process id of the service process if it is running.
+pub(crate) fn query_state(
+  service_name: &str
+) -> Result<(String, Option<u32>), Error> {
+  if !unit_path(service_name).exists() {
+    return Ok((String::from("not installed"), None));
+  }
+  let props = show(service_name, &["ActiveState", "SubState", "MainPID"])?;
+  let state = format!("{} ({})", props[0], props[1]);
+  let pid = props[2].parse::<u32>().ok().filter(|pid| *pid != 0);
+  Ok((state, pid))
+}
+
+
+/// The values of the properties `props` of the unit of the service
+/// `service_name`, in the same order.
+fn show(service_name: &str, props: &[&str]) -> Result<Vec<String>, Error> {
+  let unit = unit_name(service_name);
+  let mut args = vec!["show"];
+  let props: Vec<String> =
+    props.iter().map(|p| format!("--property={}", p)).collect();
+  args.extend(props.iter().map(String::as_str));
+  args.extend(&["--value", unit.as_str()]);
+
+  let output = Command::new("systemctl").args(&args).output().map_err(|e| {
+    Error::Service(format!("Unable to run systemctl {:?}; {}", args, e))
+  })?;
+  if !output.status.success() {
+    return Err(Error::Service(format!(
+      "systemctl {:?} returned failure ({})",
+      args, output.status
+    )));
+  }
+  let values: Vec<String> = String::from_utf8_lossy(&output.stdout)
+    .lines()
+    .map(String::from)
+    .collect();
+  if values.len() != props.len() {
+    return Err(Error::Service(format!(
+      "Unexpected output from systemctl {:?}",
+      args
+    )));
+  }
+  Ok(values)
+}
+
+
+/// Ask the service `service_name` to stop, without waiting for it to.
+pub(crate) fn request_stop(service_name: &str) -> Result<(), Error> {
+  systemctl(&["stop", "--no-block", &unit_name(service_name)])
+}
+
+
+/// Kill all processes of the service `service_name`.
+pub(crate) fn kill(service_name: &str) -> Result<(), Error> {
+  systemctl(&["kill", "--signal=SIGKILL", &unit_name(service_name)])
+}
+
+
+/// Remove the stopped service `service_name` and its service parameters.
+pub(crate) fn remove(service_name: &str, dry_run: bool) -> Result<(), Error> {
+  let unit = unit_path(service_name);
+  let fname = params_path(service_name);
+  if dry_run {
+    if unit.exists() {
+      println!("==> Would disable service '{}'", service_name);
+      println!("==> Would remove systemd unit {:?}", unit);
+    }
+    println!("==> Would remove service parameters {:?}", fname);
+    return Ok(());
+  }
+
+  if unit.exists() {
+    println!("==> Disabling service '{}' ..", service_name);
+    systemctl(&["disable", &unit_name(service_name)])?;
+
+    println!("==> Removing systemd unit {:?} ..", unit);
+    fs::remove_file(&unit)?;
+    systemctl(&["daemon-reload"])?;
+  }
+
+  println!("==> Removing service parameters {:?} ..", fname);
+  match fs::remove_file(&fname) {
+    Ok(_) => Ok(()),
+    Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+    Err(e) => Err(e.into())
+  }
+}
+
+
+/// No profile prepares the computer on Linux, so there are no firewall rules
+/// to remove.
+pub(crate) fn remove_firewall_rules(
+  _exec: &Path,
+  _dry_run: bool
+) -> Result<(), Error> {
+  Ok(())
+}
+
+
+/// Update the definition of the installed service `service_name` with what
+/// has been given on the command line, and return what changed.
+///
+/// Only the lines of the systemd unit describing the service definition and
+/// the stop timeout are rewritten; anything else is left as it is.
+pub(crate) fn reconfigure(
+  service_name: &str,
+  old_profile: &str,
+  ctx: &args::Context
+) -> Result<Vec<Change>, Error> {
+  let buf = read_unit(service_name)?;
+
+  let old = unit_def(service_name, &buf)?;
+  let new = old.reconfigured(service_name, old_profile, ctx)?;
+  if ctx.dry_run {
+    return Ok(old.diff(&new));
+  }
+
+  if new.start_type != old.start_type {
+    if new.start_type == StartType::Auto {
+      systemctl(&["enable", &unit_name(service_name)])?;
+    } else {
+      systemctl(&["disable", &unit_name(service_name)])?;
+    }
+  }
+
+  update_unit(service_name, &buf, &new)?;
+
+  Ok(old.diff(&new))
+}
+
+
+/// Ask the running service `service_name` to reload its configuration.
+///
+/// The unit's stop timeout is brought up to date with `StopGrace` first.
+pub(crate) fn reload(service_name: &str) -> Result<(), Error> {
+  let buf = read_unit(service_name)?;
+  let def = unit_def(service_name, &buf)?;
+  update_unit(service_name, &buf, &def)?;
+
+  println!("==> Asking service '{}' to reload ..", service_name);
+  systemctl(&[
+    "kill",
+    "--signal=SIGHUP",
+    "--kill-whom=main",
+    &unit_name(service_name)
+  ])
+}
+
+
+fn unit_name(service_name: &str) -> String {
+  format!("{}.service", service_name)
+}
+
+
+fn unit_path(service_name: &str) -> PathBuf {
+  PathBuf::from(UNIT_DIR).join(unit_name(service_name))
+}
+
+
+/// The definition of the installed service `service_name`.
+pub(crate) fn service_def(service_name: &str) -> Result<ServiceDef, Error> {
+  let buf = read_unit(service_name)?;
+  unit_def(service_name, &buf)
+}
+
+
+fn read_unit(service_name: &str) -> Result<String, Error> {
+  param_store(service_name)?;
+  let unit = unit_path(service_name);
+  fs::read_to_string(&unit).map_err(|e| {
+    Error::Service(format!("Unable to read systemd unit {:?}; {}", unit, e))
+  })
+}
+
+
+/// Rewrite the systemd unit `buf` of the service `service_name` with the
+/// service definition `def` and a stop timeout matching the service's
+/// current `StopGrace`, if that changes anything.
+fn update_unit(
+  service_name: &str,
+  buf: &str,
+  def: &ServiceDef
+) -> Result<(), Error> {
+  // A configuration with invalid values is rejected by the service when
+  // reloading, so only the valid ones are of interest.
+  let store = param_store(service_name)?;
+  let (cfg, _) =
+    Config::check(service_name, &store, &args::Context::default());
+
+  let new = rewrite_unit(buf, def, cfg.stop_grace);
+  if new != buf {
+    let unit = unit_path(service_name);
+    println!("==> Updating systemd unit {:?} ..", unit);
+    fs::write(&unit, new)?;
+    systemctl(&["daemon-reload"])?;
+  }
+  Ok(())
+}
+
+
+/// Replace the service definition and stop timeout lines of the systemd
+/// unit `buf`.
+fn rewrite_unit(buf: &str, def: &ServiceDef, stop_grace: Duration) -> String {
+  let mut lines = Vec::new();
+  for line in buf.lines() {
+    if line.starts_with("TimeoutStopSec=") {
+      lines.push(stop_timeout_line(stop_grace));
+    } else if !is_def_line(line) {
+      lines.push(line.to_string());
+    }
+    if line.trim() == "[Unit]" {
+      lines.extend(unit_def_lines(def));
+    }
+  }
+  lines.join("\n") + "\n"
+}
+
+
+/// systemd kills whatever is left of the service once `TimeoutStopSec` has
+/// passed, so it must leave the supervisor time to go through `StopGrace`.
+fn stop_timeout_line(stop_grace: Duration) -> String {
+  format!("TimeoutStopSec={}", (stop_grace + STOP_MARGIN).as_secs())
+}
+
+
+/// Generate the systemd unit file for a service.
+///
+/// `KillMode=mixed` makes systemd send the stop signal to the supervisor
+/// only, leaving it to the supervisor to shut down the debug server.
+fn unit_file(plan: &Plan) -> Result<String, Error> {
+  let binary = plan.binary.to_str().ok_or_else(|| {
+    Error::BadInput(format!("Exec path {:?} is not valid UTF-8", plan.binary))
+  })?;
+  let mut cmdline = vec![format!("\"{}\"", binary)];
+  cmdline.extend(plan.launch_args.iter().map(|a| format!("\"{}\"", a)));
+
+  Ok(format!(
+    "[Unit]\n\
+     {unit}\n\
+     \n\
+     [Service]\n\
+     Type=notify\n\
+     NotifyAccess=main\n\
+     ExecStart={cmdline}\n\
+     ExecReload=/bin/kill -HUP $MAINPID\n\
+     KillMode=mixed\n\
+     {stop}\n\
+     \n\
+     [Install]\n\
+     WantedBy=multi-user.target\n",
+    unit = unit_def_lines(&plan.def).join("\n"),
+    stop = stop_timeout_line(plan.stop_grace),
+    cmdline = cmdline.join(" ")
+  ))
+}
+
+
+/// The `[Unit]` section lines holding the service definition `def`.
+///
+/// systemd has no separate display name and description, so the display
+/// name is used as the unit's description, and the description is kept in
+/// an extension field for reconfiguring.  Manual start is represented by the
+/// unit not being enabled.
+fn unit_def_lines(def: &ServiceDef) -> Vec<String> {
+  let deps = dependencies(&def.dependencies).join(" ");
+  vec![
+    format!("Description={}", def.display_name),
+    format!("X-Verboten-Description={}", def.description),
+    format!("Wants={}", deps),
+    format!("After={}", deps)
+  ]
+}
+
+
+fn is_def_line(line: &str) -> bool {
+  ["Description=", "X-Verboten-Description=", "Wants=", "After="]
+    .iter()
+    .any(|key| line.starts_with(key))
+}
+
+
+/// Read the service definition back from the systemd unit `buf` of the
+/// service `service_name`.
+fn unit_def(service_name: &str, buf: &str) -> Result<ServiceDef, Error> {
+  let mut def = ServiceDef::new(service_name, "");
+  def.description = String::new();
+  for line in buf.lines() {
+    if let Some(name) = line.strip_prefix("Description=") {
+      def.display_name = name.to_string();
+    } else if let Some(desc) = line.strip_prefix("X-Verboten-Description=") {
+      def.description = desc.to_string();
+    } else if let Some(deps) = line.strip_prefix("Wants=") {
+      def.dependencies.extend(
+        deps
+          .split_whitespace()
+          .filter(|dep| *dep != NETWORK_TARGET)
+          .map(|dep| dep.strip_suffix(".service").unwrap_or(dep).to_string())
+      );
+    }
+  }
+
+  // `is-enabled` fails for units which aren't enabled.
+  let enabled = Command::new("systemctl")
+    .args(["is-enabled", "--quiet", &unit_name(service_name)])
+    .status()
+    .map_err(|e| {
+      Error::Service(format!("Unable to run systemctl is-enabled; {}", e))
+    })?
+    .success();
+  def.start_type = if enabled {
+    StartType::Auto
+  } else {
+    StartType::Manual
+  };
+
+  Ok(def)
+}
+
+
+/// All the units a service depending on `extra` depends on.
+pub(crate) fn dependencies(extra: &[String]) -> Vec<String> {
+  let mut deps = vec![String::from(NETWORK_TARGET)];
+  deps.extend(extra.iter().map(|dep| dependency_unit(dep)));
+  deps
+}
+
+
+/// Name of the unit for the dependency `dep`; plain names refer to services.
+fn dependency_unit(dep: &str) -> String {
+  if dep.contains('.') {
+    dep.to_string()
+  } else {
+    format!("{}.service", dep)
+  }
+}
+
+
+/// Run `systemctl` with the supplied arguments.
+fn systemctl(args: &[&str]) -> Result<(), Error> {
+  let status = Command::new("systemctl").args(args).status().map_err(|e| {
+    Error::Service(format!("Unable to run systemctl {:?}; {}", args, e))
+  })?;
+  if status.success() {
+    Ok(())
+  } else {
+    Err(Error::Service(format!(
+      "systemctl {:?} returned failure ({})",
+      args, status
+    )))
+  }
+}
+
+
+fn params_path(service_name: &str) -> PathBuf {
+  PathBuf::from(PARAMS_DIR).join(format!("{}.conf", service_name))
+}
+
+
+/// Parameter store of the installed service `service_name`.
+pub(crate) fn param_store(service_name: &str) -> Result<FileStore, Error> {
+  let fname = params_path(service_name);
+  if !fname.exists() {
+    return Err(Error::Service(format!(
+      "Service '{}' is not installed",
+      service_name
+    )));
+  }
+  Ok(FileStore::new(fname, FileFormat::Ini))
+}
+
+
+/// Parameter store for the service `service_name`, with any parameters
+/// left over from an earlier installation removed.
+fn create_param_store(service_name: &str) -> Result<FileStore, Error> {
+  let fname = params_path(service_name);
+  if !fname.exists() {
+    fs::create_dir_all(PARAMS_DIR)?;
+    fs::write(&fname, "")?;
+  }
+  let mut store = FileStore::new(fname, FileFormat::Ini);
+  store.clear()?;
+  Ok(store)
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn plan(stop_grace: Duration) -> Plan {
+    Plan {
+      service_name: String::from("dbg"),
+      profile_name: String::from("gdbserver"),
+      exec: PathBuf::from("/usr/bin/gdbserver"),
+      prep_args: None,
+      binary: PathBuf::from("/usr/bin/verboten"),
+      launch_args: vec![String::from("dbg")],
+      def: ServiceDef::new("dbg", "gdbserver"),
+      stop_grace,
+      params: Vec::new(),
+      kept: None
+    }
+  }
+
+  #[test]
+  fn stop_timeout_follows_stop_grace() {
+    let buf = unit_file(&plan(Duration::from_secs(10))).unwrap();
+    assert!(buf.lines().any(|l| l == "TimeoutStopSec=30"));
+
+    let buf = unit_file(&plan(Duration::from_secs(120))).unwrap();
+    assert!(buf.lines().any(|l| l == "TimeoutStopSec=140"));
+  }
+
+  #[test]
+  fn rewrite() {
+    let plan = plan(Duration::from_secs(10));
+    let buf = unit_file(&plan).unwrap();
+    assert_eq!(rewrite_unit(&buf, &plan.def, plan.stop_grace), buf);
+
+    let mut def = plan.def.clone();
+    def.display_name = String::from("Debugging");
+    let new = rewrite_unit(&buf, &def, Duration::from_secs(60));
+    assert_eq!(
+      new,
+      buf
+        .replace(&plan.def.display_name, "Debugging")
+        .replace("TimeoutStopSec=30", "TimeoutStopSec=80")
+    );
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -3,9 +3,10 @@ use std::str::FromStr;
 
 use crate::err::Error;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub enum LogLevel {
   Off,
+  #[default]
   Error,
   Warn,
   Info,
@@ -29,12 +30,6 @@ impl FromStr for LogLevel {
   }
 }
 
-impl Default for LogLevel {
-  fn default() -> Self {
-    LogLevel::Error
-  }
-}
-
 impl fmt::Display for LogLevel {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     let s = match self {
@@ -49,4 +44,17 @@ impl fmt::Display for LogLevel {
   }
 }
 
+impl From<&LogLevel> for log::LevelFilter {
+  fn from(ll: &LogLevel) -> Self {
+    match ll {
+      LogLevel::Off => log::LevelFilter::Off,
+      LogLevel::Error => log::LevelFilter::Error,
+      LogLevel::Warn => log::LevelFilter::Warn,
+      LogLevel::Info => log::LevelFilter::Info,
+      LogLevel::Debug => log::LevelFilter::Debug,
+      LogLevel::Trace => log::LevelFilter::Trace
+    }
+  }
+}
+
 // vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
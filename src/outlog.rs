@@ -0,0 +1,193 @@
+//! Forwarding of the debug server's output to the log.
+//!
+//! Each stream is drained by a thread of its own, so the debug server never
+//! blocks on a full pipe regardless of what the supervisor is doing.  Every
+//! line becomes a log record tagged with the stream and the process id, and
+//! is also written to the session's transcript, if there is one.
+
+use std::cmp;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read};
+use std::sync::Arc;
+use std::thread;
+
+use log::{debug, info, warn};
+
+use crate::transcript::Transcript;
+
+
+/// Lines longer than this are split into multiple records.
+const MAX_LINE: usize = 4096;
+
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Stream {
+  Stdout,
+  Stderr
+}
+
+impl fmt::Display for Stream {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let s = match self {
+      Stream::Stdout => "stdout",
+      Stream::Stderr => "stderr"
+    };
+    write!(f, "{}", s)
+  }
+}
+
+
+/// Spawn a thread which logs each line read from `reader` until it reaches
+/// end of file.  Returns the thread's join handle.
+///
+/// Lines from stdout are logged at `info`, lines from stderr at `warn`.
+pub(crate) fn forward<R>(
+  name: &str,
+  pid: u32,
+  stream: Stream,
+  reader: R,
+  transcript: Option<Arc<Transcript>>
+) -> io::Result<thread::JoinHandle<()>>
+where
+  R: Read + Send + 'static
+{
+  let name = name.to_string();
+  let handle = thread::Builder::new()
+    .name(format!("{}-{}", stream, pid))
+    .spawn(move || {
+      pump(reader, |line| {
+        if let Some(ref t) = transcript {
+          t.write_line(line);
+        }
+        let text = String::from_utf8_lossy(line);
+        match stream {
+          Stream::Stdout => info!("{}[{}] {}: {}", name, pid, stream, text),
+          Stream::Stderr => warn!("{}[{}] {}: {}", name, pid, stream, text)
+        }
+      });
+      debug!("{}[{}] closed its {}", name, pid, stream);
+    })?;
+  Ok(handle)
+}
+
+
+/// Read `reader` until end of file (or an error), passing each line without
+/// its line terminator to `emit`.
+fn pump<R, F>(reader: R, mut emit: F)
+where
+  R: Read,
+  F: FnMut(&[u8])
+{
+  let mut rd = BufReader::new(reader);
+  let mut line: Vec<u8> = Vec::new();
+
+  // Set when the last line was split for being too long.
+  let mut split = false;
+
+  let mut flush = |line: &mut Vec<u8>| {
+    if line.last() == Some(&b'\n') {
+      line.pop();
+    }
+    if line.last() == Some(&b'\r') {
+      line.pop();
+    }
+    emit(line);
+    line.clear();
+  };
+
+  loop {
+    let buf = match rd.fill_buf() {
+      Ok(buf) => buf,
+      Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+      Err(e) => {
+        debug!("error while reading debug server output; {}", e);
+        break;
+      }
+    };
+    if buf.is_empty() {
+      break;
+    }
+
+    let (len, eol) = match buf.iter().position(|&b| b == b'\n') {
+      Some(i) => (i + 1, true),
+      None => (buf.len(), false)
+    };
+    let n = cmp::min(len, MAX_LINE - line.len());
+    line.extend_from_slice(&buf[..n]);
+    rd.consume(n);
+
+    if eol && n == len {
+      // A line which was split right before its terminator has already
+      // been emitted in full.
+      if split && (line == b"\n" || line == b"\r\n") {
+        line.clear();
+      } else {
+        flush(&mut line);
+      }
+      split = false;
+    } else if line.len() >= MAX_LINE {
+      flush(&mut line);
+      split = true;
+    }
+  }
+
+  if !line.is_empty() {
+    flush(&mut line);
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// The lines `pump()` emits for `input`.
+  fn lines(input: &[u8]) -> Vec<Vec<u8>> {
+    let mut lines = Vec::new();
+    pump(input, |line| lines.push(line.to_vec()));
+    lines
+  }
+
+  #[test]
+  fn line_endings() {
+    assert_eq!(
+      lines(b"one\ntwo\r\n\nthree\r\n"),
+      [&b"one"[..], b"two", b"", b"three"]
+    );
+    // Only a carriage return ending a line is removed.
+    assert_eq!(lines(b"one\rtwo\r\r\n"), [b"one\rtwo\r"]);
+  }
+
+  #[test]
+  fn unterminated_last_line() {
+    assert_eq!(lines(b"one\ntwo"), [&b"one"[..], b"two"]);
+    assert_eq!(lines(b"one\r"), [b"one"]);
+    assert!(lines(b"").is_empty());
+  }
+
+  #[test]
+  fn long_lines() {
+    let mut input = vec![b'x'; MAX_LINE * 2 + 10];
+    input.push(b'\n');
+    input.extend_from_slice(b"next\n");
+    let lens: Vec<usize> = lines(&input).iter().map(Vec::len).collect();
+    assert_eq!(lens, [MAX_LINE, MAX_LINE, 10, 4]);
+
+    // No empty line for a line which is exactly as long as the maximum.
+    for eol in &[&b"\n"[..], b"\r\n"] {
+      let mut input = vec![b'x'; MAX_LINE];
+      input.extend_from_slice(eol);
+      input.extend_from_slice(b"next");
+      input.extend_from_slice(eol);
+      let lens: Vec<usize> = lines(&input).iter().map(Vec::len).collect();
+      assert_eq!(lens, [MAX_LINE, 4]);
+    }
+  }
+
+  #[test]
+  fn invalid_utf8() {
+    assert_eq!(lines(b"\xff\xfe\n\xc3("), [&b"\xff\xfe"[..], b"\xc3("]);
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,462 @@
+//! Storage of service parameters.
+//!
+//! Service parameters are plain string key/value pairs.  Where they are
+//! stored depends on the platform (the registry on Windows, a file on
+//! Linux), so everything which reads or writes them does so through the
+//! `ParamStore` trait.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::err::Error;
+
+
+pub(crate) trait ParamStore {
+  /// Human readable description of where the parameters are stored.
+  fn location(&self) -> String;
+
+  /// Get the value of the parameter `key`, or `None` if it has not been set.
+  fn get(&self, key: &str) -> Result<Option<String>, Error>;
+
+  /// Set the parameter `key` to `value`.
+  fn set(&mut self, key: &str, value: &str) -> Result<(), Error>;
+
+  /// Remove the parameter `key`.  Removing a parameter which has not been set
+  /// is not an error.
+  fn unset(&mut self, key: &str) -> Result<(), Error>;
+
+  /// All parameters which have been set, ordered by name.
+  fn list(&self) -> Result<Vec<(String, String)>, Error>;
+
+  /// Remove all parameters.
+  fn clear(&mut self) -> Result<(), Error> {
+    for (key, _) in self.list()? {
+      self.unset(&key)?;
+    }
+    Ok(())
+  }
+}
+
+
+/// Parameters kept in memory only.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MemoryStore {
+  params: BTreeMap<String, String>
+}
+
+impl MemoryStore {
+  pub(crate) fn new() -> Self {
+    MemoryStore::default()
+  }
+
+  /// Collect the parameters in `keys` for which the environment variable
+  /// named by `env_name` has been set.
+  #[cfg_attr(test, allow(dead_code))]
+  pub(crate) fn from_env<F>(keys: &[&str], env_name: F) -> Self
+  where
+    F: Fn(&str) -> String
+  {
+    let mut store = MemoryStore::new();
+    for key in keys {
+      if let Ok(value) = std::env::var(env_name(key)) {
+        store.params.insert(key.to_string(), value);
+      }
+    }
+    store
+  }
+}
+
+impl ParamStore for MemoryStore {
+  fn location(&self) -> String {
+    String::from("memory")
+  }
+
+  fn get(&self, key: &str) -> Result<Option<String>, Error> {
+    Ok(self.params.get(key).cloned())
+  }
+
+  fn set(&mut self, key: &str, value: &str) -> Result<(), Error> {
+    self.params.insert(key.to_string(), value.to_string());
+    Ok(())
+  }
+
+  fn unset(&mut self, key: &str) -> Result<(), Error> {
+    self.params.remove(key);
+    Ok(())
+  }
+
+  fn list(&self) -> Result<Vec<(String, String)>, Error> {
+    Ok(
+      self
+        .params
+        .iter()
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect()
+    )
+  }
+}
+
+
+/// Format of a parameters file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum FileFormat {
+  /// `Key=Value` lines.  Empty lines and lines starting with `#` are
+  /// ignored, and are preserved when the file is updated.
+  Ini,
+
+  /// A TOML table of strings and numbers.
+  Toml
+}
+
+
+/// Parameters stored in a file.
+///
+/// The file is read for every operation and rewritten whenever a parameter
+/// is changed, so changes made by others are picked up.  A missing file is
+/// treated as an empty one.
+pub(crate) struct FileStore {
+  path: PathBuf,
+  format: FileFormat
+}
+
+impl FileStore {
+  pub(crate) fn new(path: PathBuf, format: FileFormat) -> Self {
+    FileStore { path, format }
+  }
+
+  fn read(&self) -> Result<Option<String>, Error> {
+    match fs::read_to_string(&self.path) {
+      Ok(buf) => Ok(Some(buf)),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+      Err(e) => {
+        Err(Error::IO(format!("Unable to read {:?}; {}", self.path, e)))
+      }
+    }
+  }
+
+  /// Write the file by writing a temporary file which is then renamed over
+  /// it, so a crash part way through doesn't leave a truncated file behind.
+  fn write(&self, buf: &str) -> Result<(), Error> {
+    if let Some(dir) = self.path.parent() {
+      fs::create_dir_all(dir)?;
+    }
+    let mut tmp = self.path.clone().into_os_string();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let res = fs::write(&tmp, buf)
+      .and_then(|_| match fs::metadata(&self.path) {
+        // Keep the permissions of the file being replaced.
+        Ok(md) => fs::set_permissions(&tmp, md.permissions()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e)
+      })
+      .and_then(|_| fs::rename(&tmp, &self.path));
+    res.map_err(|e| {
+      let _ = fs::remove_file(&tmp);
+      Error::IO(format!("Unable to write {:?}; {}", self.path, e))
+    })
+  }
+
+  /// Parse an ini file into its lines, with the key and value of each
+  /// parameter line split out.
+  fn ini_lines(&self, buf: &str) -> Result<Vec<IniLine>, Error> {
+    let mut lines = Vec::new();
+    for (n, line) in buf.lines().enumerate() {
+      let trimmed = line.trim();
+      if trimmed.is_empty() || trimmed.starts_with('#') {
+        lines.push(IniLine::Other(line.to_string()));
+        continue;
+      }
+      match trimmed.find('=') {
+        Some(idx) => lines.push(IniLine::Param(
+          trimmed[..idx].trim().to_string(),
+          trimmed[idx + 1..].trim().to_string()
+        )),
+        None => {
+          return Err(Error::BadFormat(format!(
+            "{:?}:{}: expected Key=Value",
+            self.path,
+            n + 1
+          )));
+        }
+      }
+    }
+    Ok(lines)
+  }
+
+  fn toml_table(&self, buf: &str) -> Result<toml::value::Table, Error> {
+    toml::from_str(buf)
+      .map_err(|e| Error::BadFormat(format!("{:?}: {}", self.path, e)))
+  }
+
+  fn write_toml(&self, tbl: &toml::value::Table) -> Result<(), Error> {
+    let buf = toml::to_string(tbl).map_err(|e| {
+      Error::BadFormat(format!("Unable to serialize {:?}; {}", self.path, e))
+    })?;
+    self.write(&buf)
+  }
+}
+
+enum IniLine {
+  Param(String, String),
+  Other(String)
+}
+
+fn write_ini(lines: &[IniLine]) -> String {
+  let mut buf = String::new();
+  for line in lines {
+    match line {
+      IniLine::Param(k, v) => buf.push_str(&format!("{}={}\n", k, v)),
+      IniLine::Other(s) => {
+        buf.push_str(s);
+        buf.push('\n');
+      }
+    }
+  }
+  buf
+}
+
+/// String representation of a TOML parameter value.
+pub(crate) fn toml_string(
+  key: &str,
+  value: &toml::Value
+) -> Result<String, Error> {
+  match value {
+    toml::Value::String(s) => Ok(s.clone()),
+    toml::Value::Integer(i) => Ok(i.to_string()),
+    toml::Value::Float(f) => Ok(f.to_string()),
+    toml::Value::Boolean(b) => Ok(b.to_string()),
+    _ => Err(Error::BadFormat(format!(
+      "{} must be a string or a number",
+      key
+    )))
+  }
+}
+
+impl ParamStore for FileStore {
+  fn location(&self) -> String {
+    format!("{:?}", self.path)
+  }
+
+  fn get(&self, key: &str) -> Result<Option<String>, Error> {
+    Ok(
+      self
+        .list()?
+        .into_iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v)
+    )
+  }
+
+  fn set(&mut self, key: &str, value: &str) -> Result<(), Error> {
+    let buf = self.read()?.unwrap_or_default();
+    match self.format {
+      FileFormat::Ini => {
+        let mut lines = self.ini_lines(&buf)?;
+        let mut found = false;
+        for line in lines.iter_mut() {
+          if let IniLine::Param(k, v) = line {
+            if k == key {
+              *v = value.to_string();
+              found = true;
+            }
+          }
+        }
+        if !found {
+          lines.push(IniLine::Param(key.to_string(), value.to_string()));
+        }
+        self.write(&write_ini(&lines))
+      }
+      FileFormat::Toml => {
+        let mut tbl = self.toml_table(&buf)?;
+        tbl.insert(key.to_string(), toml::Value::String(value.to_string()));
+        self.write_toml(&tbl)
+      }
+    }
+  }
+
+  fn unset(&mut self, key: &str) -> Result<(), Error> {
+    let buf = match self.read()? {
+      Some(buf) => buf,
+      None => return Ok(())
+    };
+    match self.format {
+      FileFormat::Ini => {
+        let mut lines = self.ini_lines(&buf)?;
+        lines.retain(|line| match line {
+          IniLine::Param(k, _) => k != key,
+          IniLine::Other(_) => true
+        });
+        self.write(&write_ini(&lines))
+      }
+      FileFormat::Toml => {
+        let mut tbl = self.toml_table(&buf)?;
+        if tbl.remove(key).is_some() {
+          self.write_toml(&tbl)?;
+        }
+        Ok(())
+      }
+    }
+  }
+
+  fn list(&self) -> Result<Vec<(String, String)>, Error> {
+    let buf = match self.read()? {
+      Some(buf) => buf,
+      None => return Ok(Vec::new())
+    };
+    let mut params = BTreeMap::new();
+    match self.format {
+      FileFormat::Ini => {
+        for line in self.ini_lines(&buf)? {
+          if let IniLine::Param(k, v) = line {
+            params.insert(k, v);
+          }
+        }
+      }
+      FileFormat::Toml => {
+        for (k, v) in self.toml_table(&buf)? {
+          let v = toml_string(&k, &v).map_err(|e| match e {
+            Error::BadFormat(s) => {
+              Error::BadFormat(format!("{:?}: {}", self.path, s))
+            }
+            e => e
+          })?;
+          params.insert(k, v);
+        }
+      }
+    }
+    Ok(params.into_iter().collect())
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// A file in a directory of its own, removed when dropped.
+  struct TempFile {
+    dir: PathBuf,
+    path: PathBuf
+  }
+
+  impl TempFile {
+    fn new(name: &str) -> Self {
+      let dir = std::env::temp_dir().join(format!(
+        "verboten-params-{}-{}",
+        std::process::id(),
+        name
+      ));
+      fs::create_dir_all(&dir).unwrap();
+      let path = dir.join(name);
+      TempFile { dir, path }
+    }
+
+    fn store(&self, format: FileFormat) -> FileStore {
+      FileStore::new(self.path.clone(), format)
+    }
+  }
+
+  impl Drop for TempFile {
+    fn drop(&mut self) {
+      let _ = fs::remove_dir_all(&self.dir);
+    }
+  }
+
+  /// Set, get, list, unset and clear parameters in `store`.
+  fn round_trip(store: &mut dyn ParamStore) {
+    assert_eq!(store.list().unwrap(), []);
+    assert_eq!(store.get("Port").unwrap(), None);
+
+    store.set("Port", "4711").unwrap();
+    store.set("Exec", r"C:\Program Files\msvsmon.exe").unwrap();
+    store.set("Timeout", "8h 30m").unwrap();
+    store.set("Port", "4712").unwrap();
+    assert_eq!(store.get("Port").unwrap().as_deref(), Some("4712"));
+    assert_eq!(
+      store.list().unwrap(),
+      [
+        (
+          String::from("Exec"),
+          String::from(r"C:\Program Files\msvsmon.exe")
+        ),
+        (String::from("Port"), String::from("4712")),
+        (String::from("Timeout"), String::from("8h 30m"))
+      ]
+    );
+
+    store.unset("Port").unwrap();
+    store.unset("Port").unwrap();
+    assert_eq!(store.get("Port").unwrap(), None);
+    assert_eq!(store.list().unwrap().len(), 2);
+
+    store.clear().unwrap();
+    assert_eq!(store.list().unwrap(), []);
+  }
+
+  #[test]
+  fn memory_round_trip() {
+    round_trip(&mut MemoryStore::new());
+  }
+
+  #[test]
+  fn ini_round_trip() {
+    let file = TempFile::new("svc.conf");
+    round_trip(&mut file.store(FileFormat::Ini));
+  }
+
+  #[test]
+  fn toml_round_trip() {
+    let file = TempFile::new("svc.toml");
+    round_trip(&mut file.store(FileFormat::Toml));
+  }
+
+  #[test]
+  fn ini_keeps_comments() {
+    let file = TempFile::new("comments.conf");
+    fs::write(&file.path, "# Debug server\nPort = 4711\n\nLogLevel=warn\n")
+      .unwrap();
+    let mut store = file.store(FileFormat::Ini);
+    store.set("Port", "4712").unwrap();
+    store.unset("LogLevel").unwrap();
+    assert_eq!(
+      fs::read_to_string(&file.path).unwrap(),
+      "# Debug server\nPort=4712\n\n"
+    );
+  }
+
+  #[test]
+  fn toml_numbers() {
+    let file = TempFile::new("numbers.toml");
+    fs::write(&file.path, "Port = 4711\nTimeout = \"1h\"\n").unwrap();
+    let store = file.store(FileFormat::Toml);
+    assert_eq!(store.get("Port").unwrap().as_deref(), Some("4711"));
+    assert_eq!(store.get("Timeout").unwrap().as_deref(), Some("1h"));
+  }
+
+  #[test]
+  fn bad_ini_line() {
+    let file = TempFile::new("bad.conf");
+    fs::write(&file.path, "Port\n").unwrap();
+    let mut store = file.store(FileFormat::Ini);
+    assert!(store.list().is_err());
+    assert!(store.set("Port", "1").is_err());
+    assert_eq!(fs::read_to_string(&file.path).unwrap(), "Port\n");
+  }
+
+  #[test]
+  fn write_leaves_no_temporary_file() {
+    let file = TempFile::new("atomic.conf");
+    file.store(FileFormat::Ini).set("Port", "1").unwrap();
+    let names: Vec<_> = fs::read_dir(&file.dir)
+      .unwrap()
+      .map(|e| e.unwrap().file_name())
+      .collect();
+    assert_eq!(names, ["atomic.conf"]);
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,73 @@
+//! Well-known file system locations.
+
+#[cfg(test)]
+use std::path::Component;
+use std::path::PathBuf;
+
+
+/// Root directory for verboten's own configuration files.
+#[cfg(windows)]
+pub(crate) fn config_root() -> PathBuf {
+  let pd = std::env::var_os("ProgramData")
+    .map(PathBuf::from)
+    .unwrap_or_else(|| PathBuf::from(r"C:\ProgramData"));
+  rooted(pd.join("verboten"))
+}
+
+/// Root directory for verboten's own configuration files.
+#[cfg(not(windows))]
+pub(crate) fn config_root() -> PathBuf {
+  rooted(PathBuf::from("/etc/verboten"))
+}
+
+
+/// Directory containing user-defined debug server profiles.
+pub(crate) fn profile_dir() -> PathBuf {
+  config_root().join("profiles")
+}
+
+
+/// Directory under which each service's transcripts are stored by default.
+#[cfg(windows)]
+pub(crate) fn transcript_root() -> PathBuf {
+  config_root().join("transcripts")
+}
+
+/// Directory under which each service's transcripts are stored by default.
+#[cfg(not(windows))]
+pub(crate) fn transcript_root() -> PathBuf {
+  rooted(PathBuf::from("/var/log/verboten"))
+}
+
+
+/// Directory under which each service's state files are stored.
+#[cfg(windows)]
+pub(crate) fn state_root() -> PathBuf {
+  config_root().join("state")
+}
+
+/// Directory under which each service's state files are stored.
+#[cfg(not(windows))]
+pub(crate) fn state_root() -> PathBuf {
+  rooted(PathBuf::from("/var/lib/verboten"))
+}
+
+
+/// The system directory `path`.
+#[cfg(not(test))]
+fn rooted(path: PathBuf) -> PathBuf {
+  path
+}
+
+/// The system directory `path`, moved below a temporary directory so that
+/// unit tests never touch the real system directories.
+#[cfg(test)]
+fn rooted(path: PathBuf) -> PathBuf {
+  let rel: PathBuf = path
+    .components()
+    .filter(|c| matches!(c, Component::Normal(_)))
+    .collect();
+  std::env::temp_dir().join("verboten-unit-test").join(rel)
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,307 @@
+//! Export and import of a service's configuration as a portable TOML file.
+//!
+//! The file has a `[service]` table describing the service itself and a
+//! `[parameters]` table holding all of its service parameters:
+//!
+//! ```toml
+//! [service]
+//! name = "msvsmon"
+//! display_name = "Verboten msvsmon (msvsmon)"
+//! description = "A service for launching msvsmon in maximum Bad Idea Mode."
+//! start_type = "auto"
+//! dependencies = []
+//!
+//! [parameters]
+//! Exec = "C:\\Tools\\msvsmon.exe"
+//! Timeout = "8h"
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::args;
+use crate::config::{Config, Severity};
+use crate::err::Error;
+use crate::params::{self, MemoryStore, ParamStore};
+use crate::service;
+use crate::svcdef::{self, ServiceDef, StartType};
+
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Portable {
+  service: Service,
+  #[serde(default)]
+  parameters: BTreeMap<String, toml::Value>
+}
+
+/// The service and its definition.  Only the name is required, the rest of
+/// the definition is left at its defaults if it's missing.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Service {
+  name: String,
+  display_name: Option<String>,
+  description: Option<String>,
+  start_type: Option<String>,
+  dependencies: Option<Vec<String>>
+}
+
+
+/// Rewrite of the beginning of the `Exec` path, for importing on a computer
+/// where the debug server is installed elsewhere.
+#[derive(Debug, Clone)]
+pub(crate) struct Rewrite {
+  pub(crate) from: String,
+  pub(crate) to: String
+}
+
+
+/// Print the configuration of the service `service_name` to stdout.
+pub(crate) fn export(service_name: &str) -> Result<(), Error> {
+  let store = service::param_store(service_name)?;
+  let def = service::service_def(service_name)?;
+  let portable = Portable {
+    service: Service {
+      name: service_name.to_string(),
+      display_name: Some(def.display_name),
+      description: Some(def.description),
+      start_type: Some(def.start_type.to_string()),
+      dependencies: Some(def.dependencies)
+    },
+    parameters: store
+      .list()?
+      .into_iter()
+      .map(|(k, v)| (k, toml::Value::String(v)))
+      .collect()
+  };
+  let buf = toml::to_string(&portable).map_err(|e| {
+    Error::BadFormat(format!("Unable to serialize configuration; {}", e))
+  })?;
+  print!("{}", buf);
+  Ok(())
+}
+
+
+/// Create or update a service from the configuration in `fname`.
+///
+/// The service is named `name` if given, and after the name in the file
+/// otherwise.  The configuration and the service definition are validated
+/// before anything is changed.
+pub(crate) fn import(
+  fname: &Path,
+  name: Option<&str>,
+  rewrite: Option<&Rewrite>
+) -> Result<(), Error> {
+  let buf = fs::read_to_string(fname).map_err(|e| {
+    Error::IO(format!("Unable to read {:?}; {}", fname, e))
+  })?;
+  let portable: Portable = toml::from_str(&buf)
+    .map_err(|e| Error::BadFormat(format!("{:?}: {}", fname, e)))?;
+  let service_name = name.unwrap_or(&portable.service.name);
+
+  let mut params = MemoryStore::new();
+  for (k, v) in &portable.parameters {
+    params.set(k, &params::toml_string(k, v)?)?;
+  }
+
+  if let Some(rw) = rewrite {
+    let exec = params.get("Exec")?.ok_or_else(|| {
+      Error::BadInput(String::from("Exec rewrite given, but Exec is not set"))
+    })?;
+    let rest = exec.strip_prefix(&rw.from).ok_or_else(|| {
+      Error::BadInput(format!(
+        "Exec '{}' does not start with '{}'",
+        exec, rw.from
+      ))
+    })?;
+    let exec = format!("{}{}", rw.to, rest);
+    println!("==> Rewrote Exec to '{}'", exec);
+    params.set("Exec", &exec)?;
+  }
+
+  // Validate everything up front, so a bad file leaves the service as it
+  // was.
+  let cli = args::Context::default();
+  let (cfg, problems) = Config::check(service_name, &params, &cli);
+  let mut failed = false;
+  for p in &problems {
+    eprintln!("{}", p);
+    failed |= p.severity == Severity::Error;
+  }
+  if failed {
+    return Err(Error::BadInput(format!(
+      "{:?} is not a valid configuration for service '{}'",
+      fname, service_name
+    )));
+  }
+
+  let def_ctx = def_options(&portable.service, service_name, &cfg.profile);
+  ServiceDef::new(service_name, &cfg.profile).apply(&def_ctx)?;
+
+  let mut store = match service::param_store(service_name) {
+    Ok(store) => {
+      println!("==> Updating service '{}' ..", service_name);
+      store
+    }
+    Err(_) => {
+      let exec = cfg.exec.clone().ok_or_else(|| {
+        Error::BadInput(String::from("Exec must be set to create a service"))
+      })?;
+      let ctx = args::Context {
+        exec: Some(exec),
+        profile: Some(cfg.profile.clone()),
+        ..def_ctx.clone()
+      };
+      service::install(service_name, &ctx)?;
+      service::param_store(service_name)?
+    }
+  };
+
+  println!("==> Writing service parameters to {} ..", store.location());
+  store.clear()?;
+  for (k, v) in params.list()? {
+    store.set(&k, &v)?;
+  }
+
+  // This comes after the service parameters, as the unit's stop timeout
+  // depends on them on Linux.
+  let changes = service::reconfigure(service_name, &cfg.profile, &def_ctx)?;
+  if !changes.is_empty() {
+    println!("==> Changed the service definition:");
+    for change in &changes {
+      println!("{}", change);
+    }
+  }
+
+  println!("==> Service '{}' imported from {:?}", service_name, fname);
+
+  Ok(())
+}
+
+
+/// The install options setting the service definition in `service` of the
+/// service `service_name`, which runs the profile `profile_name`.
+///
+/// A display name which is the default of the exported service follows the
+/// name the service is imported under, and delayed start becomes automatic
+/// start where it isn't supported.
+fn def_options(
+  service: &Service,
+  service_name: &str,
+  profile_name: &str
+) -> args::Context {
+  let exported = svcdef::display_name(&service.name, profile_name);
+  let display_name = service
+    .display_name
+    .clone()
+    .filter(|name| *name != exported || service.name == service_name);
+
+  let mut start_type = service.start_type.clone();
+  if !cfg!(windows)
+    && start_type.as_deref() == Some(&StartType::Delayed.to_string())
+  {
+    println!("==> Delayed start is not supported; using automatic start");
+    start_type = Some(StartType::Auto.to_string());
+  }
+
+  args::Context {
+    display_name,
+    description: service.description.clone(),
+    start_type,
+    dependencies: service.dependencies.clone(),
+    ..Default::default()
+  }
+}
+
+
+/// Parse a `FROM=TO` Exec rewrite.
+pub(crate) fn parse_rewrite(s: &str) -> Result<Rewrite, Error> {
+  match s.find('=') {
+    Some(idx) if idx > 0 => Ok(Rewrite {
+      from: s[..idx].to_string(),
+      to: s[idx + 1..].to_string()
+    }),
+    _ => Err(Error::ArgParser(format!(
+      "Invalid rewrite '{}'; expected FROM=TO",
+      s
+    )))
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn service(name: &str, display_name: &str) -> Service {
+    Service {
+      name: name.to_string(),
+      display_name: Some(display_name.to_string()),
+      description: Some(String::from("Debugging")),
+      start_type: Some(String::from("manual")),
+      dependencies: Some(vec![String::from("sshd")])
+    }
+  }
+
+  #[test]
+  fn name_only() {
+    let portable: Portable =
+      toml::from_str("[service]\nname = \"x\"\n").unwrap();
+    assert_eq!(portable.service.name, "x");
+    assert!(portable.service.display_name.is_none());
+    assert!(portable.parameters.is_empty());
+
+    let ctx = def_options(&portable.service, "x", "gdbserver");
+    let mut def = ServiceDef::new("x", "gdbserver");
+    def.apply(&ctx).unwrap();
+    assert_eq!(def, ServiceDef::new("x", "gdbserver"));
+  }
+
+  #[test]
+  fn definition() {
+    let ctx = def_options(&service("x", "Remote"), "y", "gdbserver");
+    let mut def = ServiceDef::new("y", "gdbserver");
+    def.apply(&ctx).unwrap();
+    assert_eq!(def.display_name, "Remote");
+    assert_eq!(def.description, "Debugging");
+    assert_eq!(def.start_type, StartType::Manual);
+    assert_eq!(def.dependencies, ["sshd"]);
+  }
+
+  #[test]
+  fn default_display_name_follows_name() {
+    let exported = svcdef::display_name("x", "gdbserver");
+    let ctx = def_options(&service("x", &exported), "x", "gdbserver");
+    assert_eq!(ctx.display_name, Some(exported.clone()));
+    let ctx = def_options(&service("x", &exported), "y", "gdbserver");
+    assert_eq!(ctx.display_name, None);
+  }
+
+  #[test]
+  fn delayed_start() {
+    let mut svc = service("x", "Remote");
+    svc.start_type = Some(String::from("delayed"));
+    let ctx = def_options(&svc, "x", "gdbserver");
+    let expected = if cfg!(windows) { "delayed" } else { "auto" };
+    assert_eq!(ctx.start_type.as_deref(), Some(expected));
+  }
+
+  #[test]
+  fn round_trip() {
+    let portable = Portable {
+      service: service("x", "Remote"),
+      parameters: BTreeMap::new()
+    };
+    let buf = toml::to_string(&portable).unwrap();
+    let back: Portable = toml::from_str(&buf).unwrap();
+    assert_eq!(back.service.display_name.as_deref(), Some("Remote"));
+    assert_eq!(back.service.start_type.as_deref(), Some("manual"));
+    assert_eq!(back.service.dependencies, Some(vec![String::from("sshd")]));
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
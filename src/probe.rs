@@ -0,0 +1,184 @@
+//! Probes used to find out whether the debug server is accepting
+//! connections.
+
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+
+/// A profile-specific exchange used to check that the debug server is not
+/// only accepting connections, but is also responding.
+#[derive(Debug, Clone)]
+pub(crate) struct Handshake {
+  /// Sent to the debug server once connected.
+  pub(crate) send: Vec<u8>,
+
+  /// The debug server's response is expected to start with this.
+  pub(crate) expect: Vec<u8>
+}
+
+
+/// Try to connect to `port` on the loopback interface.
+///
+/// Both the IPv4 and the IPv6 loopback addresses are tried, since a debug
+/// server may only be listening on one of them.  Returns the error of the
+/// last attempt if neither accepted a connection within `timeout`.
+pub(crate) fn tcp(port: u16, timeout: Duration) -> io::Result<TcpStream> {
+  let addrs = [
+    SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+    SocketAddr::from((Ipv6Addr::LOCALHOST, port))
+  ];
+
+  let mut last_err = None;
+  for addr in &addrs {
+    match TcpStream::connect_timeout(addr, timeout) {
+      Ok(stream) => return Ok(stream),
+      Err(e) => last_err = Some(e)
+    }
+  }
+  Err(last_err.unwrap())
+}
+
+
+/// Connect to `port` and, if a handshake is given, perform it.
+///
+/// Each step of the check is given `timeout` to complete.
+pub(crate) fn check(
+  port: u16,
+  timeout: Duration,
+  handshake: Option<&Handshake>
+) -> io::Result<()> {
+  let mut stream = tcp(port, timeout)?;
+  let hs = match handshake {
+    Some(hs) => hs,
+    None => return Ok(())
+  };
+
+  stream.set_read_timeout(Some(timeout))?;
+  stream.set_write_timeout(Some(timeout))?;
+  stream.write_all(&hs.send)?;
+
+  let mut buf = vec![0u8; hs.expect.len()];
+  let mut got = 0;
+  while got < buf.len() {
+    let n = stream.read(&mut buf[got..]).map_err(|e| match e.kind() {
+      // Depending on the platform a read timeout is reported as either.
+      io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!(
+          "no handshake response within {}",
+          humantime::format_duration(timeout)
+        )
+      ),
+      _ => e
+    })?;
+    match n {
+      0 => {
+        return Err(io::Error::new(
+          io::ErrorKind::UnexpectedEof,
+          "connection closed during handshake"
+        ));
+      }
+      n => got += n
+    }
+  }
+  if buf != hs.expect {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      format!(
+        "unexpected handshake response {:?}",
+        String::from_utf8_lossy(&buf)
+      )
+    ));
+  }
+  Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::net::TcpListener;
+  use std::thread;
+
+  const TIMEOUT: Duration = Duration::from_secs(5);
+
+  /// A port on which nothing is listening.
+  fn closed_port() -> u16 {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    listener.local_addr().unwrap().port()
+  }
+
+  /// Accept a single connection on a loopback port, and answer whatever is
+  /// sent with `response`.  Returns the port.
+  fn serve(response: &'static [u8]) -> u16 {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+      let (mut stream, _) = listener.accept().unwrap();
+      let mut buf = [0u8; 64];
+      if stream.read(&mut buf).unwrap_or(0) > 0 {
+        let _ = stream.write_all(response);
+      }
+    });
+    port
+  }
+
+  fn handshake() -> Handshake {
+    Handshake {
+      send: b"PING\n".to_vec(),
+      expect: b"PONG".to_vec()
+    }
+  }
+
+  #[test]
+  fn tcp_ipv4() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let stream = tcp(port, TIMEOUT).unwrap();
+    assert!(stream.peer_addr().unwrap().is_ipv4());
+  }
+
+  #[test]
+  fn tcp_ipv6() {
+    // Not every machine has an IPv6 loopback interface.
+    let listener = match TcpListener::bind((Ipv6Addr::LOCALHOST, 0)) {
+      Ok(listener) => listener,
+      Err(_) => return
+    };
+    let port = listener.local_addr().unwrap().port();
+    let stream = tcp(port, TIMEOUT).unwrap();
+    assert!(stream.peer_addr().unwrap().is_ipv6());
+  }
+
+  #[test]
+  fn tcp_refused() {
+    let e = tcp(closed_port(), TIMEOUT).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::ConnectionRefused);
+  }
+
+  #[test]
+  fn check_connect_only() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let port = listener.local_addr().unwrap().port();
+    check(port, TIMEOUT, None).unwrap();
+    assert!(check(closed_port(), TIMEOUT, None).is_err());
+  }
+
+  #[test]
+  fn check_handshake() {
+    let port = serve(b"PONG and then some");
+    check(port, TIMEOUT, Some(&handshake())).unwrap();
+
+    let port = serve(b"PANG");
+    let e = check(port, TIMEOUT, Some(&handshake())).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(e.to_string(), "unexpected handshake response \"PANG\"");
+
+    let port = serve(b"PO");
+    let e = check(port, TIMEOUT, Some(&handshake())).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,424 @@
+//! Debug server profiles.
+//!
+//! A profile knows how to turn the generic service settings (port, timeout,
+//! authentication) into the command line of a particular debug server.
+//!
+//! Besides the built-in profiles, profiles can be defined in TOML files in
+//! the profile directory; see the `profiledef` module.
+
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::err::Error;
+use crate::paths;
+use crate::probe::Handshake;
+use crate::profiledef;
+
+/// Name of the profile used when none has been configured.
+pub(crate) const DEFAULT_PROFILE: &str = "msvsmon";
+
+
+/// Authentication mode requested for the debug server.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) enum Auth {
+  /// Disable authentication and allow anyone to connect.
+  #[default]
+  None,
+
+  /// Use whatever authentication the debug server uses by default.
+  Native
+}
+
+impl FromStr for Auth {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "none" => Ok(Auth::None),
+      "native" => Ok(Auth::Native),
+      _ => Err(Error::BadInput(format!("Unknown auth mode '{}'", s)))
+    }
+  }
+}
+
+impl fmt::Display for Auth {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let s = match self {
+      Auth::None => "none",
+      Auth::Native => "native"
+    };
+    write!(f, "{}", s)
+  }
+}
+
+
+/// When a debug server is considered to be up and running.
+#[derive(Debug, Clone)]
+pub(crate) enum Readiness {
+  /// As soon as the process has been spawned.
+  Spawned,
+
+  /// A fixed amount of time after the process has been spawned.
+  Delay(Duration),
+
+  /// Once the debug server accepts TCP connections on its port.
+  Listening
+}
+
+
+/// Settings which a profile turns into debug server arguments.
+#[derive(Debug, Clone)]
+pub(crate) struct Settings {
+  pub(crate) port: u16,
+  pub(crate) timeout: Option<Duration>,
+  pub(crate) auth: Auth
+}
+
+
+pub(crate) trait DebugServer: Send {
+  /// Name of the profile, as used in the `Profile` service parameter.
+  fn name(&self) -> &str;
+
+  /// Port the debug server listens on unless configured otherwise.
+  fn default_port(&self) -> u16;
+
+  /// Generate the debug server command line arguments.
+  fn args(&self, settings: &Settings) -> Vec<String>;
+
+  /// Returns `true` if the debug server terminates on its own once the
+  /// session timeout has expired.  If not, the supervisor enforces the
+  /// timeout.
+  fn handles_timeout(&self) -> bool {
+    false
+  }
+
+  /// Arguments to run the debug server executable with once at install time
+  /// to prepare the computer (open the firewall, etc), if any.
+  fn prep_args(&self) -> Option<Vec<String>> {
+    None
+  }
+
+  /// Executable to run if the service does not have an `Exec` parameter.
+  fn exec(&self) -> Option<&Path> {
+    None
+  }
+
+  /// Additional environment variables for the debug server process.
+  fn env(&self) -> Vec<(String, String)> {
+    Vec::new()
+  }
+
+  /// When the debug server should be considered to be started.
+  fn readiness(&self) -> Readiness {
+    Readiness::Listening
+  }
+
+  /// Exchange used by health checks to verify that the debug server is
+  /// responding.  Without one a health check only connects to the port.
+  fn handshake(&self) -> Option<Handshake> {
+    None
+  }
+}
+
+
+/// Look up a built-in profile by name.
+pub(crate) fn builtin(name: &str) -> Option<Box<dyn DebugServer>> {
+  match name {
+    "msvsmon" => Some(Box::new(MsVsMon)),
+    "gdbserver" => Some(Box::new(GdbServer)),
+    "lldb-server" => Some(Box::new(LldbServer)),
+    "debugpy" => Some(Box::new(DebugPy)),
+    "dlv" => Some(Box::new(Delve)),
+    _ => None
+  }
+}
+
+
+/// Look up a profile by name.
+///
+/// Built-in profiles take precedence over user-defined profiles in the
+/// profile directory, so the built-in names are effectively reserved.
+pub(crate) fn lookup(name: &str) -> Result<Box<dyn DebugServer>, Error> {
+  if let Some(p) = builtin(name) {
+    return Ok(p);
+  }
+  let dir = paths::profile_dir();
+  match profiledef::find(&dir, name)? {
+    Some(p) => Ok(Box::new(p)),
+    None => Err(Error::BadInput(format!(
+      "Unknown profile '{}' (not built-in and not found in {:?})",
+      name, dir
+    )))
+  }
+}
+
+
+/// Handshake for servers speaking the GDB remote serial protocol: any
+/// well-formed packet is acknowledged with a `+`.
+fn rsp_handshake() -> Handshake {
+  Handshake {
+    send: b"$?#3f".to_vec(),
+    expect: b"+".to_vec()
+  }
+}
+
+
+/// Run a profile's computer preparation command, which runs the debug server
+/// executable `exec` with the arguments `eargs`.
+///
+/// Failures are reported but are not considered fatal.
+pub(crate) fn prepare(exec: &Path, eargs: &[String]) {
+  println!("==> Preparing computer (opening up firewall) ..");
+  let res = Command::new(exec).args(eargs).output();
+  match res {
+    Ok(output) => {
+      if !output.status.success() {
+        println!("{:?} {:?} returned failure", exec, eargs);
+
+        let raw_output = String::from_utf8_lossy(&output.stdout);
+        for line in raw_output.lines() {
+          println!("[stdout] {}", line);
+        }
+        let raw_output = String::from_utf8_lossy(&output.stderr);
+        for line in raw_output.lines() {
+          println!("[stderr] {}", line);
+        }
+      }
+    }
+    Err(_e) => {
+      println!("Unable to run: {:?} {:?}", exec, eargs);
+    }
+  }
+}
+
+
+/// Visual Studio Remote Debugger.
+pub(crate) struct MsVsMon;
+
+impl DebugServer for MsVsMon {
+  fn name(&self) -> &str {
+    "msvsmon"
+  }
+
+  fn default_port(&self) -> u16 {
+    4024
+  }
+
+  fn args(&self, settings: &Settings) -> Vec<String> {
+    let mut eargs: Vec<String> = vec![
+      String::from("/port"),
+      settings.port.to_string()
+    ];
+
+    if let Some(timeout) = settings.timeout {
+      eargs.push(String::from("/timeout"));
+      eargs.push(timeout.as_secs().to_string());
+    }
+
+    if settings.auth == Auth::None {
+      // chest hairs mode
+      // ``Our path was set by the travel agency.
+      //   That's for schoolgirls.
+      //   Now here's a route with some chest hair!''
+      eargs.push(String::from("/noauth"));
+      eargs.push(String::from("/anyuser"));
+      eargs.push(String::from("/nosecuritywarn"));
+    }
+
+    // background process
+    eargs.push(String::from("/silent"));
+
+    eargs
+  }
+
+  fn handles_timeout(&self) -> bool {
+    true
+  }
+
+  fn prep_args(&self) -> Option<Vec<String>> {
+    Some(vec![String::from("/prepcomputer"), String::from("/quiet")])
+  }
+}
+
+
+/// GDB's remote server, in multi-process mode so it keeps running between
+/// debugging sessions.
+///
+/// gdbserver does not do authentication.
+pub(crate) struct GdbServer;
+
+impl DebugServer for GdbServer {
+  fn name(&self) -> &str {
+    "gdbserver"
+  }
+
+  fn default_port(&self) -> u16 {
+    2345
+  }
+
+  fn args(&self, settings: &Settings) -> Vec<String> {
+    vec![String::from("--multi"), format!(":{}", settings.port)]
+  }
+
+  fn handshake(&self) -> Option<Handshake> {
+    Some(rsp_handshake())
+  }
+}
+
+
+/// LLDB's remote platform server.
+///
+/// lldb-server does not do authentication.
+pub(crate) struct LldbServer;
+
+impl DebugServer for LldbServer {
+  fn name(&self) -> &str {
+    "lldb-server"
+  }
+
+  fn default_port(&self) -> u16 {
+    1234
+  }
+
+  fn args(&self, settings: &Settings) -> Vec<String> {
+    vec![
+      String::from("platform"),
+      String::from("--server"),
+      String::from("--listen"),
+      format!("*:{}", settings.port)
+    ]
+  }
+
+  fn handshake(&self) -> Option<Handshake> {
+    Some(rsp_handshake())
+  }
+}
+
+
+/// Python's debugpy adapter.  The executable is the Python interpreter which
+/// has debugpy installed.
+///
+/// debugpy does not do authentication.
+pub(crate) struct DebugPy;
+
+impl DebugServer for DebugPy {
+  fn name(&self) -> &str {
+    "debugpy"
+  }
+
+  fn default_port(&self) -> u16 {
+    5678
+  }
+
+  fn args(&self, settings: &Settings) -> Vec<String> {
+    vec![
+      String::from("-m"),
+      String::from("debugpy.adapter"),
+      String::from("--host"),
+      String::from("0.0.0.0"),
+      String::from("--port"),
+      settings.port.to_string()
+    ]
+  }
+}
+
+
+/// Delve, the Go debugger, as a headless DAP server.
+///
+/// Delve's own default port is the same as gdbserver's, so it gets the next
+/// one to let both profiles be installed side by side with their defaults.
+///
+/// Delve does not do authentication.
+pub(crate) struct Delve;
+
+impl DebugServer for Delve {
+  fn name(&self) -> &str {
+    "dlv"
+  }
+
+  fn default_port(&self) -> u16 {
+    2346
+  }
+
+  fn args(&self, settings: &Settings) -> Vec<String> {
+    vec![String::from("dap"), format!("--listen=:{}", settings.port)]
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn settings(timeout: Option<u64>, auth: Auth) -> Settings {
+    Settings {
+      port: 4711,
+      timeout: timeout.map(Duration::from_secs),
+      auth
+    }
+  }
+
+  #[test]
+  fn builtin_names() {
+    for name in &["msvsmon", "gdbserver", "lldb-server", "debugpy", "dlv"] {
+      assert_eq!(builtin(name).unwrap().name(), *name);
+    }
+    assert!(builtin("vim").is_none());
+  }
+
+  #[test]
+  fn msvsmon_args() {
+    let args = MsVsMon.args(&settings(Some(60), Auth::None));
+    assert_eq!(
+      args,
+      [
+        "/port",
+        "4711",
+        "/timeout",
+        "60",
+        "/noauth",
+        "/anyuser",
+        "/nosecuritywarn",
+        "/silent"
+      ]
+    );
+
+    let args = MsVsMon.args(&settings(None, Auth::Native));
+    assert_eq!(args, ["/port", "4711", "/silent"]);
+  }
+
+  #[test]
+  fn server_args() {
+    let s = settings(Some(60), Auth::None);
+    assert_eq!(GdbServer.args(&s), ["--multi", ":4711"]);
+    assert_eq!(
+      LldbServer.args(&s),
+      ["platform", "--server", "--listen", "*:4711"]
+    );
+    assert_eq!(DebugPy.args(&s)[..2], ["-m", "debugpy.adapter"]);
+    assert_eq!(Delve.args(&s), ["dap", "--listen=:4711"]);
+  }
+
+  #[test]
+  fn distinct_default_ports() {
+    let names = ["msvsmon", "gdbserver", "lldb-server", "debugpy", "dlv"];
+    let mut ports: Vec<u16> = names
+      .iter()
+      .map(|name| builtin(name).unwrap().default_port())
+      .collect();
+    ports.sort_unstable();
+    ports.dedup();
+    assert_eq!(ports.len(), names.len());
+  }
+
+  #[test]
+  fn timeout_handling() {
+    assert!(MsVsMon.handles_timeout());
+    assert!(!GdbServer.handles_timeout());
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,499 @@
+//! User-defined debug server profiles loaded from TOML files.
+//!
+//! Each file `<name>.toml` in the profile directory defines the profile
+//! `<name>`:
+//!
+//! ```toml
+//! # Executable to run if the service does not have an Exec parameter.
+//! exec = "/opt/agent/bin/agent"
+//! port = 7000
+//! args = ["--listen", "0.0.0.0:{port}"]
+//!
+//! # Only added if a session timeout has been configured.  When present the
+//! # debug server is expected to terminate by itself once the timeout has
+//! # expired.
+//! timeout_args = ["--idle-timeout", "{timeout_secs}"]
+//!
+//! # Only added if authentication has been disabled (Auth=none).
+//! noauth_args = ["--insecure"]
+//!
+//! [env]
+//! AGENT_LOG = "debug"
+//!
+//! # Consider the debug server started once this long has passed since it
+//! # was spawned, or (with tcp = true) once it accepts connections on its
+//! # port.  Without a [ready] table it is considered started immediately.
+//! [ready]
+//! delay = "2s"
+//!
+//! # Exchange performed by health checks after connecting to the port.  The
+//! # response must start with `expect`.
+//! [health]
+//! send = "PING\n"
+//! expect = "PONG"
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use crate::err::Error;
+use crate::probe::Handshake;
+use crate::profile::{Auth, DebugServer, Readiness, Settings};
+
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileDef {
+  exec: Option<PathBuf>,
+  port: u16,
+  #[serde(default)]
+  args: Vec<String>,
+  #[serde(default)]
+  timeout_args: Vec<String>,
+  #[serde(default)]
+  noauth_args: Vec<String>,
+  #[serde(default)]
+  env: BTreeMap<String, String>,
+  ready: Option<ReadyDef>,
+  health: Option<HealthDef>
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ReadyDef {
+  delay: Option<String>,
+  #[serde(default)]
+  tcp: bool
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HealthDef {
+  send: String,
+  expect: String
+}
+
+
+/// Placeholders which may be used in argument templates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Placeholder {
+  Port,
+  TimeoutSecs
+}
+
+#[derive(Debug, Clone)]
+enum Piece {
+  Lit(String),
+  Var(Placeholder)
+}
+
+/// A single parsed argument template.
+#[derive(Debug, Clone)]
+struct Template {
+  pieces: Vec<Piece>
+}
+
+impl Template {
+  /// Parse an argument template.
+  ///
+  /// Placeholders are written as `{name}`; literal braces are written as
+  /// `{{` and `}}`.
+  fn parse(s: &str) -> Result<Self, String> {
+    let mut pieces = Vec::new();
+    let mut lit = String::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+      match c {
+        '{' if chars.peek() == Some(&'{') => {
+          chars.next();
+          lit.push('{');
+        }
+        '}' if chars.peek() == Some(&'}') => {
+          chars.next();
+          lit.push('}');
+        }
+        '{' => {
+          let mut name = String::new();
+          loop {
+            match chars.next() {
+              Some('}') => break,
+              Some(c) => name.push(c),
+              None => {
+                return Err(format!("unterminated placeholder in '{}'", s));
+              }
+            }
+          }
+          let var = match name.as_str() {
+            "port" => Placeholder::Port,
+            "timeout_secs" => Placeholder::TimeoutSecs,
+            _ => {
+              return Err(format!(
+                "unknown placeholder '{{{}}}' in '{}' (expected {{port}} or \
+                 {{timeout_secs}})",
+                name, s
+              ));
+            }
+          };
+          if !lit.is_empty() {
+            pieces.push(Piece::Lit(std::mem::take(&mut lit)));
+          }
+          pieces.push(Piece::Var(var));
+        }
+        '}' => {
+          return Err(format!("unmatched '}}' in '{}'", s));
+        }
+        c => lit.push(c)
+      }
+    }
+    if !lit.is_empty() {
+      pieces.push(Piece::Lit(lit));
+    }
+
+    Ok(Template { pieces })
+  }
+
+  fn uses(&self, var: Placeholder) -> bool {
+    self.pieces.iter().any(|p| match p {
+      Piece::Var(v) => *v == var,
+      Piece::Lit(_) => false
+    })
+  }
+
+  fn expand(&self, settings: &Settings) -> String {
+    let mut out = String::new();
+    for p in &self.pieces {
+      match p {
+        Piece::Lit(s) => out.push_str(s),
+        Piece::Var(Placeholder::Port) => {
+          out.push_str(&settings.port.to_string());
+        }
+        Piece::Var(Placeholder::TimeoutSecs) => {
+          let secs = settings.timeout.map(|d| d.as_secs()).unwrap_or(0);
+          out.push_str(&secs.to_string());
+        }
+      }
+    }
+    out
+  }
+}
+
+
+/// A debug server profile defined in a TOML file.
+pub(crate) struct TomlProfile {
+  name: String,
+  exec: Option<PathBuf>,
+  port: u16,
+  args: Vec<Template>,
+  timeout_args: Vec<Template>,
+  noauth_args: Vec<Template>,
+  env: Vec<(String, String)>,
+  readiness: Readiness,
+  handshake: Option<Handshake>
+}
+
+impl TomlProfile {
+  /// Load and validate the profile definition in `fname`.
+  pub(crate) fn load(name: &str, fname: &Path) -> Result<Self, Error> {
+    let buf = fs::read_to_string(fname).map_err(|e| {
+      Error::IO(format!("Unable to read profile {:?}; {}", fname, e))
+    })?;
+    Self::parse(name, &buf)
+      .map_err(|e| Error::BadFormat(format!("Profile {:?}: {}", fname, e)))
+  }
+
+  fn parse(name: &str, buf: &str) -> Result<Self, String> {
+    let def: ProfileDef = toml::from_str(buf).map_err(|e| e.to_string())?;
+
+    let args = parse_templates("args", &def.args)?;
+    let timeout_args = parse_templates("timeout_args", &def.timeout_args)?;
+    let noauth_args = parse_templates("noauth_args", &def.noauth_args)?;
+
+    // The timeout is only known to be set when timeout_args are used.
+    for (field, tmpls) in &[("args", &args), ("noauth_args", &noauth_args)] {
+      if tmpls.iter().any(|t| t.uses(Placeholder::TimeoutSecs)) {
+        return Err(format!(
+          "{{timeout_secs}} may only be used in timeout_args, not in {}",
+          field
+        ));
+      }
+    }
+
+    let readiness = match def.ready {
+      Some(ReadyDef {
+        delay: Some(_),
+        tcp: true
+      }) => {
+        return Err(String::from(
+          "ready.delay and ready.tcp can not be used together"
+        ));
+      }
+      Some(ReadyDef {
+        delay: Some(delay), ..
+      }) => {
+        let d: Duration = delay
+          .parse::<humantime::Duration>()
+          .map_err(|e| format!("invalid ready.delay '{}'; {}", delay, e))?
+          .into();
+        Readiness::Delay(d)
+      }
+      Some(ReadyDef {
+        delay: None,
+        tcp: true
+      }) => Readiness::Listening,
+      Some(ReadyDef {
+        delay: None,
+        tcp: false
+      })
+      | None => Readiness::Spawned
+    };
+
+    let handshake = match def.health {
+      Some(HealthDef { expect, .. }) if expect.is_empty() => {
+        return Err(String::from("health.expect must not be empty"));
+      }
+      Some(HealthDef { send, expect }) => Some(Handshake {
+        send: send.into_bytes(),
+        expect: expect.into_bytes()
+      }),
+      None => None
+    };
+
+    Ok(TomlProfile {
+      name: name.to_string(),
+      exec: def.exec,
+      port: def.port,
+      args,
+      timeout_args,
+      noauth_args,
+      env: def.env.into_iter().collect(),
+      readiness,
+      handshake
+    })
+  }
+}
+
+fn parse_templates(
+  field: &str,
+  args: &[String]
+) -> Result<Vec<Template>, String> {
+  args
+    .iter()
+    .enumerate()
+    .map(|(i, a)| {
+      Template::parse(a).map_err(|e| format!("{}[{}]: {}", field, i, e))
+    })
+    .collect()
+}
+
+impl DebugServer for TomlProfile {
+  fn name(&self) -> &str {
+    &self.name
+  }
+
+  fn default_port(&self) -> u16 {
+    self.port
+  }
+
+  fn args(&self, settings: &Settings) -> Vec<String> {
+    let mut eargs: Vec<String> =
+      self.args.iter().map(|t| t.expand(settings)).collect();
+    if settings.timeout.is_some() {
+      eargs.extend(self.timeout_args.iter().map(|t| t.expand(settings)));
+    }
+    if settings.auth == Auth::None {
+      eargs.extend(self.noauth_args.iter().map(|t| t.expand(settings)));
+    }
+    eargs
+  }
+
+  fn handles_timeout(&self) -> bool {
+    !self.timeout_args.is_empty()
+  }
+
+  fn exec(&self) -> Option<&Path> {
+    self.exec.as_deref()
+  }
+
+  fn env(&self) -> Vec<(String, String)> {
+    self.env.clone()
+  }
+
+  fn readiness(&self) -> Readiness {
+    self.readiness.clone()
+  }
+
+  fn handshake(&self) -> Option<Handshake> {
+    self.handshake.clone()
+  }
+}
+
+
+/// Load the user-defined profile `name` from `dir`.
+///
+/// Returns `Ok(None)` if there is no such profile.
+pub(crate) fn find(
+  dir: &Path,
+  name: &str
+) -> Result<Option<TomlProfile>, Error> {
+  // Don't let the profile name escape the profile directory.
+  if name.is_empty()
+    || !name
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+    || name.starts_with('.')
+  {
+    return Err(Error::BadInput(format!("Invalid profile name '{}'", name)));
+  }
+
+  let fname = dir.join(format!("{}.toml", name));
+  if !fname.exists() {
+    return Ok(None);
+  }
+  Ok(Some(TomlProfile::load(name, &fname)?))
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::paths;
+  use crate::profile;
+
+  fn settings(timeout: Option<u64>) -> Settings {
+    Settings {
+      port: 4711,
+      timeout: timeout.map(Duration::from_secs),
+      auth: Auth::Native
+    }
+  }
+
+  fn expand(tmpl: &str) -> String {
+    Template::parse(tmpl).unwrap().expand(&settings(Some(60)))
+  }
+
+  /// The error parsing the profile definition `buf` fails with.
+  fn error(buf: &str) -> String {
+    TomlProfile::parse("custom", buf).err().unwrap()
+  }
+
+  #[test]
+  fn templates() {
+    assert_eq!(expand("--listen=0.0.0.0:{port}"), "--listen=0.0.0.0:4711");
+    assert_eq!(expand("{timeout_secs}s"), "60s");
+    assert_eq!(expand("{{port}}"), "{port}");
+    assert_eq!(expand("a}}b{{"), "a}b{");
+    assert_eq!(expand("{{{port}}}"), "{4711}");
+  }
+
+  #[test]
+  fn template_errors() {
+    let e = Template::parse("--host={host}").unwrap_err();
+    assert!(e.starts_with("unknown placeholder '{host}'"), "{}", e);
+    let e = Template::parse("--port={port").unwrap_err();
+    assert!(e.starts_with("unterminated placeholder"), "{}", e);
+    let e = Template::parse("--port=port}").unwrap_err();
+    assert!(e.starts_with("unmatched '}'"), "{}", e);
+
+    let e = error("port = 1\nargs = [\"a\", \"{port\"]");
+    assert!(e.starts_with("args[1]: unterminated placeholder"), "{}", e);
+  }
+
+  #[test]
+  fn timeout_secs_only_in_timeout_args() {
+    let p = TomlProfile::parse(
+      "custom",
+      r#"
+        port = 1
+        args = ["-p", "{port}"]
+        timeout_args = ["-t", "{timeout_secs}"]
+      "#
+    )
+    .unwrap();
+    assert!(p.handles_timeout());
+    assert_eq!(p.args(&settings(None)), ["-p", "4711"]);
+    assert_eq!(p.args(&settings(Some(60))), ["-p", "4711", "-t", "60"]);
+
+    for field in &["args", "noauth_args"] {
+      let e = error(&format!("port = 1\n{} = [\"{{timeout_secs}}\"]", field));
+      assert_eq!(
+        e,
+        format!(
+          "{{timeout_secs}} may only be used in timeout_args, not in {}",
+          field
+        )
+      );
+    }
+  }
+
+  #[test]
+  fn readiness() {
+    let p = TomlProfile::parse("custom", "port = 1\n[ready]\ndelay = \"2s\"");
+    match p.unwrap().readiness() {
+      Readiness::Delay(d) => assert_eq!(d, Duration::from_secs(2)),
+      r => panic!("{:?}", r)
+    }
+    let p = TomlProfile::parse("custom", "port = 1\n[ready]\ntcp = true");
+    assert!(matches!(p.unwrap().readiness(), Readiness::Listening));
+    let p = TomlProfile::parse("custom", "port = 1");
+    assert!(matches!(p.unwrap().readiness(), Readiness::Spawned));
+
+    let e = error("port = 1\n[ready]\ndelay = \"2s\"\ntcp = true");
+    assert_eq!(e, "ready.delay and ready.tcp can not be used together");
+    let e = error("port = 1\n[ready]\ndelay = \"soon\"");
+    assert!(e.starts_with("invalid ready.delay 'soon'"), "{}", e);
+  }
+
+  #[test]
+  fn health() {
+    let p = TomlProfile::parse(
+      "custom",
+      "port = 1\n[health]\nsend = \"PING\"\nexpect = \"PONG\""
+    )
+    .unwrap();
+    let hs = p.handshake().unwrap();
+    assert_eq!(hs.send, b"PING");
+    assert_eq!(hs.expect, b"PONG");
+
+    let e = error("port = 1\n[health]\nsend = \"PING\"\nexpect = \"\"");
+    assert_eq!(e, "health.expect must not be empty");
+  }
+
+  #[test]
+  fn invalid_names() {
+    let dir = paths::profile_dir();
+    for name in &["", "../custom", "sub/custom", r"sub\custom", ".hidden"] {
+      match find(&dir, name) {
+        Err(Error::BadInput(_)) => {}
+        Err(e) => panic!("{:?}: {}", name, e),
+        Ok(_) => panic!("{:?} accepted", name)
+      }
+    }
+    assert!(find(&dir, "no-such-profile").unwrap().is_none());
+  }
+
+  #[test]
+  fn builtin_names_are_reserved() {
+    let dir = paths::profile_dir();
+    fs::create_dir_all(&dir).unwrap();
+    let fname = dir.join("dlv.toml");
+    fs::write(&fname, "port = 1\n").unwrap();
+
+    // The file is a valid profile, but the built-in one is used.
+    assert_eq!(find(&dir, "dlv").unwrap().unwrap().default_port(), 1);
+    let p = profile::lookup("dlv").unwrap();
+    assert_eq!(
+      p.default_port(),
+      profile::builtin("dlv").unwrap().default_port()
+    );
+
+    let _ = fs::remove_file(&fname);
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
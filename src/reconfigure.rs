@@ -0,0 +1,281 @@
+//! Reconfiguration of an installed service in place.
+//!
+//! Only what has been given on the command line is changed; both the service
+//! parameters and the service definition are otherwise left as they are.
+
+use std::fmt;
+
+use crate::args;
+use crate::config::{self, Config};
+use crate::err::Error;
+use crate::params::ParamStore;
+use crate::service;
+use crate::svcdef::ServiceDef;
+
+
+/// A single change made by reconfiguring a service.
+pub(crate) struct Change {
+  what: String,
+  old: Option<String>,
+  new: String
+}
+
+impl Change {
+  pub(crate) fn new(what: &str, old: Option<String>, new: String) -> Self {
+    Change {
+      what: what.to_string(),
+      old,
+      new
+    }
+  }
+}
+
+impl fmt::Display for Change {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self.old {
+      Some(ref old) => {
+        write!(f, "  {}: '{}' -> '{}'", self.what, old, self.new)
+      }
+      None => write!(f, "  {}: (unset) -> '{}'", self.what, self.new)
+    }
+  }
+}
+
+
+/// Apply the options in `ctx` to the installed service `service_name` and
+/// print what changed, or only print what would change for a dry run.
+pub(crate) fn reconfigure(
+  service_name: &str,
+  ctx: &args::Context
+) -> Result<(), Error> {
+  // A service with such a name can't be launched; it must be reinstalled
+  // under a different one.
+  args::check_service_name(service_name)?;
+  let mut store = service::param_store(service_name)?;
+
+  apply(service_name, &mut store, ctx, |old_profile| {
+    service::reconfigure(service_name, old_profile, ctx)
+  })
+}
+
+
+/// Apply the options in `ctx` to the service parameters in `store`, then
+/// update the service definition using `update_def`, which is passed the
+/// profile the service was configured with before.
+///
+/// The parameters are written first, since the service definition may be
+/// derived from them (the systemd unit's stop timeout is).  They are
+/// restored if updating the service definition fails.
+fn apply<F>(
+  service_name: &str,
+  store: &mut dyn ParamStore,
+  ctx: &args::Context,
+  update_def: F
+) -> Result<(), Error>
+where
+  F: FnOnce(&str) -> Result<Vec<Change>, Error>
+{
+  let mut given = Config::default();
+  given.apply_cli(ctx)?;
+
+  let mut params = Vec::new();
+  let mut previous = Vec::new();
+  let mut changes = Vec::new();
+  for (key, value) in given.explicit() {
+    let old = store.get(key)?;
+    if old.as_deref() != Some(value.as_str()) {
+      changes.push(Change::new(key, old.clone(), value.clone()));
+      params.push((key, value));
+      previous.push((key, old));
+    }
+  }
+
+  // Make sure the options are valid before touching anything.
+  ServiceDef::new(service_name, &given.profile).apply(ctx)?;
+  config::check_change(service_name, store, |p| {
+    for (k, v) in &params {
+      p.set(k, v)?;
+    }
+    Ok(())
+  })?;
+
+  let cli = args::Context::default();
+  let (old_cfg, _problems) = Config::check(service_name, store, &cli);
+
+  if ctx.dry_run {
+    println!("==> Dry run; not reconfiguring service '{}'", service_name);
+    changes.extend(update_def(&old_cfg.profile)?);
+    if changes.is_empty() {
+      println!("==> Nothing would change");
+    } else {
+      println!("==> Would change:");
+    }
+  } else {
+    println!("==> Reconfiguring service '{}' ..", service_name);
+    let res =
+      set_params(store, &params).and_then(|_| update_def(&old_cfg.profile));
+    match res {
+      Ok(def_changes) => changes.extend(def_changes),
+      Err(e) => {
+        restore_params(store, &previous);
+        return Err(e);
+      }
+    }
+    if changes.is_empty() {
+      println!("==> Nothing has changed");
+    }
+  }
+  for change in &changes {
+    println!("{}", change);
+  }
+
+  Ok(())
+}
+
+
+fn set_params(
+  store: &mut dyn ParamStore,
+  params: &[(&str, String)]
+) -> Result<(), Error> {
+  for (k, v) in params {
+    store.set(k, v)?;
+  }
+  Ok(())
+}
+
+
+/// Put the parameters back the way they were before a failed
+/// reconfiguration.
+fn restore_params(
+  store: &mut dyn ParamStore,
+  previous: &[(&str, Option<String>)]
+) {
+  for (k, old) in previous {
+    let res = match old {
+      Some(v) => store.set(k, v),
+      None => store.unset(k)
+    };
+    if let Err(e) = res {
+      eprintln!("==> Unable to restore parameter {}; {}", k, e);
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fs;
+  use std::path::PathBuf;
+
+  use crate::params::{FileFormat, FileStore};
+
+  const SVC: &str = "verboten-unit-test";
+
+  /// A parameters file in a directory of its own, removed when dropped.
+  struct TempParams(PathBuf);
+
+  impl TempParams {
+    fn new(name: &str) -> Self {
+      let dir = std::env::temp_dir().join(format!(
+        "verboten-reconfigure-{}-{}",
+        std::process::id(),
+        name
+      ));
+      fs::create_dir_all(&dir).unwrap();
+      let params = TempParams(dir);
+      let mut store = params.store();
+      let exec = std::env::current_exe().unwrap();
+      store.set("Exec", exec.to_str().unwrap()).unwrap();
+      store.set("Profile", "gdbserver").unwrap();
+      params
+    }
+
+    fn store(&self) -> FileStore {
+      FileStore::new(self.0.join("params.conf"), FileFormat::Ini)
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+      self.store().get(key).unwrap()
+    }
+  }
+
+  impl Drop for TempParams {
+    fn drop(&mut self) {
+      let _ = fs::remove_dir_all(&self.0);
+    }
+  }
+
+  fn ctx(profile: &str, port: &str) -> args::Context {
+    args::Context {
+      profile: Some(profile.to_string()),
+      port: Some(port.to_string()),
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn params_before_definition() {
+    let params = TempParams::new("order");
+    let mut updated = false;
+    apply(
+      SVC,
+      &mut params.store(),
+      &ctx("dlv", "2000"),
+      |old_profile| {
+        assert_eq!(old_profile, "gdbserver");
+        // The definition is updated from the new parameters.
+        assert_eq!(params.get("Profile").as_deref(), Some("dlv"));
+        assert_eq!(params.get("Port").as_deref(), Some("2000"));
+        updated = true;
+        Ok(Vec::new())
+      }
+    )
+    .unwrap();
+    assert!(updated);
+    assert_eq!(params.get("Profile").as_deref(), Some("dlv"));
+  }
+
+  #[test]
+  fn restore_params_on_failure() {
+    let params = TempParams::new("restore");
+    let res = apply(SVC, &mut params.store(), &ctx("dlv", "2000"), |_| {
+      Err(Error::Service(String::from("nope")))
+    });
+    assert!(res.is_err());
+    assert_eq!(params.get("Profile").as_deref(), Some("gdbserver"));
+    assert_eq!(params.get("Port"), None);
+  }
+
+  #[test]
+  fn dry_run() {
+    let params = TempParams::new("dry-run");
+    let ctx = args::Context {
+      dry_run: true,
+      ..ctx("dlv", "2000")
+    };
+    let mut updated = false;
+    apply(SVC, &mut params.store(), &ctx, |_| {
+      updated = true;
+      Ok(Vec::new())
+    })
+    .unwrap();
+    assert!(updated);
+    assert_eq!(params.get("Profile").as_deref(), Some("gdbserver"));
+    assert_eq!(params.get("Port"), None);
+  }
+
+  #[test]
+  fn invalid_change() {
+    let params = TempParams::new("invalid");
+    for ctx in &[ctx("dlv", "0"), ctx("no-such-profile", "2000")] {
+      let res = apply(SVC, &mut params.store(), ctx, |_| {
+        panic!("definition updated")
+      });
+      assert!(res.is_err());
+    }
+    assert_eq!(params.get("Profile").as_deref(), Some("gdbserver"));
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
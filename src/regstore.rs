@@ -0,0 +1,108 @@
+//! Service parameters stored in the registry.
+
+use std::io::ErrorKind;
+
+use winreg::{enums::*, types::FromRegValue, RegKey};
+
+use crate::err::Error;
+use crate::params::ParamStore;
+
+const SERVICES_KEY: &str = "SYSTEM\\CurrentControlSet\\Services";
+
+
+/// Parameters stored as values of the `Parameters` subkey of a service's
+/// registry key.
+pub(crate) struct RegistryStore {
+  path: String,
+  key: RegKey
+}
+
+impl RegistryStore {
+  /// Open the parameters of the installed service `service_name`.
+  ///
+  /// The `Parameters` subkey is created if the service does not have one.
+  pub(crate) fn open(service_name: &str) -> Result<Self, Error> {
+    let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
+    let services = hklm.open_subkey(SERVICES_KEY)?;
+    let asrv = match services.open_subkey(service_name) {
+      Ok(k) => k,
+      Err(e) if e.kind() == ErrorKind::NotFound => {
+        return Err(Error::Service(format!(
+          "Service '{}' is not installed",
+          service_name
+        )));
+      }
+      Err(e) => return Err(e.into())
+    };
+    let (key, _disp) = asrv.create_subkey("Parameters")?;
+
+    Ok(RegistryStore {
+      path: format!("HKLM\\{}\\{}\\Parameters", SERVICES_KEY, service_name),
+      key
+    })
+  }
+
+  /// Open the parameters of the service `service_name`, removing any
+  /// parameters which have already been set.
+  pub(crate) fn create(service_name: &str) -> Result<Self, Error> {
+    let mut store = RegistryStore::open(service_name)?;
+    store.clear()?;
+    Ok(store)
+  }
+}
+
+impl ParamStore for RegistryStore {
+  fn location(&self) -> String {
+    self.path.clone()
+  }
+
+  fn get(&self, key: &str) -> Result<Option<String>, Error> {
+    match self.key.get_raw_value(key) {
+      Ok(v) => Ok(Some(reg_string(key, &v)?)),
+      Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+      Err(e) => Err(e.into())
+    }
+  }
+
+  fn set(&mut self, key: &str, value: &str) -> Result<(), Error> {
+    self.key.set_value(key, &value)?;
+    Ok(())
+  }
+
+  fn unset(&mut self, key: &str) -> Result<(), Error> {
+    match self.key.delete_value(key) {
+      Ok(_) => Ok(()),
+      Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+      Err(e) => Err(e.into())
+    }
+  }
+
+  fn list(&self) -> Result<Vec<(String, String)>, Error> {
+    let mut params = Vec::new();
+    for item in self.key.enum_values() {
+      let (key, v) = item?;
+      let v = reg_string(&key, &v)?;
+      params.push((key, v));
+    }
+    params.sort();
+    Ok(params)
+  }
+}
+
+
+/// String representation of a registry value.  Parameters are normally
+/// stored as strings, but DWORDs set by hand are accepted too.
+fn reg_string(key: &str, v: &winreg::RegValue) -> Result<String, Error> {
+  if let Ok(s) = String::from_reg_value(v) {
+    return Ok(s);
+  }
+  if let Ok(n) = u32::from_reg_value(v) {
+    return Ok(n.to_string());
+  }
+  Err(Error::BadFormat(format!(
+    "Registry value {} must be a string or a DWORD",
+    key
+  )))
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
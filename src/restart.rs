@@ -0,0 +1,256 @@
+//! Restart policy for the supervised debug server.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::err::Error;
+
+
+/// When the debug server should be restarted after it has exited on its
+/// own.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub(crate) enum Policy {
+  /// Never restart; the service stops when the debug server exits.
+  #[default]
+  Never,
+
+  /// Restart if the debug server exited with a failure status.
+  OnFailure,
+
+  /// Always restart, regardless of exit status.
+  Always
+}
+
+impl FromStr for Policy {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "never" => Ok(Policy::Never),
+      "on-failure" => Ok(Policy::OnFailure),
+      "always" => Ok(Policy::Always),
+      _ => Err(Error::BadInput(format!("Unknown restart policy '{}'", s)))
+    }
+  }
+}
+
+impl fmt::Display for Policy {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let s = match self {
+      Policy::Never => "never",
+      Policy::OnFailure => "on-failure",
+      Policy::Always => "always"
+    };
+    write!(f, "{}", s)
+  }
+}
+
+
+#[derive(Debug, Clone)]
+pub(crate) struct RestartConfig {
+  pub(crate) policy: Policy,
+
+  /// Delay before the first restart.  Doubled for each consecutive restart.
+  pub(crate) initial_backoff: Duration,
+
+  /// Upper bound for the restart delay.
+  pub(crate) max_backoff: Duration,
+
+  /// Maximum number of restarts allowed within `window` before the
+  /// supervisor gives up.
+  pub(crate) max_restarts: u32,
+
+  pub(crate) window: Duration
+}
+
+impl RestartConfig {
+  pub(crate) fn from_config(cfg: &Config) -> Self {
+    RestartConfig {
+      policy: cfg.restart_policy,
+      initial_backoff: cfg.restart_delay,
+      max_backoff: cfg.restart_max_delay,
+      max_restarts: cfg.restart_limit,
+      window: cfg.restart_window
+    }
+  }
+}
+
+
+/// What to do after the debug server has exited on its own.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Decision {
+  /// Don't restart; stop the service.
+  Stop,
+
+  /// Restart after the given delay.
+  Restart(Duration),
+
+  /// The debug server has been restarted too many times within the restart
+  /// window; give up.
+  CrashLoop
+}
+
+
+/// Keeps track of restarts and applies the restart policy.
+pub(crate) struct Restarter {
+  cfg: RestartConfig,
+  backoff: Duration,
+  history: VecDeque<Instant>
+}
+
+impl Restarter {
+  pub(crate) fn new(cfg: RestartConfig) -> Self {
+    let backoff = cfg.initial_backoff;
+    Restarter {
+      cfg,
+      backoff,
+      history: VecDeque::new()
+    }
+  }
+
+  /// Replace the restart configuration, keeping the restart history.
+  pub(crate) fn set_config(&mut self, cfg: RestartConfig) {
+    self.backoff = self.backoff.max(cfg.initial_backoff).min(cfg.max_backoff);
+    self.cfg = cfg;
+  }
+
+  /// Number of restarts within the current restart window.
+  pub(crate) fn restarts(&self) -> usize {
+    self.history.len()
+  }
+
+  /// Decide what to do about a debug server which exited at `now` after
+  /// having run for `uptime`.
+  ///
+  /// A debug server which stayed up for longer than the maximum backoff is
+  /// considered to have been healthy, and the backoff is reset.
+  pub(crate) fn on_exit(
+    &mut self,
+    success: bool,
+    uptime: Duration,
+    now: Instant
+  ) -> Decision {
+    match self.cfg.policy {
+      Policy::Never => return Decision::Stop,
+      Policy::OnFailure if success => return Decision::Stop,
+      _ => {}
+    }
+
+    while let Some(t) = self.history.front() {
+      if now.duration_since(*t) > self.cfg.window {
+        self.history.pop_front();
+      } else {
+        break;
+      }
+    }
+    if self.history.len() >= self.cfg.max_restarts as usize {
+      return Decision::CrashLoop;
+    }
+    self.history.push_back(now);
+
+    if uptime > self.cfg.max_backoff {
+      self.backoff = self.cfg.initial_backoff;
+    }
+    let delay = self.backoff;
+    self.backoff = std::cmp::min(self.backoff * 2, self.cfg.max_backoff);
+
+    Decision::Restart(delay)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn cfg(policy: Policy) -> RestartConfig {
+    RestartConfig {
+      policy,
+      initial_backoff: Duration::from_secs(1),
+      max_backoff: Duration::from_secs(8),
+      max_restarts: 3,
+      window: Duration::from_secs(60)
+    }
+  }
+
+  const SHORT: Duration = Duration::from_secs(1);
+
+  #[test]
+  fn never_stops() {
+    let mut r = Restarter::new(cfg(Policy::Never));
+    let now = Instant::now();
+    assert_eq!(r.on_exit(false, SHORT, now), Decision::Stop);
+    assert_eq!(r.on_exit(true, SHORT, now), Decision::Stop);
+  }
+
+  #[test]
+  fn on_failure_only_restarts_failures() {
+    let mut r = Restarter::new(cfg(Policy::OnFailure));
+    let now = Instant::now();
+    assert_eq!(r.on_exit(true, SHORT, now), Decision::Stop);
+    assert_eq!(
+      r.on_exit(false, SHORT, now),
+      Decision::Restart(Duration::from_secs(1))
+    );
+  }
+
+  #[test]
+  fn backoff_doubles_up_to_max() {
+    let mut c = cfg(Policy::Always);
+    c.max_restarts = 10;
+    let mut r = Restarter::new(c);
+    let now = Instant::now();
+    let delays: Vec<Decision> =
+      (0..5).map(|_| r.on_exit(true, SHORT, now)).collect();
+    let expected: Vec<Decision> = [1, 2, 4, 8, 8]
+      .iter()
+      .map(|s| Decision::Restart(Duration::from_secs(*s)))
+      .collect();
+    assert_eq!(delays, expected);
+  }
+
+  #[test]
+  fn backoff_resets_after_healthy_run() {
+    let mut r = Restarter::new(cfg(Policy::Always));
+    let now = Instant::now();
+    r.on_exit(false, SHORT, now);
+    r.on_exit(false, SHORT, now);
+    assert_eq!(
+      r.on_exit(false, Duration::from_secs(9), now),
+      Decision::Restart(Duration::from_secs(1))
+    );
+  }
+
+  #[test]
+  fn crash_loop_within_window() {
+    let mut r = Restarter::new(cfg(Policy::Always));
+    let start = Instant::now();
+    for i in 0..3 {
+      let now = start + Duration::from_secs(i);
+      assert!(matches!(r.on_exit(false, SHORT, now), Decision::Restart(_)));
+    }
+    assert_eq!(r.restarts(), 3);
+    let now = start + Duration::from_secs(3);
+    assert_eq!(r.on_exit(false, SHORT, now), Decision::CrashLoop);
+  }
+
+  #[test]
+  fn restarts_expire_from_window() {
+    let mut r = Restarter::new(cfg(Policy::Always));
+    let start = Instant::now();
+    for _ in 0..3 {
+      r.on_exit(false, SHORT, start);
+    }
+    let later = start + Duration::from_secs(61);
+    assert!(matches!(
+      r.on_exit(false, SHORT, later),
+      Decision::Restart(_)
+    ));
+    assert_eq!(r.restarts(), 1);
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,341 @@
+//! Thin wrappers around the parts of the Windows service control manager API
+//! which the windows-service crate doesn't cover.
+
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::ptr;
+
+use winapi::shared::minwindef::{BOOL, DWORD};
+use winapi::shared::winerror::ERROR_INSUFFICIENT_BUFFER;
+use winapi::um::winnt::{SERVICE_AUTO_START, SERVICE_DEMAND_START};
+use winapi::um::winsvc::{
+  ChangeServiceConfig2W, ChangeServiceConfigW, CloseServiceHandle,
+  ControlService, OpenSCManagerW, OpenServiceW, QueryServiceConfig2W,
+  QueryServiceConfigW, QueryServiceStatusEx, QUERY_SERVICE_CONFIGW,
+  SC_HANDLE, SC_MANAGER_CONNECT, SC_STATUS_PROCESS_INFO,
+  SERVICE_CONFIG_DELAYED_AUTO_START_INFO, SERVICE_CONFIG_DESCRIPTION,
+  SERVICE_DESCRIPTIONW, SERVICE_NO_CHANGE, SERVICE_STATUS,
+  SERVICE_STATUS_PROCESS
+};
+
+use crate::err::Error;
+use crate::svcdef::StartType;
+
+
+/// `SERVICE_DELAYED_AUTO_START_INFO`, which winapi doesn't define.
+#[repr(C)]
+struct DelayedAutoStartInfo {
+  delayed: BOOL
+}
+
+
+/// An open service, along with the service control manager it was opened
+/// through.
+pub(crate) struct ServiceHandle {
+  scm: SC_HANDLE,
+  svc: SC_HANDLE
+}
+
+impl ServiceHandle {
+  /// Open the service `service_name` with the access rights `access`.
+  pub(crate) fn open(
+    service_name: &str,
+    access: DWORD
+  ) -> Result<Self, Error> {
+    let name = to_wide(service_name);
+    unsafe {
+      let scm = OpenSCManagerW(ptr::null(), ptr::null(), SC_MANAGER_CONNECT);
+      if scm.is_null() {
+        return Err(io::Error::last_os_error().into());
+      }
+      let svc = OpenServiceW(scm, name.as_ptr(), access);
+      if svc.is_null() {
+        let e = io::Error::last_os_error();
+        CloseServiceHandle(scm);
+        return Err(Error::Service(format!(
+          "Unable to open service '{}'; {}",
+          service_name, e
+        )));
+      }
+      Ok(ServiceHandle { scm, svc })
+    }
+  }
+
+  /// Send the control code `control` to the service.
+  pub(crate) fn control(&self, control: DWORD) -> Result<(), Error> {
+    let mut status: SERVICE_STATUS = unsafe { std::mem::zeroed() };
+    if unsafe { ControlService(self.svc, control, &mut status) } == 0 {
+      return Err(Error::Service(format!(
+        "Unable to send control {} to service; {}",
+        control,
+        io::Error::last_os_error()
+      )));
+    }
+    Ok(())
+  }
+
+  /// The service's current status, including the id of its process.
+  /// Requires `SERVICE_QUERY_STATUS` access.
+  pub(crate) fn status(&self) -> Result<SERVICE_STATUS_PROCESS, Error> {
+    let mut status: SERVICE_STATUS_PROCESS = unsafe { std::mem::zeroed() };
+    let mut needed: DWORD = 0;
+    let res = unsafe {
+      QueryServiceStatusEx(
+        self.svc,
+        SC_STATUS_PROCESS_INFO,
+        &mut status as *mut SERVICE_STATUS_PROCESS as *mut u8,
+        std::mem::size_of::<SERVICE_STATUS_PROCESS>() as DWORD,
+        &mut needed
+      )
+    };
+    if res == 0 {
+      return Err(io::Error::last_os_error().into());
+    }
+    Ok(status)
+  }
+
+  /// The service's display name.  Requires `SERVICE_QUERY_CONFIG` access.
+  pub(crate) fn display_name(&self) -> Result<String, Error> {
+    let buf = self.query_config()?;
+    let cfg = buf.as_ptr() as *const QUERY_SERVICE_CONFIGW;
+    Ok(unsafe { from_wide_ptr((*cfg).lpDisplayName) })
+  }
+
+  /// Change the service's display name.  Requires `SERVICE_CHANGE_CONFIG`
+  /// access.
+  pub(crate) fn set_display_name(&self, name: &str) -> Result<(), Error> {
+    let name = to_wide(name);
+    self.change_config(SERVICE_NO_CHANGE, ptr::null(), name.as_ptr())
+  }
+
+  /// When the service is started.  Requires `SERVICE_QUERY_CONFIG` access.
+  pub(crate) fn start_type(&self) -> Result<StartType, Error> {
+    let buf = self.query_config()?;
+    let cfg = buf.as_ptr() as *const QUERY_SERVICE_CONFIGW;
+    match unsafe { (*cfg).dwStartType } {
+      SERVICE_AUTO_START => {
+        let buf = self.query(|buf, size, needed| unsafe {
+          QueryServiceConfig2W(
+            self.svc,
+            SERVICE_CONFIG_DELAYED_AUTO_START_INFO,
+            buf,
+            size,
+            needed
+          )
+        })?;
+        let info = buf.as_ptr() as *const DelayedAutoStartInfo;
+        if unsafe { (*info).delayed } != 0 {
+          Ok(StartType::Delayed)
+        } else {
+          Ok(StartType::Auto)
+        }
+      }
+      SERVICE_DEMAND_START => Ok(StartType::Manual),
+      other => Err(Error::Service(format!(
+        "Service has an unsupported start type ({})",
+        other
+      )))
+    }
+  }
+
+  /// Change when the service is started.  Requires `SERVICE_CHANGE_CONFIG`
+  /// access.
+  pub(crate) fn set_start_type(&self, st: StartType) -> Result<(), Error> {
+    let dw = match st {
+      StartType::Auto | StartType::Delayed => SERVICE_AUTO_START,
+      StartType::Manual => SERVICE_DEMAND_START
+    };
+    self.change_config(dw, ptr::null(), ptr::null())?;
+    if dw == SERVICE_AUTO_START {
+      self.set_delayed(st == StartType::Delayed)?;
+    }
+    Ok(())
+  }
+
+  /// Set whether an automatically started service is started shortly after
+  /// the computer has started rather than right away.
+  pub(crate) fn set_delayed(&self, delayed: bool) -> Result<(), Error> {
+    let mut info = DelayedAutoStartInfo {
+      delayed: delayed as BOOL
+    };
+    let res = unsafe {
+      ChangeServiceConfig2W(
+        self.svc,
+        SERVICE_CONFIG_DELAYED_AUTO_START_INFO,
+        &mut info as *mut DelayedAutoStartInfo as *mut _
+      )
+    };
+    if res == 0 {
+      return Err(io::Error::last_os_error().into());
+    }
+    Ok(())
+  }
+
+  /// Names of the services the service depends on.  Requires
+  /// `SERVICE_QUERY_CONFIG` access.
+  pub(crate) fn dependencies(&self) -> Result<Vec<String>, Error> {
+    let buf = self.query_config()?;
+    let cfg = buf.as_ptr() as *const QUERY_SERVICE_CONFIGW;
+    let mut deps = Vec::new();
+    let mut p = unsafe { (*cfg).lpDependencies } as *const u16;
+    if p.is_null() {
+      return Ok(deps);
+    }
+    // The dependencies are a list of nul-terminated strings, terminated by
+    // an empty string.
+    loop {
+      let dep = unsafe { from_wide_ptr(p) };
+      if dep.is_empty() {
+        break;
+      }
+      p = unsafe { p.add(dep.encode_utf16().count() + 1) };
+      deps.push(dep);
+    }
+    Ok(deps)
+  }
+
+  /// Replace the services the service depends on.  Requires
+  /// `SERVICE_CHANGE_CONFIG` access.
+  pub(crate) fn set_dependencies(&self, deps: &[String]) -> Result<(), Error> {
+    let mut list: Vec<u16> = Vec::new();
+    for dep in deps {
+      list.extend(to_wide(dep));
+    }
+    list.push(0);
+    self.change_config(SERVICE_NO_CHANGE, list.as_ptr(), ptr::null())
+  }
+
+  /// The service's description.  Requires `SERVICE_QUERY_CONFIG` access.
+  pub(crate) fn description(&self) -> Result<String, Error> {
+    let buf = self.query(|buf, size, needed| unsafe {
+      QueryServiceConfig2W(
+        self.svc,
+        SERVICE_CONFIG_DESCRIPTION,
+        buf,
+        size,
+        needed
+      )
+    })?;
+    let desc = buf.as_ptr() as *const SERVICE_DESCRIPTIONW;
+    Ok(unsafe { from_wide_ptr((*desc).lpDescription) })
+  }
+
+  /// Change the service's description.  Requires `SERVICE_CHANGE_CONFIG`
+  /// access.
+  pub(crate) fn set_description(&self, desc: &str) -> Result<(), Error> {
+    let mut desc = to_wide(desc);
+    let mut info = SERVICE_DESCRIPTIONW {
+      lpDescription: desc.as_mut_ptr()
+    };
+    let res = unsafe {
+      ChangeServiceConfig2W(
+        self.svc,
+        SERVICE_CONFIG_DESCRIPTION,
+        &mut info as *mut SERVICE_DESCRIPTIONW as *mut _
+      )
+    };
+    if res == 0 {
+      return Err(io::Error::last_os_error().into());
+    }
+    Ok(())
+  }
+
+  /// The service's `QUERY_SERVICE_CONFIGW`, in a buffer which also holds
+  /// the strings it points to.
+  fn query_config(&self) -> Result<Vec<u64>, Error> {
+    self.query(|buf, size, needed| unsafe {
+      QueryServiceConfigW(
+        self.svc,
+        buf as *mut QUERY_SERVICE_CONFIGW,
+        size,
+        needed
+      )
+    })
+  }
+
+  /// Call `ChangeServiceConfigW()` with the start type `start_type`, the
+  /// dependencies `deps` and the display name `name`, leaving everything
+  /// else as it is.  `SERVICE_NO_CHANGE` and null pointers leave the
+  /// corresponding setting as it is too.
+  fn change_config(
+    &self,
+    start_type: DWORD,
+    deps: *const u16,
+    name: *const u16
+  ) -> Result<(), Error> {
+    let res = unsafe {
+      ChangeServiceConfigW(
+        self.svc,
+        SERVICE_NO_CHANGE,
+        start_type,
+        SERVICE_NO_CHANGE,
+        ptr::null(),
+        ptr::null(),
+        ptr::null_mut(),
+        deps,
+        ptr::null(),
+        ptr::null(),
+        name
+      )
+    };
+    if res == 0 {
+      return Err(io::Error::last_os_error().into());
+    }
+    Ok(())
+  }
+
+  /// Call one of the `Query*` functions, which take a buffer and its size
+  /// and report the size they need, with a large enough buffer.
+  ///
+  /// The buffer is made of `u64`s so it is suitably aligned for the
+  /// structures returned.
+  fn query<F>(&self, f: F) -> Result<Vec<u64>, Error>
+  where
+    F: Fn(*mut u8, DWORD, &mut DWORD) -> i32
+  {
+    let mut needed: DWORD = 0;
+    if f(ptr::null_mut(), 0, &mut needed) == 0 {
+      let e = io::Error::last_os_error();
+      if e.raw_os_error() != Some(ERROR_INSUFFICIENT_BUFFER as i32) {
+        return Err(e.into());
+      }
+    }
+    let mut buf = vec![0u64; (needed as usize).div_ceil(8)];
+    let size = (buf.len() * 8) as DWORD;
+    if f(buf.as_mut_ptr() as *mut u8, size, &mut needed) == 0 {
+      return Err(io::Error::last_os_error().into());
+    }
+    Ok(buf)
+  }
+}
+
+impl Drop for ServiceHandle {
+  fn drop(&mut self) {
+    unsafe {
+      CloseServiceHandle(self.svc);
+      CloseServiceHandle(self.scm);
+    }
+  }
+}
+
+
+fn to_wide(s: &str) -> Vec<u16> {
+  OsStr::new(s).encode_wide().chain(std::iter::once(0)).collect()
+}
+
+/// Convert a nul-terminated wide string to a `String`.  A null pointer is
+/// treated as an empty string.
+unsafe fn from_wide_ptr(p: *const u16) -> String {
+  if p.is_null() {
+    return String::new();
+  }
+  let mut len = 0;
+  while *p.add(len) != 0 {
+    len += 1;
+  }
+  let s = std::slice::from_raw_parts(p, len);
+  OsString::from_wide(s).to_string_lossy().into_owned()
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
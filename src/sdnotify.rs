@@ -0,0 +1,161 @@
+//! Minimal implementation of the systemd service notification protocol.
+//!
+//! See sd_notify(3) for the details.  Messages are newline-separated
+//! `VARIABLE=value` assignments sent as a single datagram to the unix socket
+//! named by the `NOTIFY_SOCKET` environment variable.
+
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::time::Duration;
+
+use log::trace;
+
+use crate::appstate::AppState;
+use crate::err::Error;
+use crate::exitcode::ExitReason;
+
+/// How much longer systemd should wait for the service to start, counted
+/// from each start checkpoint.  Checkpoints are sent at least every second
+/// while the debug server comes up.
+const START_EXTEND: Duration = Duration::from_secs(10);
+
+/// How much longer systemd should wait for the service to stop, counted from
+/// each stop checkpoint.
+const STOP_EXTEND: Duration = Duration::from_secs(30);
+
+
+pub(crate) struct Notifier {
+  sock: UnixDatagram,
+  addr: SocketAddr
+}
+
+impl Notifier {
+  /// Create a notifier for the socket given in `NOTIFY_SOCKET`.
+  ///
+  /// Returns `Ok(None)` if the environment variable is not set, which is the
+  /// case when not running under systemd (or not as a `Type=notify`
+  /// service).
+  pub(crate) fn from_env() -> Result<Option<Self>, Error> {
+    Notifier::from_socket(std::env::var("NOTIFY_SOCKET").ok().as_deref())
+  }
+
+  /// Create a notifier for the value `path` of `NOTIFY_SOCKET`, if it is
+  /// set.
+  pub(crate) fn from_socket(
+    path: Option<&str>
+  ) -> Result<Option<Self>, Error> {
+    match path {
+      Some(path) => Ok(Some(Notifier::new(path)?)),
+      None => Ok(None)
+    }
+  }
+
+  /// Create a notifier for a socket path.
+  ///
+  /// A leading `@` denotes a socket in the abstract namespace.
+  pub(crate) fn new(path: &str) -> Result<Self, Error> {
+    let addr = match path.strip_prefix('@') {
+      Some(name) => SocketAddr::from_abstract_name(name)?,
+      None => SocketAddr::from_pathname(path)?
+    };
+    let sock = UnixDatagram::unbound()?;
+
+    Ok(Notifier { sock, addr })
+  }
+
+  /// Send a raw notification message.
+  pub(crate) fn notify(&self, msg: &str) -> Result<(), Error> {
+    trace!("sd_notify: {:?}", msg);
+    self.sock.send_to_addr(msg.as_bytes(), &self.addr)?;
+    Ok(())
+  }
+
+  /// Report an application state change.
+  pub(crate) fn report(&self, state: &AppState) -> Result<(), Error> {
+    self.notify(&state_message(state))
+  }
+}
+
+
+/// Translate an application state into a notification message.
+///
+/// Start and stop checkpoints extend systemd's start and stop timeouts, so
+/// a service which is making progress isn't killed for taking its time.
+pub(crate) fn state_message(state: &AppState) -> String {
+  match state {
+    AppState::Starting(checkpoint) => format!(
+      "STATUS=Starting (checkpoint {})\nEXTEND_TIMEOUT_USEC={}",
+      checkpoint,
+      START_EXTEND.as_micros()
+    ),
+    AppState::Started => String::from("READY=1\nSTATUS=Running"),
+    AppState::Stopping(checkpoint) => format!(
+      "STOPPING=1\nSTATUS=Stopping (checkpoint {})\nEXTEND_TIMEOUT_USEC={}",
+      checkpoint,
+      STOP_EXTEND.as_micros()
+    ),
+    AppState::Stopped(ExitReason::Clean) => String::from("STATUS=Stopped"),
+    AppState::Stopped(reason) => format!("STATUS=Stopped ({})", reason)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn notify_socket() {
+    let path = std::env::temp_dir()
+      .join(format!("verboten-notify-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let sock = UnixDatagram::bind(&path).unwrap();
+    sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    assert!(Notifier::from_socket(None).unwrap().is_none());
+    let notifier = Notifier::from_socket(path.to_str()).unwrap().unwrap();
+
+    let states = [
+      AppState::Starting(1),
+      AppState::Started,
+      AppState::Stopping(2),
+      AppState::Stopped(ExitReason::Clean),
+      AppState::Stopped(ExitReason::CrashLoop)
+    ];
+    let expected = [
+      "STATUS=Starting (checkpoint 1)\nEXTEND_TIMEOUT_USEC=10000000",
+      "READY=1\nSTATUS=Running",
+      concat!(
+        "STOPPING=1\nSTATUS=Stopping (checkpoint 2)\n",
+        "EXTEND_TIMEOUT_USEC=30000000"
+      ),
+      "STATUS=Stopped",
+      "STATUS=Stopped (crash loop)"
+    ];
+
+    let mut buf = [0u8; 256];
+    for (state, expected) in states.iter().zip(expected.iter()) {
+      notifier.report(state).unwrap();
+      let n = sock.recv(&mut buf).unwrap();
+      assert_eq!(std::str::from_utf8(&buf[..n]).unwrap(), *expected);
+    }
+
+    let _ = std::fs::remove_file(&path);
+  }
+
+  #[test]
+  fn abstract_socket() {
+    let name = format!("verboten-notify-{}", std::process::id());
+    let addr = SocketAddr::from_abstract_name(&name).unwrap();
+    let sock = UnixDatagram::bind_addr(&addr).unwrap();
+    sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    let notifier = Notifier::new(&format!("@{}", name)).unwrap();
+    notifier.notify("READY=1").unwrap();
+    let mut buf = [0u8; 64];
+    let n = sock.recv(&mut buf).unwrap();
+    assert_eq!(&buf[..n], b"READY=1");
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
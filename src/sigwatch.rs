@@ -0,0 +1,85 @@
+//! Turn termination and reload signals into something the rest of the
+//! application can wait on.
+//!
+//! Signal handlers are very limited in what they are allowed to do, so the
+//! handler only writes the signal number to a pipe.  A watcher thread blocks
+//! on the read end of the pipe and does the actual work.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::io::FromRawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread;
+
+use log::{debug, error};
+
+use crate::err::Error;
+
+static PIPE_WR: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn handler(sig: libc::c_int) {
+  let fd = PIPE_WR.load(Ordering::Relaxed);
+  if fd >= 0 {
+    let b = sig as u8;
+    unsafe {
+      libc::write(fd, &b as *const u8 as *const libc::c_void, 1);
+    }
+  }
+}
+
+
+/// From a watcher thread, call `terminate` (once) when SIGTERM or SIGINT is
+/// received, and `reload` each time SIGHUP is received before that.
+pub(crate) fn watch<T, R>(terminate: T, reload: R) -> Result<(), Error>
+where
+  T: FnOnce() + Send + 'static,
+  R: Fn() + Send + 'static
+{
+  let mut fds: [libc::c_int; 2] = [-1; 2];
+  if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+    return Err(io::Error::last_os_error().into());
+  }
+  let mut rd = unsafe { File::from_raw_fd(fds[0]) };
+  PIPE_WR.store(fds[1], Ordering::Relaxed);
+
+  for sig in &[libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+    let mut sa: libc::sigaction = unsafe { std::mem::zeroed() };
+    sa.sa_sigaction = handler as extern "C" fn(libc::c_int) as usize;
+    sa.sa_flags = libc::SA_RESTART;
+    let res = unsafe {
+      libc::sigemptyset(&mut sa.sa_mask);
+      libc::sigaction(*sig, &sa, std::ptr::null_mut())
+    };
+    if res != 0 {
+      return Err(io::Error::last_os_error().into());
+    }
+  }
+
+  thread::spawn(move || {
+    let mut buf = [0u8; 1];
+    loop {
+      match rd.read(&mut buf) {
+        Ok(1) if libc::c_int::from(buf[0]) == libc::SIGHUP => {
+          debug!("reload signal received");
+          reload();
+        }
+        Ok(1) => break,
+        Ok(_) => {
+          error!("signal pipe unexpectedly closed");
+          return;
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+        Err(e) => {
+          error!("unable to read from signal pipe; {}", e);
+          return;
+        }
+      }
+    }
+    debug!("termination signal received");
+    terminate();
+  });
+
+  Ok(())
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
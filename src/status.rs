@@ -0,0 +1,400 @@
+//! What a service is doing.
+//!
+//! The supervisor records its state in `status.json` in the service's state
+//! directory whenever it changes.  The `status` command combines it with
+//! what the service manager reports: while the service process which wrote
+//! the file is running the file reflects what the supervisor is doing right
+//! now, otherwise it holds the last known state.
+
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::args;
+use crate::config::Config;
+use crate::err::Error;
+use crate::paths;
+use crate::profile;
+use crate::service;
+
+
+/// The state recorded by the supervisor.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Status {
+  /// Process id of the service process, which runs the supervisor.
+  pub(crate) supervisor_pid: Option<u32>,
+
+  /// Process id of the debug server, while it is running.
+  pub(crate) server_pid: Option<u32>,
+
+  /// Port the debug server listens on.
+  pub(crate) port: Option<u16>,
+
+  /// When the current session started and when it ends (RFC 3339).
+  pub(crate) session_start: Option<String>,
+  pub(crate) session_end: Option<String>,
+
+  /// Number of times the debug server has been restarted this session.
+  pub(crate) restarts: u32,
+
+  /// How the debug server last exited.
+  pub(crate) last_exit: Option<String>,
+
+  /// Why the service stopped, once it has.
+  pub(crate) stop_reason: Option<String>
+}
+
+
+/// The supervisor's record of its state, written to disk on every update.
+pub(crate) struct StatusFile {
+  path: PathBuf,
+  status: Status
+}
+
+impl StatusFile {
+  pub(crate) fn new(service_name: &str) -> Self {
+    StatusFile {
+      path: status_path(service_name),
+      status: Status::default()
+    }
+  }
+
+  /// Change the recorded state using `f`, and write it out.
+  ///
+  /// Failing to write it is not fatal; it is only used for reporting.
+  pub(crate) fn update<F>(&mut self, f: F)
+  where
+    F: FnOnce(&mut Status)
+  {
+    f(&mut self.status);
+    if let Err(e) = self.write() {
+      warn!("Unable to write status to {:?}; {}", self.path, e);
+    }
+  }
+
+  /// Write the state to a temporary file which is then renamed, so readers
+  /// never see a partially written file.
+  fn write(&self) -> Result<(), Error> {
+    if let Some(dir) = self.path.parent() {
+      fs::create_dir_all(dir)?;
+    }
+    let buf = serde_json::to_string_pretty(&self.status).map_err(|e| {
+      Error::BadFormat(format!("Unable to serialize status; {}", e))
+    })?;
+    let tmp = self.path.with_extension("json.tmp");
+    fs::write(&tmp, buf)?;
+    fs::rename(&tmp, &self.path)?;
+    Ok(())
+  }
+}
+
+
+/// Format `t` the way times are recorded.
+pub(crate) fn timestamp(t: SystemTime) -> String {
+  humantime::format_rfc3339_seconds(t).to_string()
+}
+
+
+fn status_path(service_name: &str) -> PathBuf {
+  paths::state_root().join(service_name).join("status.json")
+}
+
+
+/// Read the recorded state of the service `service_name`, if there is any.
+fn load(service_name: &str) -> Result<Option<Status>, Error> {
+  let fname = status_path(service_name);
+  let buf = match fs::read_to_string(&fname) {
+    Ok(buf) => buf,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+    Err(e) => {
+      return Err(Error::IO(format!("Unable to read {:?}; {}", fname, e)))
+    }
+  };
+  let status = serde_json::from_str(&buf)
+    .map_err(|e| Error::BadFormat(format!("{:?}: {}", fname, e)))?;
+  Ok(Some(status))
+}
+
+
+/// The status of a service, as printed by the `status` command.
+#[derive(Serialize)]
+struct Report {
+  service: String,
+
+  /// State according to the service manager.
+  state: String,
+
+  /// Where the rest comes from: `supervisor` if the running supervisor
+  /// recorded it, `persisted` if it is what was last recorded and `none`
+  /// if nothing has been recorded.
+  source: &'static str,
+
+  supervisor_pid: Option<u32>,
+  server_pid: Option<u32>,
+  port: Option<u16>,
+  session_start: Option<String>,
+  session_end: Option<String>,
+
+  /// Seconds left of the session, while it is running.
+  time_left: Option<u64>,
+
+  restarts: u32,
+  last_exit: Option<String>,
+  stop_reason: Option<String>
+}
+
+
+/// Print the status of the service `service_name`, as JSON if `json` is
+/// set.
+pub(crate) fn show(service_name: &str, json: bool) -> Result<(), Error> {
+  let store = service::param_store(service_name)?;
+  let (state, main_pid) = service::query_state(service_name)?;
+  let recorded = load(service_name)?;
+
+  let now = SystemTime::now();
+  let report = report(service_name, state, main_pid, recorded, now, || {
+    let cli = args::Context::default();
+    let (cfg, _problems) = Config::check(service_name, &store, &cli);
+    configured_port(&cfg)
+  });
+
+  if json {
+    let buf = serde_json::to_string_pretty(&report).map_err(|e| {
+      Error::BadFormat(format!("Unable to serialize status; {}", e))
+    })?;
+    println!("{}", buf);
+  } else {
+    print_report(&report);
+  }
+
+  Ok(())
+}
+
+
+/// Combine the state `recorded` by the supervisor of the service
+/// `service_name` with the `state` and main process id `main_pid` reported
+/// by the service manager at the time `now`.
+///
+/// `port` is called for the configured port if no port has been recorded.
+fn report<F>(
+  service_name: &str,
+  state: String,
+  main_pid: Option<u32>,
+  recorded: Option<Status>,
+  now: SystemTime,
+  port: F
+) -> Report
+where
+  F: FnOnce() -> Option<u16>
+{
+  // Only trust the recorded process ids if the process which recorded them
+  // is the one the service manager considers to be running the service.
+  let live = match (&recorded, main_pid) {
+    (Some(st), Some(pid)) => {
+      st.supervisor_pid == Some(pid) && st.stop_reason.is_none()
+    }
+    _ => false
+  };
+  let source = match (&recorded, live) {
+    (_, true) => "supervisor",
+    (Some(_), false) => "persisted",
+    (None, _) => "none"
+  };
+  let st = recorded.unwrap_or_default();
+
+  // Without a recorded port, report the configured one.
+  let port = st.port.or_else(port);
+
+  let time_left = match (live, &st.session_end) {
+    (true, Some(end)) => humantime::parse_rfc3339(end)
+      .ok()
+      .map(|end| end.duration_since(now).unwrap_or_default().as_secs()),
+    _ => None
+  };
+
+  Report {
+    service: service_name.to_string(),
+    state,
+    source,
+    supervisor_pid: if live { st.supervisor_pid } else { None },
+    server_pid: if live { st.server_pid } else { None },
+    port,
+    session_start: st.session_start,
+    session_end: st.session_end,
+    time_left,
+    restarts: st.restarts,
+    last_exit: st.last_exit,
+    stop_reason: st.stop_reason
+  }
+}
+
+
+fn configured_port(cfg: &Config) -> Option<u16> {
+  cfg.port.or_else(|| {
+    profile::lookup(&cfg.profile)
+      .ok()
+      .map(|p| p.default_port())
+  })
+}
+
+
+fn print_report(r: &Report) {
+  println!("Service:         {}", r.service);
+  println!("State:           {}", r.state);
+  println!("Supervisor PID:  {}", or_dash(r.supervisor_pid));
+  println!("Debug server:    {}", or_dash(r.server_pid));
+  println!("Port:            {}", or_dash(r.port));
+  println!("Session start:   {}", or_dash(r.session_start.as_ref()));
+  match (&r.session_end, r.time_left) {
+    (Some(end), Some(left)) => println!(
+      "Time left:       {} (until {})",
+      humantime::format_duration(Duration::from_secs(left)),
+      end
+    ),
+    (None, _) if r.source == "supervisor" => {
+      println!("Time left:       no timeout")
+    }
+    _ => println!("Time left:       -")
+  }
+  println!("Restarts:        {}", r.restarts);
+  println!("Last exit:       {}", or_dash(r.last_exit.as_ref()));
+  if let Some(ref reason) = r.stop_reason {
+    println!("Stopped:         {}", reason);
+  }
+  match r.source {
+    "supervisor" => {}
+    "persisted" => {
+      println!("(last recorded state; the supervisor is not running)")
+    }
+    _ => println!("(nothing has been recorded by the supervisor)")
+  }
+}
+
+
+
+fn or_dash<T: fmt::Display>(v: Option<T>) -> String {
+  v.map(|v| v.to_string()).unwrap_or_else(|| String::from("-"))
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SVC: &str = "verboten-unit-test";
+
+  /// What a supervisor with process id 100 records while it is running.
+  fn running() -> Status {
+    Status {
+      supervisor_pid: Some(100),
+      server_pid: Some(101),
+      port: Some(4711),
+      session_start: Some(String::from("2024-01-31T22:00:00Z")),
+      session_end: Some(String::from("2024-01-31T23:00:00Z")),
+      restarts: 2,
+      last_exit: Some(String::from("exited with status 1")),
+      stop_reason: None
+    }
+  }
+
+  fn at(ts: &str) -> SystemTime {
+    humantime::parse_rfc3339(ts).unwrap()
+  }
+
+  fn report_of(main_pid: Option<u32>, recorded: Option<Status>) -> Report {
+    let now = at("2024-01-31T22:30:00Z");
+    let state = String::from("running");
+    report(SVC, state, main_pid, recorded, now, || Some(1234))
+  }
+
+  #[test]
+  fn live_status() {
+    let r = report_of(Some(100), Some(running()));
+    assert_eq!(r.source, "supervisor");
+    assert_eq!(r.supervisor_pid, Some(100));
+    assert_eq!(r.server_pid, Some(101));
+    assert_eq!(r.port, Some(4711));
+    assert_eq!(r.time_left, Some(30 * 60));
+    assert_eq!(r.restarts, 2);
+  }
+
+  #[test]
+  fn persisted_status() {
+    // Another process is running the service, or none is.
+    for main_pid in &[Some(200), None] {
+      let r = report_of(*main_pid, Some(running()));
+      assert_eq!(r.source, "persisted");
+      assert_eq!(r.supervisor_pid, None);
+      assert_eq!(r.server_pid, None);
+      assert_eq!(r.time_left, None);
+      assert_eq!(r.last_exit.as_deref(), Some("exited with status 1"));
+    }
+
+    // The supervisor has stopped, but its process is still around.
+    let st = Status {
+      server_pid: None,
+      stop_reason: Some(String::from("clean")),
+      ..running()
+    };
+    assert_eq!(report_of(Some(100), Some(st)).source, "persisted");
+  }
+
+  #[test]
+  fn nothing_recorded() {
+    let r = report_of(Some(100), None);
+    assert_eq!(r.source, "none");
+    assert_eq!(r.supervisor_pid, None);
+    assert_eq!(r.port, Some(1234));
+    assert_eq!(r.restarts, 0);
+  }
+
+  #[test]
+  fn json_source() {
+    let cases = [
+      (Some(running()), "supervisor"),
+      (Some(Status::default()), "persisted"),
+      (None, "none")
+    ];
+    for (recorded, source) in cases.iter().cloned() {
+      let r = report_of(Some(100), recorded);
+      let v = serde_json::to_value(&r).unwrap();
+      assert_eq!(v["source"], source);
+      assert_eq!(v["service"], SVC);
+    }
+  }
+
+  #[test]
+  fn status_file_round_trip() {
+    let name = format!("{}-status-{}", SVC, std::process::id());
+    let mut file = StatusFile::new(&name);
+    file.update(|st| *st = running());
+    file.update(|st| {
+      st.server_pid = None;
+      st.stop_reason = Some(String::from("clean"));
+    });
+
+    let st = load(&name).unwrap().unwrap();
+    assert_eq!(st.supervisor_pid, Some(100));
+    assert_eq!(st.server_pid, None);
+    assert_eq!(st.session_end, running().session_end);
+    assert_eq!(st.stop_reason.as_deref(), Some("clean"));
+
+    // Only the renamed file is left behind.
+    let dir = status_path(&name).parent().unwrap().to_path_buf();
+    let names: Vec<_> = fs::read_dir(&dir)
+      .unwrap()
+      .map(|de| de.unwrap().file_name())
+      .collect();
+    assert_eq!(names, ["status.json"]);
+    let _ = fs::remove_dir_all(&dir);
+
+    assert!(load(&name).unwrap().is_none());
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,59 @@
+//! Minimal logger which writes log records to stderr.
+//!
+//! When running under a service manager (such as systemd) stderr is
+//! typically captured into the system log.  If stderr is connected to the
+//! journal each record is prefixed with its syslog priority (see
+//! sd-daemon(3)) so the journal can tell the levels apart.
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::err::Error;
+
+struct StderrLogger;
+
+static LOGGER: StderrLogger = StderrLogger;
+
+static JOURNAL: AtomicBool = AtomicBool::new(false);
+
+impl log::Log for StderrLogger {
+  fn enabled(&self, metadata: &log::Metadata) -> bool {
+    metadata.level() <= log::max_level()
+  }
+
+  fn log(&self, record: &log::Record) {
+    if !self.enabled(record.metadata()) {
+      return;
+    }
+    if JOURNAL.load(Ordering::Relaxed) {
+      let prio = match record.level() {
+        log::Level::Error => 3,
+        log::Level::Warn => 4,
+        log::Level::Info => 6,
+        log::Level::Debug | log::Level::Trace => 7
+      };
+      eprintln!("<{}>{}", prio, record.args());
+    } else {
+      eprintln!("{}: {}", record.level(), record.args());
+    }
+  }
+
+  fn flush(&self) {
+    let _ = io::stderr().flush();
+  }
+}
+
+
+/// Install the stderr logger and set the maximum log level.
+pub fn init(lf: log::LevelFilter) -> Result<(), Error> {
+  // systemd sets JOURNAL_STREAM when stdout/stderr are connected to the
+  // journal.
+  let journal = std::env::var_os("JOURNAL_STREAM").is_some();
+  JOURNAL.store(journal, Ordering::Relaxed);
+
+  log::set_logger(&LOGGER).map_err(|e| Error::IO(e.to_string()))?;
+  log::set_max_level(lf);
+  Ok(())
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,1303 @@
+//! Platform-neutral debug server supervision.
+//!
+//! The service frontends (Windows SCM, Linux) are responsible for loading the
+//! service configuration, delivering stop requests and reporting the
+//! application state to their respective service managers.  Everything in
+//! between lives here.
+
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use log::{debug, error, info, trace, warn};
+
+use crate::appstate::{state_channel, AppState, AppStateSender};
+use crate::child::ServerProcess;
+use crate::config::{self, Config};
+use crate::err::Error;
+use crate::event::{Clock, Event, EventReceiver, EventSender, Wait};
+use crate::exitcode::ExitReason;
+use crate::health::{HealthConfig, HealthTracker};
+use crate::probe;
+use crate::profile::{self, DebugServer, Readiness, Settings};
+use crate::restart::{Decision, RestartConfig, Restarter};
+use crate::status::{self, StatusFile};
+use crate::transcript::{self, Transcript, TranscriptConfig};
+
+/// How often the readiness probe tries to connect to the debug server.
+const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long a single readiness probe connection attempt may take.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// How long to wait for the last output of the debug server to be forwarded
+/// after it has terminated.
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often start and stop checkpoints are reported while waiting.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(1);
+
+pub(crate) struct ServerCtx {
+  pub(crate) service_name: String,
+
+  /// The configuration the context was built from.
+  pub(crate) config: Config,
+
+  pub(crate) exec: PathBuf,
+  pub(crate) profile: Box<dyn DebugServer>,
+  pub(crate) settings: Settings,
+  pub(crate) restart: RestartConfig,
+  pub(crate) health: HealthConfig,
+  pub(crate) transcripts: TranscriptConfig,
+
+  /// How long the debug server is given to terminate after it has been
+  /// asked to before it is killed.
+  pub(crate) stop_grace: Duration,
+
+  /// How long the debug server is given to become ready before the start is
+  /// considered to have failed.
+  pub(crate) ready_timeout: Duration
+}
+
+impl ServerCtx {
+  /// Build a context for the service `service_name` from its
+  /// configuration.
+  pub(crate) fn new(service_name: &str, cfg: &Config) -> Result<Self, Error> {
+    let profile = profile::lookup(&cfg.profile)?;
+
+    let exec = match (&cfg.exec, profile.exec()) {
+      (Some(exec), _) => exec.clone(),
+      (None, Some(exec)) => exec.to_path_buf(),
+      (None, None) => {
+        return Err(Error::BadInput(String::from(
+          "Unable to get Exec parameter."
+        )));
+      }
+    };
+
+    let port = cfg.port.unwrap_or_else(|| profile.default_port());
+
+    Ok(ServerCtx {
+      service_name: service_name.to_string(),
+      config: cfg.clone(),
+      exec,
+      profile,
+      settings: Settings {
+        port,
+        timeout: cfg.timeout,
+        auth: cfg.auth
+      },
+      restart: RestartConfig::from_config(cfg),
+      health: HealthConfig::from_config(cfg),
+      transcripts: TranscriptConfig::from_config(service_name, cfg),
+      stop_grace: cfg.stop_grace,
+      ready_timeout: cfg.ready_timeout
+    })
+  }
+}
+
+
+/// Run the debug server in a worker thread and pass each application state
+/// change to `report` until the worker reports that it has stopped.
+///
+/// All of the supervisor's timing is measured by, and waited for using,
+/// `clock`.  `ev_tx` and `ev_rx` are the two ends of the supervisor's event
+/// channel.  The frontend is expected to hold on to a clone of the sender in
+/// order to deliver stop requests.
+///
+/// Returns the reason the service stopped.
+pub(crate) fn supervise<C, F>(
+  ctx: ServerCtx,
+  clock: C,
+  ev_tx: EventSender,
+  ev_rx: EventReceiver,
+  mut report: F
+) -> ExitReason
+where
+  C: Clock + Send + 'static,
+  F: FnMut(&AppState)
+{
+  // Create channel for reporting the application state to the application
+  // state monitoring loop below.
+  let (app_state_tx, app_state_rx) = state_channel();
+
+  trace!("launching thread for spawning {}", ctx.profile.name());
+  let thrd = thread::spawn(move || {
+    trace!("debug server worker thread reporting in");
+
+    app_state_tx.starting(Some(1));
+
+    let session_start = clock.now();
+    let session_started_at = SystemTime::now();
+
+    let mut status = StatusFile::new(&ctx.service_name);
+    status.update(|st| {
+      st.supervisor_pid = Some(std::process::id());
+      st.port = Some(ctx.settings.port);
+      st.session_start = Some(status::timestamp(session_started_at));
+      st.session_end = ctx
+        .settings
+        .timeout
+        .map(|t| status::timestamp(session_started_at + t));
+    });
+
+    let mut sup = Supervisor {
+      session_end: ctx.settings.timeout.map(|t| session_start + t),
+      restarter: Restarter::new(ctx.restart.clone()),
+      ctx,
+      pending: None,
+      state_tx: &app_state_tx,
+      ev_tx,
+      events: ev_rx,
+      clock: &clock,
+      session_start,
+      session_started_at,
+      status,
+      started: false,
+      checkpoint: 2
+    };
+
+    let reason = match sup.run() {
+      Ok(reason) => {
+        debug!("Supervisor terminated successfully ({})", reason);
+        reason
+      }
+      Err(e) => {
+        error!("Supervisor terminated with an error; {}", e);
+        ExitReason::from_error(&e)
+      }
+    };
+
+    sup.status.update(|st| {
+      st.server_pid = None;
+      st.stop_reason = Some(reason.to_string());
+    });
+    app_state_tx.stopped(reason);
+
+    trace!("debug server worker thread reporting out");
+
+    reason
+  });
+
+  //
+  // Enter a loop that waits for application to report back its status.
+  // Terminate the loop once application reports that it has stopped.
+  //
+  loop {
+    trace!("waiting for app state event");
+    let state = app_state_rx.recv();
+    report(&state);
+    if let AppState::Stopped(_) = state {
+      break;
+    }
+  }
+
+  trace!("waiting for worker thread to croak");
+  match thrd.join() {
+    Ok(ExitReason::Clean) => {
+      trace!("worker thread has croaked happy");
+      ExitReason::Clean
+    }
+    Ok(reason) => {
+      trace!("worker thread has croaked sad");
+      reason
+    }
+    Err(_) => {
+      trace!("worker thread has croaked in a panic");
+      ExitReason::Failure
+    }
+  }
+}
+
+
+/// How a single run of the debug server ended.
+enum Outcome {
+  /// The supervisor was asked to stop, or the session timeout expired.  The
+  /// debug server is still running.
+  Stop,
+
+  /// The debug server exited on its own.
+  Exited(ExitStatus),
+
+  /// The debug server did not become ready in time.  It is still running.
+  NotReady,
+
+  /// The debug server failed too many consecutive health checks.  It is
+  /// still running.
+  Unhealthy
+}
+
+
+struct Supervisor<'a, C: Clock> {
+  ctx: ServerCtx,
+
+  /// Context built from a reloaded configuration, to be used the next time
+  /// the debug server is started.
+  pending: Option<ServerCtx>,
+
+  state_tx: &'a AppStateSender,
+
+  /// Handed to child exit waiters.
+  ev_tx: EventSender,
+  events: EventReceiver,
+  clock: &'a C,
+
+  /// When the service started, and when its session ends.
+  session_start: Instant,
+  session_end: Option<Instant>,
+
+  /// Wall clock time the service started, for reporting.
+  session_started_at: SystemTime,
+
+  /// State recorded for the `status` command.
+  status: StatusFile,
+
+  restarter: Restarter,
+
+  /// Set once the service has been reported as started.
+  started: bool,
+
+  /// Next start checkpoint to report.
+  checkpoint: u32
+}
+
+impl<'a, C: Clock> Supervisor<'a, C> {
+  fn name(&self) -> &str {
+    self.ctx.profile.name()
+  }
+
+  /// Run the debug server, restarting it according to the restart policy,
+  /// until the service should stop.  Returns the reason it stopped.
+  fn run(&mut self) -> Result<ExitReason, Error> {
+    loop {
+      if let Some(ctx) = self.pending.take() {
+        self.ctx = ctx;
+      }
+
+      // On restarts only the remaining part of the session is handed to the
+      // debug server.
+      let mut settings = self.ctx.settings.clone();
+      if let Some(end) = self.session_end {
+        settings.timeout =
+          Some(end.saturating_duration_since(self.clock.now()));
+      }
+      let eargs = self.ctx.profile.args(&settings);
+
+      if !self.started {
+        self.state_tx.starting(Some(self.checkpoint));
+        self.checkpoint += 1;
+      }
+
+      debug!("Running: {:?} {:?}", self.ctx.exec, eargs);
+      let spawned_at = self.clock.now();
+      let mut child = ServerProcess::spawn(
+        Command::new(&self.ctx.exec)
+          .args(&eargs)
+          .envs(self.ctx.profile.env())
+          .stdin(Stdio::null())
+          .stdout(Stdio::piped())
+          .stderr(Stdio::piped())
+      )?;
+      let (pid, port) = (child.id(), self.ctx.settings.port);
+      self.status.update(|st| {
+        st.server_pid = Some(pid);
+        st.port = Some(port);
+      });
+      let transcript = self.open_transcript(child.id());
+      child.forward_output(self.name(), transcript)?;
+      child.notify_exit(self.ev_tx.clone())?;
+
+      let status = match self.watch(&mut child) {
+        Outcome::Stop => {
+          self.shutdown(&mut child);
+          return Ok(ExitReason::Clean);
+        }
+        Outcome::Exited(status) => Some(status),
+        Outcome::Unhealthy => {
+          warn!(
+            "{} failed {} consecutive health checks -- considering it hung",
+            self.name(),
+            self.ctx.health.max_failures
+          );
+          self.shutdown(&mut child);
+          None
+        }
+        Outcome::NotReady => {
+          error!(
+            "{} did not accept connections on port {} within {}",
+            self.name(),
+            self.ctx.settings.port,
+            humantime::format_duration(self.ctx.ready_timeout)
+          );
+          self.shutdown(&mut child);
+          return Err(Error::Startup(format!(
+            "{} did not become ready",
+            self.name()
+          )));
+        }
+      };
+
+      // Don't leave anything the debug server spawned behind; it may be
+      // holding on to the port.
+      reap_tree(self.clock, self.name(), &mut child);
+
+      // An unresponsive debug server counts as a failed one.
+      let success = status.map(|st| st.success()).unwrap_or(false);
+      let ended = match status {
+        Some(st) => format!("exited with status {}", st),
+        None => String::from("was unresponsive")
+      };
+      self.status.update(|st| {
+        st.server_pid = None;
+        st.last_exit = Some(ended.clone());
+      });
+
+      let now = self.clock.now();
+      if let Some(end) = self.session_end {
+        if now >= end {
+          info!("{} {} at the end of the session", self.name(), ended);
+          self.state_tx.stopping(Some(0));
+          return Ok(ExitReason::Clean);
+        }
+      }
+
+      let uptime = now.duration_since(spawned_at);
+      match self.restarter.on_exit(success, uptime, now) {
+        Decision::Stop => {
+          match status {
+            Some(st) => info!(
+              "Apparently {} self-croaked with status {}",
+              self.name(),
+              st
+            ),
+            None => info!("{} was unresponsive; stopping", self.name())
+          }
+          self.state_tx.stopping(Some(0));
+          return Ok(match status {
+            Some(st) => ExitReason::from_status(&st),
+            None => ExitReason::Unresponsive
+          });
+        }
+        Decision::CrashLoop => {
+          error!(
+            "{} {} after {} restarts within the restart window -- giving up",
+            self.name(),
+            ended,
+            self.restarter.restarts()
+          );
+          self.state_tx.stopping(Some(0));
+          return Err(Error::CrashLoop(format!(
+            "{} restarted too many times",
+            self.name()
+          )));
+        }
+        Decision::Restart(delay) => {
+          self.status.update(|st| st.restarts += 1);
+          warn!(
+            "{} {}; restarting in {}",
+            self.name(),
+            ended,
+            humantime::format_duration(delay)
+          );
+          if self.wait_stop(now + delay) {
+            self.state_tx.stopping(Some(0));
+            return Ok(ExitReason::Clean);
+          }
+        }
+      }
+    }
+  }
+
+  /// Watch a running debug server until it exits or the supervisor is asked
+  /// to stop.
+  fn watch(&mut self, child: &mut ServerProcess) -> Outcome {
+    // Report back to the service monitoring loop that we consider outselves
+    // to be "started" -- either right away or once the profile's readiness
+    // condition has been met.  While waiting for the latter, a start
+    // checkpoint is reported every second.
+    let mut ready_at = None;
+    let mut probe_at = None;
+    let mut ready_deadline = None;
+    let mut next_checkpoint = None;
+
+    // Health checks run in a thread of their own, so a hung debug server
+    // can't stall the supervisor.  Only one check is in flight at any time;
+    // the next one is scheduled once its result has arrived.
+    let mut tracker = HealthTracker::new(&self.ctx.health);
+    let mut next_health = None;
+    let mut health_pending = false;
+    if !self.started {
+      let now = self.clock.now();
+      match self.ctx.profile.readiness() {
+        Readiness::Spawned => {
+          self.state_tx.started();
+          self.started = true;
+        }
+        Readiness::Delay(d) => {
+          ready_at = Some(now + d);
+          next_checkpoint = Some(now + CHECKPOINT_INTERVAL);
+        }
+        Readiness::Listening => {
+          probe_at = Some(now);
+          ready_deadline = Some(now + self.ctx.ready_timeout);
+          next_checkpoint = Some(now + CHECKPOINT_INTERVAL);
+        }
+      }
+    }
+
+    loop {
+      let now = self.clock.now();
+
+      // Debug servers which can't time out on their own get their session
+      // deadline enforced here.
+      let deadline = if self.ctx.profile.handles_timeout() {
+        None
+      } else {
+        self.session_end
+      };
+
+      if let Some(t) = ready_at {
+        if now >= t {
+          self.state_tx.started();
+          self.started = true;
+          ready_at = None;
+          next_checkpoint = None;
+        }
+      }
+      if let Some(t) = probe_at {
+        if now >= t {
+          let port = self.ctx.settings.port;
+          match probe::tcp(port, PROBE_TIMEOUT) {
+            Ok(_) => {
+              debug!(
+                "{} is accepting connections on port {}",
+                self.name(),
+                port
+              );
+              self.state_tx.started();
+              self.started = true;
+              probe_at = None;
+              ready_deadline = None;
+              next_checkpoint = None;
+            }
+            Err(e) => {
+              trace!("readiness probe of port {} failed; {}", port, e);
+              probe_at = Some(self.clock.now() + PROBE_INTERVAL);
+            }
+          }
+        }
+      }
+      if let Some(t) = ready_deadline {
+        if self.clock.now() >= t {
+          return Outcome::NotReady;
+        }
+      }
+      if self.started && !health_pending {
+        match (next_health, self.ctx.health.interval) {
+          (None, Some(iv)) => next_health = Some(now + iv),
+          (Some(t), _) if now >= t => {
+            self.check_health(child.id());
+            health_pending = true;
+            next_health = None;
+          }
+          _ => {}
+        }
+      }
+      if let Some(t) = next_checkpoint {
+        if now >= t {
+          self.state_tx.starting(Some(self.checkpoint));
+          self.checkpoint += 1;
+          next_checkpoint = Some(t + CHECKPOINT_INTERVAL);
+        }
+      }
+      if let Some(t) = deadline {
+        if now >= t {
+          info!("{} session timeout expired", self.name());
+          return Outcome::Stop;
+        }
+      }
+
+      let next = [
+        ready_at,
+        probe_at,
+        ready_deadline,
+        next_checkpoint,
+        next_health,
+        deadline
+      ]
+      .iter()
+      .flatten()
+      .min()
+      .copied();
+
+      match self.events.wait(self.clock, next) {
+        Wait::Event(Event::Stop) => {
+          debug!("kill switch activated");
+          return Outcome::Stop;
+        }
+        Wait::Event(Event::ChildExited(pid)) if pid == child.id() => {
+          match child.try_wait() {
+            Ok(Some(status)) => return Outcome::Exited(status),
+            Ok(None) => {
+              trace!("{} reported as exited, but isn't", self.name());
+            }
+            Err(e) => {
+              warn!("error during try_wait(): {}", e);
+              return Outcome::Stop;
+            }
+          }
+        }
+        Wait::Event(Event::ChildExited(pid)) => {
+          trace!("ignoring exit of stale process {}", pid);
+        }
+        Wait::Event(Event::Health(pid, res)) if pid == child.id() => {
+          health_pending = false;
+          let unhealthy = match res {
+            Ok(_) => {
+              debug!("health check of {} succeeded", self.name());
+              tracker.record(true)
+            }
+            Err(e) => {
+              let unhealthy = tracker.record(false);
+              debug!(
+                "health check of {} failed ({} of {}); {}",
+                self.name(),
+                tracker.failures(),
+                self.ctx.health.max_failures,
+                e
+              );
+              unhealthy
+            }
+          };
+          if unhealthy {
+            return Outcome::Unhealthy;
+          }
+        }
+        Wait::Event(Event::Health(pid, _)) => {
+          trace!("ignoring health check result of stale process {}", pid);
+        }
+        Wait::Event(Event::Reload(cfg)) => {
+          self.reload(*cfg);
+          // Start over with the (possibly) new health check settings.
+          tracker = HealthTracker::new(&self.ctx.health);
+          if !health_pending {
+            next_health = None;
+          }
+        }
+        Wait::Deadline => {}
+        Wait::Disconnected => {
+          error!("Event channel disconnected");
+          return Outcome::Stop;
+        }
+      }
+    }
+  }
+
+  /// Apply a reloaded configuration.
+  ///
+  /// The log level, the session timeout and the settings which only concern
+  /// the supervisor take effect right away.  The rest take effect the next
+  /// time the debug server is started.
+  fn reload(&mut self, cfg: Config) {
+    let latest = match self.pending {
+      Some(ref ctx) => &ctx.config,
+      None => &self.ctx.config
+    };
+    let changed: Vec<&str> = config::KEYS
+      .iter()
+      .copied()
+      .filter(|key| latest.get(key) != cfg.get(key))
+      .collect();
+    if changed.is_empty() {
+      info!("Configuration reloaded; nothing has changed");
+      return;
+    }
+
+    let ctx = match ServerCtx::new(&self.ctx.service_name, &cfg) {
+      Ok(ctx) => ctx,
+      Err(e) => {
+        error!("Ignoring reloaded configuration; {}", e);
+        return;
+      }
+    };
+
+    self.session_end = cfg.timeout.map(|t| self.session_start + t);
+    let started_at = self.session_started_at;
+    self.status.update(|st| {
+      st.session_end = cfg.timeout.map(|t| status::timestamp(started_at + t));
+    });
+    self.restarter.set_config(ctx.restart.clone());
+    self.ctx.restart = ctx.restart.clone();
+    self.ctx.health = ctx.health.clone();
+    self.ctx.stop_grace = ctx.stop_grace;
+    self.ctx.ready_timeout = ctx.ready_timeout;
+
+    for key in changed {
+      let value = cfg.get(key).unwrap_or_else(|| String::from("unset"));
+      match key {
+        "Profile" | "Exec" | "Port" | "Auth" | "TranscriptDir"
+        | "TranscriptKeep" | "TranscriptMaxAge" => warn!(
+          "{} changed to {}; takes effect when {} is restarted",
+          key,
+          value,
+          self.name()
+        ),
+        "Timeout" if self.ctx.profile.handles_timeout() => warn!(
+          "Timeout changed to {}; {} keeps its current timeout until it is \
+           restarted",
+          value,
+          self.name()
+        ),
+        _ => info!("{} changed to {}", key, value)
+      }
+    }
+
+    // Changed last, so the changes are logged at the previous level.
+    log::set_max_level(log::LevelFilter::from(&cfg.log_level));
+
+    self.pending = Some(ctx);
+  }
+
+  /// Create a transcript for the debug server with process id `pid`, and
+  /// prune old ones.
+  ///
+  /// Failing to do so is not fatal; the debug server just runs without a
+  /// transcript.
+  fn open_transcript(&self, pid: u32) -> Option<Arc<Transcript>> {
+    let cfg = &self.ctx.transcripts;
+    if cfg.keep == 0 {
+      return None;
+    }
+    let t = match Transcript::create(cfg, pid) {
+      Ok(t) => t,
+      Err(e) => {
+        warn!("Unable to create transcript in {:?}; {}", cfg.dir, e);
+        return None;
+      }
+    };
+    info!("Writing {} output to {:?}", self.name(), t.path());
+    if let Err(e) = transcript::prune(cfg) {
+      warn!("Unable to prune transcripts in {:?}; {}", cfg.dir, e);
+    }
+    Some(Arc::new(t))
+  }
+
+  /// Run a health check of the debug server with process id `pid` in a
+  /// thread of its own.  The result is delivered as an `Event::Health`.
+  fn check_health(&self, pid: u32) {
+    let port = self.ctx.settings.port;
+    let timeout = self.ctx.health.timeout;
+    let handshake = self.ctx.profile.handshake();
+    let tx = self.ev_tx.clone();
+    let res = thread::Builder::new()
+      .name(String::from("health-check"))
+      .spawn(move || {
+        let res = probe::check(port, timeout, handshake.as_ref())
+          .map_err(|e| e.to_string());
+        tx.send(Event::Health(pid, res));
+      });
+    if let Err(e) = res {
+      // Treat it like a failed check rather than skipping it, so the
+      // supervisor doesn't wait for a result which never arrives.
+      self.ev_tx.send(Event::Health(
+        pid,
+        Err(format!("unable to start health check; {}", e))
+      ));
+    }
+  }
+
+  /// Wait until `until` unless a stop request arrives first.
+  ///
+  /// Returns `true` if a stop was requested.
+  fn wait_stop(&mut self, until: Instant) -> bool {
+    loop {
+      match self.events.wait(self.clock, Some(until)) {
+        Wait::Event(Event::Stop) => {
+          debug!("kill switch activated");
+          return true;
+        }
+        Wait::Event(Event::Reload(cfg)) => self.reload(*cfg),
+        Wait::Event(_) => {}
+        Wait::Deadline => return false,
+        Wait::Disconnected => {
+          error!("Event channel disconnected");
+          return true;
+        }
+      }
+    }
+  }
+
+  /// Shut down the debug server.
+  ///
+  /// The debug server is first asked to terminate.  If it hasn't done so
+  /// within the grace period its whole process tree is forcefully killed.
+  /// Increasing stop checkpoints are reported while waiting.
+  fn shutdown(&self, child: &mut ServerProcess) {
+    let name = self.name();
+    let grace = self.ctx.stop_grace;
+    let mut checkpoint = 1;
+    self.state_tx.stopping(Some(checkpoint));
+
+    info!("asking {} to terminate", name);
+    let asked = match child.request_stop() {
+      Ok(_) => true,
+      Err(e) => {
+        warn!("unable to ask {} to terminate; {}", name, e);
+        false
+      }
+    };
+
+    if asked {
+      let until = self.clock.now() + grace;
+      let mut next_checkpoint = self.clock.now() + CHECKPOINT_INTERVAL;
+      loop {
+        match child.try_wait() {
+          Ok(Some(status)) => {
+            info!("{} terminated gracefully with status {}", name, status);
+            reap_tree(self.clock, name, child);
+            return;
+          }
+          Ok(None) => {}
+          Err(e) => {
+            warn!("error during try_wait(): {}", e);
+            break;
+          }
+        }
+        let now = self.clock.now();
+        if now >= until {
+          warn!(
+            "{} did not terminate within {}",
+            name,
+            humantime::format_duration(grace)
+          );
+          break;
+        }
+        if now >= next_checkpoint {
+          checkpoint += 1;
+          self.state_tx.stopping(Some(checkpoint));
+          next_checkpoint += CHECKPOINT_INTERVAL;
+        }
+
+        // Further stop requests are of no interest at this point; just wait
+        // for the exit notification or the next timer.
+        match self
+          .events
+          .wait(self.clock, Some(std::cmp::min(until, next_checkpoint)))
+        {
+          Wait::Disconnected => {
+            error!("Event channel disconnected");
+            break;
+          }
+          Wait::Event(_) | Wait::Deadline => {}
+        }
+      }
+    }
+
+    checkpoint += 1;
+    self.state_tx.stopping(Some(checkpoint));
+
+    info!("killing {}", name);
+    match child.kill() {
+      Ok(_) => {
+        debug!("{} process killed successfully.", name);
+      }
+      Err(e) => {
+        error!("unable to kill {}; {}", name, e);
+        return;
+      }
+    }
+    match child.wait() {
+      Ok(status) => debug!("{} terminated with status {}", name, status),
+      Err(e) => {
+        warn!("unable to wait for {}; {}", name, e);
+        return;
+      }
+    }
+    reap_tree(self.clock, name, child);
+  }
+}
+
+
+/// Make sure no process of the debug server's process tree is left once the
+/// debug server itself has terminated, and give its output a moment to be
+/// forwarded.
+fn reap_tree(clock: &dyn Clock, name: &str, child: &mut ServerProcess) {
+  kill_leftovers(clock, name, child);
+  if !child.drain_output(clock, OUTPUT_DRAIN_TIMEOUT) {
+    debug!("output of {} is still open", name);
+  }
+}
+
+fn kill_leftovers(clock: &dyn Clock, name: &str, child: &mut ServerProcess) {
+  if !child.is_tree_alive() {
+    trace!("no processes of {} left", name);
+    return;
+  }
+
+  warn!("{} left processes behind -- killing them", name);
+  if let Err(e) = child.kill() {
+    error!("unable to kill processes left by {}; {}", name, e);
+    return;
+  }
+
+  let until = clock.now() + Duration::from_secs(2);
+  while child.is_tree_alive() {
+    if clock.now() >= until {
+      error!("processes left by {} survived being killed", name);
+      return;
+    }
+    clock.sleep(Duration::from_millis(50));
+  }
+  debug!("all processes of {} are gone", name);
+}
+
+
+#[cfg(all(test, unix))]
+mod tests {
+  use super::*;
+  use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError};
+  use std::sync::Mutex;
+
+  use crate::config::Source;
+  use crate::event::{self, SystemClock};
+
+  /// A service which has no configuration file.
+  const SVC: &str = "verboten-unit-test";
+
+  /// Clock which only moves when the supervisor waits for something.  When
+  /// no event is pending it jumps straight to the deadline.
+  #[derive(Clone)]
+  struct FakeClock(Arc<Mutex<FakeTime>>);
+
+  struct FakeTime {
+    now: Instant,
+
+    /// How far the clock has jumped for each event wait which reached its
+    /// deadline.
+    waits: Vec<Duration>
+  }
+
+  impl FakeClock {
+    fn new() -> Self {
+      FakeClock(Arc::new(Mutex::new(FakeTime {
+        now: Instant::now(),
+        waits: Vec::new()
+      })))
+    }
+
+    fn waits(&self) -> Vec<Duration> {
+      self.0.lock().unwrap().waits.clone()
+    }
+  }
+
+  impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+      self.0.lock().unwrap().now
+    }
+
+    /// Real processes are involved, so let a little real time pass too.
+    fn sleep(&self, d: Duration) {
+      self.0.lock().unwrap().now += d;
+      thread::sleep(Duration::from_millis(1));
+    }
+
+    fn recv_until(
+      &self,
+      rx: &Receiver<Event>,
+      deadline: Instant
+    ) -> Result<Event, RecvTimeoutError> {
+      match rx.try_recv() {
+        Ok(ev) => Ok(ev),
+        Err(TryRecvError::Disconnected) => Err(RecvTimeoutError::Disconnected),
+        Err(TryRecvError::Empty) => {
+          let mut t = self.0.lock().unwrap();
+          let d = deadline.saturating_duration_since(t.now);
+          t.waits.push(d);
+          t.now += d;
+          Err(RecvTimeoutError::Timeout)
+        }
+      }
+    }
+  }
+
+  /// Debug server running a shell script, which leaves the session timeout
+  /// to the supervisor.
+  struct Script {
+    script: &'static str,
+    readiness: Readiness
+  }
+
+  impl DebugServer for Script {
+    fn name(&self) -> &str {
+      "script"
+    }
+
+    fn default_port(&self) -> u16 {
+      1
+    }
+
+    fn args(&self, _settings: &Settings) -> Vec<String> {
+      vec![String::from("-c"), self.script.to_string()]
+    }
+
+    fn readiness(&self) -> Readiness {
+      self.readiness.clone()
+    }
+  }
+
+  /// Supervise `script`, which is ready once spawned, with the service
+  /// parameters `params` using `clock`.
+  ///
+  /// Once the clock has jumped `stop_after` times, the supervisor is asked
+  /// to stop.
+  fn supervise_script(
+    script: &'static str,
+    params: &[(&str, &str)],
+    clock: &FakeClock,
+    stop_after: Option<usize>
+  ) -> ExitReason {
+    let profile = Script {
+      script,
+      readiness: Readiness::Spawned
+    };
+    supervise_profile(profile, params, clock, stop_after)
+  }
+
+  /// Supervise the debug server `profile` like `supervise_script()` does.
+  fn supervise_profile(
+    profile: Script,
+    params: &[(&str, &str)],
+    clock: &FakeClock,
+    stop_after: Option<usize>
+  ) -> ExitReason {
+    let mut ctx = ServerCtx::new(SVC, &config(params)).unwrap();
+    ctx.profile = Box::new(profile);
+    let stop_when = stop_after.map(|n| {
+      let clock = clock.clone();
+      Box::new(move || clock.waits().len() >= n) as StopWhen
+    });
+    supervise_ctx(ctx, clock, stop_when, Vec::new())
+  }
+
+  /// The configuration with the service parameters `params`.
+  fn config(params: &[(&str, &str)]) -> Config {
+    let mut cfg = Config::default();
+    cfg.set("Exec", "/bin/sh", Source::Params).unwrap();
+    cfg.set("Timeout", "none", Source::Params).unwrap();
+    cfg.set("TranscriptKeep", "0", Source::Params).unwrap();
+    for (k, v) in params {
+      cfg.set(k, v, Source::Params).unwrap();
+    }
+    cfg
+  }
+
+  /// Condition upon which the supervisor is asked to stop.
+  type StopWhen = Box<dyn Fn() -> bool + Send>;
+
+  /// Supervise the debug server in `ctx` using `clock`.
+  ///
+  /// The configurations in `reloads` are waiting for the supervisor when it
+  /// starts.  Once `stop_when` returns true, or 10 seconds have passed, the
+  /// supervisor is asked to stop.
+  fn supervise_ctx(
+    ctx: ServerCtx,
+    clock: &FakeClock,
+    stop_when: Option<StopWhen>,
+    reloads: Vec<Config>
+  ) -> ExitReason {
+    let (ev_tx, ev_rx) = event::event_channel();
+    for cfg in reloads {
+      ev_tx.reload(cfg);
+    }
+    if let Some(stop_when) = stop_when {
+      let ev_tx = ev_tx.clone();
+      thread::spawn(move || {
+        let until = Instant::now() + Duration::from_secs(10);
+        while !stop_when() && Instant::now() < until {
+          thread::sleep(Duration::from_millis(1));
+        }
+        ev_tx.stop();
+      });
+    }
+    let reason = supervise(ctx, clock.clone(), ev_tx, ev_rx, |_| {});
+    let _ = std::fs::remove_dir_all(crate::paths::state_root().join(SVC));
+    reason
+  }
+
+  /// Supervise `script`, which is ready once spawned, with the service
+  /// parameters `params`, after the configuration has been reloaded with
+  /// the parameters `reloaded` added.
+  fn supervise_reloaded(
+    script: &'static str,
+    params: &[(&str, &str)],
+    reloaded: &[(&str, &str)],
+    clock: &FakeClock,
+    stop_when: Option<StopWhen>
+  ) -> ExitReason {
+    let mut ctx = ServerCtx::new(SVC, &config(params)).unwrap();
+    ctx.profile = Box::new(Script {
+      script,
+      readiness: Readiness::Spawned
+    });
+    let reloaded = config(&[params, reloaded].concat());
+    supervise_ctx(ctx, clock, stop_when, vec![reloaded])
+  }
+
+  fn secs(secs: &[u64]) -> Vec<Duration> {
+    secs.iter().copied().map(Duration::from_secs).collect()
+  }
+
+  /// A port on which nothing is listening.
+  fn closed_port() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().port().to_string()
+  }
+
+  #[test]
+  fn session_timeout() {
+    let clock = FakeClock::new();
+    let start = clock.now();
+    let reason =
+      supervise_script("sleep 1000", &[("Timeout", "1h")], &clock, None);
+    assert_eq!(reason, ExitReason::Clean);
+    assert_eq!(clock.waits()[0], Duration::from_secs(60 * 60));
+    assert!(clock.now() >= start + Duration::from_secs(60 * 60));
+  }
+
+  #[test]
+  fn restart_backoff() {
+    let clock = FakeClock::new();
+    let params = [
+      ("RestartPolicy", "always"),
+      ("RestartDelay", "1s"),
+      ("RestartMaxDelay", "4s"),
+      ("RestartLimit", "100")
+    ];
+    let reason = supervise_script("exit 0", &params, &clock, Some(5));
+    assert_eq!(reason, ExitReason::Clean);
+    assert_eq!(clock.waits()[..5], secs(&[1, 2, 4, 4, 4])[..]);
+  }
+
+  #[test]
+  fn crash_loop() {
+    let clock = FakeClock::new();
+    let params = [
+      ("RestartPolicy", "on-failure"),
+      ("RestartDelay", "1s"),
+      ("RestartMaxDelay", "1s"),
+      ("RestartLimit", "3"),
+      ("RestartWindow", "1min")
+    ];
+    let reason = supervise_script("exit 3", &params, &clock, None);
+    assert_eq!(reason, ExitReason::CrashLoop);
+    assert_eq!(clock.waits(), secs(&[1, 1, 1]));
+  }
+
+  #[test]
+  fn restarts_expire_from_window() {
+    let clock = FakeClock::new();
+    let params = [
+      ("RestartPolicy", "on-failure"),
+      ("RestartDelay", "10s"),
+      ("RestartMaxDelay", "10s"),
+      ("RestartLimit", "2"),
+      ("RestartWindow", "15s")
+    ];
+    let reason = supervise_script("exit 3", &params, &clock, Some(4));
+    assert_eq!(reason, ExitReason::Clean);
+    assert_eq!(clock.waits()[..4], secs(&[10, 10, 10, 10])[..]);
+  }
+
+  #[test]
+  fn not_ready() {
+    let clock = FakeClock::new();
+    let start = clock.now();
+    let profile = Script {
+      script: "sleep 1000",
+      readiness: Readiness::Listening
+    };
+    let port = closed_port();
+    let params = [("Port", port.as_str()), ("ReadyTimeout", "5s")];
+    let reason = supervise_profile(profile, &params, &clock, None);
+    assert_eq!(reason, ExitReason::StartFailed);
+    assert_eq!(reason.exit_status(), 3);
+    assert!(clock.now() >= start + Duration::from_secs(5));
+    // Probed every PROBE_INTERVAL until ReadyTimeout has passed.
+    assert_eq!(clock.waits()[..20], [PROBE_INTERVAL; 20][..]);
+  }
+
+  #[test]
+  fn unhealthy() {
+    let clock = FakeClock::new();
+    let port = closed_port();
+    let params = [
+      ("Port", port.as_str()),
+      ("HealthInterval", "10s"),
+      ("HealthFailures", "2")
+    ];
+    let reason = supervise_script("sleep 1000", &params, &clock, None);
+    assert_eq!(reason, ExitReason::Unresponsive);
+    assert_eq!(reason.exit_status(), 5);
+    assert_eq!(clock.waits()[..2], secs(&[10, 10])[..]);
+  }
+
+  #[test]
+  fn reload_timeout() {
+    let clock = FakeClock::new();
+    let params = [("Timeout", "1h")];
+    let reloaded = [("Timeout", "2h")];
+    let reason =
+      supervise_reloaded("sleep 1000", &params, &reloaded, &clock, None);
+    assert_eq!(reason, ExitReason::Clean);
+    assert_eq!(clock.waits()[0], Duration::from_secs(2 * 60 * 60));
+  }
+
+  #[test]
+  fn reload_stop_grace() {
+    // The shell's parent is the supervisor, this process.
+    let marker = std::env::temp_dir()
+      .join(format!("verboten-stop-grace-{}", std::process::id()));
+    let _ = std::fs::remove_file(&marker);
+    // Ignored signals stay ignored in the commands the shell runs.
+    let script = concat!(
+      "trap '' TERM; ",
+      "touch \"${TMPDIR:-/tmp}/verboten-stop-grace-$PPID\"; ",
+      "sleep 1000"
+    );
+
+    let clock = FakeClock::new();
+    let params = [("StopGrace", "10s")];
+    let reloaded = [("StopGrace", "3s")];
+    let ignoring_term = marker.clone();
+    let stop_when = Box::new(move || ignoring_term.exists()) as StopWhen;
+    let reason =
+      supervise_reloaded(script, &params, &reloaded, &clock, Some(stop_when));
+    let _ = std::fs::remove_file(&marker);
+
+    assert_eq!(reason, ExitReason::Clean);
+    assert_eq!(clock.waits(), secs(&[1, 1, 1]));
+  }
+
+  #[test]
+  fn reload_restart() {
+    // Restarts use the reloaded configuration, so the debug server needs to
+    // be a profile of its own.
+    let dir = crate::paths::profile_dir();
+    std::fs::create_dir_all(&dir).unwrap();
+    let name = format!("{}-{}", SVC, std::process::id());
+    let fname = dir.join(format!("{}.toml", name));
+    std::fs::write(&fname, "port = 1\nargs = [\"-c\", \"exit 3\"]").unwrap();
+
+    let clock = FakeClock::new();
+    let params = [("Profile", name.as_str()), ("RestartPolicy", "never")];
+    let reloaded = [
+      ("RestartPolicy", "on-failure"),
+      ("RestartDelay", "1s"),
+      ("RestartMaxDelay", "1s"),
+      ("RestartLimit", "2"),
+      ("RestartWindow", "1min")
+    ];
+    let ctx = ServerCtx::new(SVC, &config(&params)).unwrap();
+    let reloaded = config(&[&params[..], &reloaded].concat());
+    let reason = supervise_ctx(ctx, &clock, None, vec![reloaded]);
+    let _ = std::fs::remove_file(&fname);
+
+    assert_eq!(reason, ExitReason::CrashLoop);
+    assert_eq!(clock.waits(), secs(&[1, 1]));
+  }
+
+  #[test]
+  fn reload_health() {
+    let clock = FakeClock::new();
+    let port = closed_port();
+    let params = [("Port", port.as_str())];
+    let reloaded = [("HealthInterval", "10s"), ("HealthFailures", "1")];
+    let reason =
+      supervise_reloaded("sleep 1000", &params, &reloaded, &clock, None);
+    assert_eq!(reason, ExitReason::Unresponsive);
+    assert_eq!(clock.waits()[0], Duration::from_secs(10));
+  }
+
+  #[test]
+  fn reload_invalid() {
+    let clock = FakeClock::new();
+    let params = [("Timeout", "1h")];
+    let reloaded = [("Timeout", "2h"), ("Profile", "no-such-profile")];
+    let reason =
+      supervise_reloaded("sleep 1000", &params, &reloaded, &clock, None);
+    assert_eq!(reason, ExitReason::Clean);
+    assert_eq!(clock.waits()[0], Duration::from_secs(60 * 60));
+  }
+
+  /// Number of processes in the process group `pgid`.
+  #[cfg(target_os = "linux")]
+  fn group_size(pgid: u32) -> usize {
+    let mut n = 0;
+    for entry in std::fs::read_dir("/proc").unwrap().flatten() {
+      let stat = match std::fs::read_to_string(entry.path().join("stat")) {
+        Ok(stat) => stat,
+        Err(_) => continue
+      };
+      // The fields after the command name are state, ppid and pgrp.
+      let fields = match stat.rfind(')') {
+        Some(idx) => stat[idx + 1..].split_whitespace().collect::<Vec<_>>(),
+        None => continue
+      };
+      if fields.get(2) == Some(&pgid.to_string().as_str()) {
+        n += 1;
+      }
+    }
+    n
+  }
+
+  /// Spawn a shell leaving a background process behind, and wait for both
+  /// of its processes to have started.
+  #[cfg(target_os = "linux")]
+  fn spawn_sleeps() -> ServerProcess {
+    let mut cmd = Command::new("sh");
+    cmd.args(["-c", "sleep 1000 & sleep 1000"]);
+    let child = ServerProcess::spawn(&mut cmd).unwrap();
+
+    let until = Instant::now() + Duration::from_secs(5);
+    while group_size(child.id()) < 2 {
+      assert!(Instant::now() < until, "sleeps were not started");
+      thread::sleep(Duration::from_millis(10));
+    }
+    child
+  }
+
+  #[test]
+  #[cfg(target_os = "linux")]
+  fn stop_terminates_group() {
+    let mut child = spawn_sleeps();
+    let pgid = child.id();
+
+    child.request_stop().unwrap();
+    child.wait().unwrap();
+    reap_tree(&SystemClock, "sh", &mut child);
+    assert_eq!(group_size(pgid), 0);
+  }
+
+  #[test]
+  #[cfg(target_os = "linux")]
+  fn leftovers_are_killed() {
+    let mut child = spawn_sleeps();
+    let pgid = child.id();
+
+    // Only the debug server itself terminates, leaving the background
+    // sleep behind.
+    unsafe {
+      libc::kill(pgid as libc::pid_t, libc::SIGKILL);
+    }
+    child.wait().unwrap();
+    assert!(child.is_tree_alive());
+
+    reap_tree(&SystemClock, "sh", &mut child);
+    assert!(!child.is_tree_alive());
+    assert_eq!(group_size(pgid), 0);
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
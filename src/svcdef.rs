@@ -0,0 +1,379 @@
+//! The service definition; how the service is registered with the service
+//! manager, as opposed to the service parameters which configure the
+//! service itself.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::args;
+use crate::config::Config;
+use crate::err::Error;
+use crate::params::ParamStore;
+use crate::reconfigure::Change;
+
+/// Longest display name accepted by the Windows service control manager.
+const MAX_DISPLAY_NAME: usize = 256;
+
+/// Longest description accepted.
+const MAX_DESCRIPTION: usize = 1024;
+
+
+/// When the service is started.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum StartType {
+  /// When the computer starts.
+  Auto,
+
+  /// Shortly after the computer has started (Windows only).
+  Delayed,
+
+  /// Only when explicitly started.
+  Manual
+}
+
+impl FromStr for StartType {
+  type Err = Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "auto" => Ok(StartType::Auto),
+      "delayed" => Ok(StartType::Delayed),
+      "manual" => Ok(StartType::Manual),
+      _ => Err(Error::BadInput(format!(
+        "Unknown start type '{}'; expected auto, delayed or manual",
+        s
+      )))
+    }
+  }
+}
+
+impl fmt::Display for StartType {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let s = match self {
+      StartType::Auto => "auto",
+      StartType::Delayed => "delayed",
+      StartType::Manual => "manual"
+    };
+    write!(f, "{}", s)
+  }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ServiceDef {
+  pub(crate) display_name: String,
+  pub(crate) description: String,
+  pub(crate) start_type: StartType,
+
+  /// Services the service depends on, in addition to the network.
+  pub(crate) dependencies: Vec<String>
+}
+
+impl ServiceDef {
+  /// The definition of the service `service_name` running the profile
+  /// `profile_name` when no options have been given.
+  pub(crate) fn new(service_name: &str, profile_name: &str) -> Self {
+    ServiceDef {
+      display_name: display_name(service_name, profile_name),
+      description: description(profile_name),
+      start_type: StartType::Auto,
+      dependencies: Vec::new()
+    }
+  }
+
+  /// Override the definition with the options given in `ctx`, validating
+  /// them.
+  pub(crate) fn apply(&mut self, ctx: &args::Context) -> Result<(), Error> {
+    if let Some(ref name) = ctx.display_name {
+      check_text("Display name", name, MAX_DISPLAY_NAME)?;
+      if name.is_empty() {
+        return Err(Error::BadInput(String::from(
+          "Display name must not be empty"
+        )));
+      }
+      self.display_name = name.clone();
+    }
+    if let Some(ref desc) = ctx.description {
+      check_text("Description", desc, MAX_DESCRIPTION)?;
+      self.description = desc.clone();
+    }
+    if let Some(ref st) = ctx.start_type {
+      let st = st.parse::<StartType>()?;
+      if st == StartType::Delayed && !cfg!(windows) {
+        return Err(Error::BadInput(String::from(
+          "Delayed start is only supported on Windows"
+        )));
+      }
+      self.start_type = st;
+    }
+    if let Some(ref deps) = ctx.dependencies {
+      for (i, dep) in deps.iter().enumerate() {
+        check_dependency(dep)?;
+        if deps[..i].contains(dep) {
+          return Err(Error::BadInput(format!(
+            "Dependency '{}' given more than once",
+            dep
+          )));
+        }
+      }
+      self.dependencies = deps.clone();
+    }
+    Ok(())
+  }
+
+  /// The definition after reconfiguring the service `service_name`, which
+  /// was running the profile `old_profile`, with the options given in
+  /// `ctx`.
+  ///
+  /// Changing the profile resets the display name and description to the
+  /// new profile's defaults, unless they are given too or have been
+  /// customized.
+  pub(crate) fn reconfigured(
+    &self,
+    service_name: &str,
+    old_profile: &str,
+    ctx: &args::Context
+  ) -> Result<Self, Error> {
+    let mut def = self.clone();
+    if let Some(ref profile_name) = ctx.profile {
+      if def.display_name == display_name(service_name, old_profile) {
+        def.display_name = display_name(service_name, profile_name);
+      }
+      if def.description == description(old_profile) {
+        def.description = description(profile_name);
+      }
+    }
+    def.apply(ctx)?;
+    Ok(def)
+  }
+
+  /// What differs between this definition and `new`.
+  pub(crate) fn diff(&self, new: &ServiceDef) -> Vec<Change> {
+    let mut changes = Vec::new();
+    if self.display_name != new.display_name {
+      changes.push(Change::new(
+        "display name",
+        Some(self.display_name.clone()),
+        new.display_name.clone()
+      ));
+    }
+    if self.description != new.description {
+      changes.push(Change::new(
+        "description",
+        Some(self.description.clone()),
+        new.description.clone()
+      ));
+    }
+    if self.start_type != new.start_type {
+      changes.push(Change::new(
+        "start type",
+        Some(self.start_type.to_string()),
+        new.start_type.to_string()
+      ));
+    }
+    if self.dependencies != new.dependencies {
+      changes.push(Change::new(
+        "dependencies",
+        Some(self.dependencies.join(",")),
+        new.dependencies.join(",")
+      ));
+    }
+    changes
+  }
+}
+
+
+/// Validate all the install options in `ctx` for the service
+/// `service_name`, which is to be installed with the service parameters
+/// `params` in addition to the ones given in `ctx`.
+///
+/// Returns the configuration the service will start with, and the service
+/// definition to install the service with.
+pub(crate) fn check_install(
+  service_name: &str,
+  params: &dyn ParamStore,
+  ctx: &args::Context
+) -> Result<(Config, ServiceDef), Error> {
+  // This includes any configuration file already in place, so the profile
+  // is the one the service will actually run.
+  let cfg = Config::load(service_name, params, ctx)?;
+
+  let mut def = ServiceDef::new(service_name, &cfg.profile);
+  def.apply(ctx)?;
+
+  Ok((cfg, def))
+}
+
+
+/// The default display name of the service `service_name`.  It includes the
+/// service name, so several instances can be told apart.
+pub(crate) fn display_name(service_name: &str, profile_name: &str) -> String {
+  format!("Verboten {} ({})", service_name, profile_name)
+}
+
+
+/// The default description of a service running the profile
+/// `profile_name`.
+pub(crate) fn description(profile_name: &str) -> String {
+  format!(
+    "A service for launching {} in maximum Bad Idea Mode.",
+    profile_name
+  )
+}
+
+
+fn check_text(what: &str, s: &str, max: usize) -> Result<(), Error> {
+  if s.chars().count() > max {
+    return Err(Error::BadInput(format!(
+      "{} must be at most {} characters long",
+      what, max
+    )));
+  }
+  if s.chars().any(char::is_control) {
+    return Err(Error::BadInput(format!(
+      "{} must not contain control characters",
+      what
+    )));
+  }
+  Ok(())
+}
+
+
+/// Dependencies are names of services (Windows) or units (Linux), which are
+/// restricted to characters which are valid in both.
+fn check_dependency(dep: &str) -> Result<(), Error> {
+  let valid = |c: char| c.is_ascii_alphanumeric() || "-_.@".contains(c);
+  if dep.is_empty() || !dep.chars().all(valid) {
+    return Err(Error::BadInput(format!(
+      "Invalid dependency '{}'; expected a service name",
+      dep
+    )));
+  }
+  Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  use crate::params::MemoryStore;
+
+  const SVC: &str = "verboten-unit-test";
+
+  fn profile_ctx(profile: &str) -> args::Context {
+    args::Context {
+      profile: Some(profile.to_string()),
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn reconfigure_profile_resets_defaults() {
+    let old = ServiceDef::new(SVC, "gdbserver");
+    let new = old
+      .reconfigured(SVC, "gdbserver", &profile_ctx("dlv"))
+      .unwrap();
+    assert_eq!(new, ServiceDef::new(SVC, "dlv"));
+
+    let ctx = args::Context {
+      display_name: Some(String::from("Debugger")),
+      ..profile_ctx("dlv")
+    };
+    let new = old.reconfigured(SVC, "gdbserver", &ctx).unwrap();
+    assert_eq!(new.display_name, "Debugger");
+    assert_eq!(new.description, description("dlv"));
+  }
+
+  #[test]
+  fn reconfigure_profile_keeps_custom_names() {
+    let mut old = ServiceDef::new(SVC, "gdbserver");
+    old.display_name = String::from("Debugger");
+    let new = old
+      .reconfigured(SVC, "gdbserver", &profile_ctx("dlv"))
+      .unwrap();
+    assert_eq!(new.display_name, "Debugger");
+    assert_eq!(new.description, description("dlv"));
+
+    let mut old = ServiceDef::new(SVC, "gdbserver");
+    old.description = String::from("Debugs things");
+    let new = old
+      .reconfigured(SVC, "gdbserver", &profile_ctx("dlv"))
+      .unwrap();
+    assert_eq!(new.display_name, display_name(SVC, "dlv"));
+    assert_eq!(new.description, "Debugs things");
+  }
+
+  #[test]
+  fn diff() {
+    let old = ServiceDef::new(SVC, "gdbserver");
+    assert!(old.diff(&old).is_empty());
+
+    let new = ServiceDef {
+      description: String::from("Debugs things"),
+      start_type: StartType::Manual,
+      dependencies: vec![String::from("a"), String::from("b")],
+      ..old.clone()
+    };
+    let changes: Vec<String> =
+      old.diff(&new).iter().map(|c| c.to_string()).collect();
+    assert_eq!(
+      changes,
+      [
+        format!("  description: '{}' -> 'Debugs things'", old.description),
+        String::from("  start type: 'auto' -> 'manual'"),
+        String::from("  dependencies: '' -> 'a,b'")
+      ]
+    );
+  }
+
+  #[test]
+  fn install_options() {
+    let mut params = MemoryStore::new();
+    let exec = std::env::current_exe().unwrap();
+    params.set("Exec", exec.to_str().unwrap()).unwrap();
+
+    let ctx = args::Context {
+      start_type: Some(String::from("manual")),
+      dependencies: Some(vec![String::from("sshd")]),
+      ..profile_ctx("dlv")
+    };
+    let (cfg, def) = check_install(SVC, &params, &ctx).unwrap();
+    assert_eq!(cfg.profile, "dlv");
+    assert_eq!(def.display_name, display_name(SVC, "dlv"));
+    assert_eq!(def.start_type, StartType::Manual);
+    assert_eq!(def.dependencies, ["sshd"]);
+
+    let bad = [
+      args::Context {
+        start_type: Some(String::from("sometimes")),
+        ..Default::default()
+      },
+      args::Context {
+        display_name: Some(String::new()),
+        ..Default::default()
+      },
+      args::Context {
+        description: Some(String::from("two\nlines")),
+        ..Default::default()
+      },
+      args::Context {
+        dependencies: Some(vec![String::from("a"), String::from("a")]),
+        ..Default::default()
+      },
+      args::Context {
+        dependencies: Some(vec![String::from("a b")]),
+        ..Default::default()
+      },
+      args::Context {
+        port: Some(String::from("http")),
+        ..Default::default()
+      }
+    ];
+    for ctx in &bad {
+      assert!(check_install(SVC, &params, ctx).is_err(), "{:?}", ctx);
+    }
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
@@ -0,0 +1,337 @@
+//! Per-session transcripts of the debug server's output.
+//!
+//! Each run of the debug server gets a transcript file of its own, named
+//! after the time it was started and its process id, containing the lines it
+//! wrote to stdout and stderr as they are logged.  Old transcripts are pruned
+//! whenever a new one is created.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use log::{debug, warn};
+
+use crate::config::Config;
+use crate::err::Error;
+use crate::paths;
+
+
+#[derive(Debug, Clone)]
+pub(crate) struct TranscriptConfig {
+  /// Directory the service's transcripts are stored in.
+  pub(crate) dir: PathBuf,
+
+  /// Maximum number of transcripts to keep.  `0` disables transcripts.
+  pub(crate) keep: usize,
+
+  /// Transcripts older than this are removed.
+  pub(crate) max_age: Duration
+}
+
+impl TranscriptConfig {
+  /// Transcript configuration of the service `service_name`.
+  pub(crate) fn from_config(service_name: &str, cfg: &Config) -> Self {
+    let dir = match cfg.transcript_dir {
+      Some(ref dir) => dir.clone(),
+      None => paths::transcript_root().join(service_name)
+    };
+    TranscriptConfig {
+      dir,
+      keep: cfg.transcript_keep,
+      max_age: cfg.transcript_max_age
+    }
+  }
+}
+
+
+/// An open transcript file, shared by the threads forwarding the debug
+/// server's output.
+pub(crate) struct Transcript {
+  path: PathBuf,
+  file: Mutex<Option<File>>
+}
+
+impl Transcript {
+  /// Create a new transcript for the debug server with process id `pid`.
+  pub(crate) fn create(cfg: &TranscriptConfig, pid: u32) -> io::Result<Self> {
+    fs::create_dir_all(&cfg.dir)?;
+
+    // Timestamps are in UTC, without separators so they are valid in file
+    // names everywhere and sort chronologically.
+    let ts = humantime::format_rfc3339_seconds(SystemTime::now())
+      .to_string()
+      .replace(&['-', ':'][..], "");
+    let path = cfg.dir.join(format!("{}-{}.log", ts, pid));
+    let file = File::create(&path)?;
+
+    Ok(Transcript {
+      path,
+      file: Mutex::new(Some(file))
+    })
+  }
+
+  pub(crate) fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// Append a line of output to the transcript.
+  ///
+  /// If writing fails the transcript is closed, and further lines are
+  /// dropped.
+  pub(crate) fn write_line(&self, line: &[u8]) {
+    let mut file = match self.file.lock() {
+      Ok(file) => file,
+      Err(_) => return
+    };
+    if let Some(f) = file.as_mut() {
+      if let Err(e) = f.write_all(line).and_then(|_| f.write_all(b"\n")) {
+        warn!("Unable to write to transcript {:?}; {}", self.path, e);
+        *file = None;
+      }
+    }
+  }
+}
+
+
+/// A transcript file found in a transcript directory.
+pub(crate) struct Entry {
+  pub(crate) name: String,
+  pub(crate) path: PathBuf,
+  pub(crate) size: u64,
+  pub(crate) modified: SystemTime
+}
+
+/// List the transcripts in `dir`, oldest first.
+pub(crate) fn list(dir: &Path) -> io::Result<Vec<Entry>> {
+  let mut entries = Vec::new();
+  let rd = match fs::read_dir(dir) {
+    Ok(rd) => rd,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(entries),
+    Err(e) => return Err(e)
+  };
+  for de in rd {
+    let de = de?;
+    let name = match de.file_name().into_string() {
+      Ok(name) => name,
+      Err(_) => continue
+    };
+    if !name.ends_with(".log") {
+      continue;
+    }
+    let md = de.metadata()?;
+    if !md.is_file() {
+      continue;
+    }
+    entries.push(Entry {
+      name,
+      path: de.path(),
+      size: md.len(),
+      modified: md.modified()?
+    });
+  }
+  // The names start with a timestamp.
+  entries.sort_by(|a, b| a.name.cmp(&b.name));
+  Ok(entries)
+}
+
+
+/// Remove transcripts which are too old, and the oldest transcripts beyond
+/// the number to keep.
+pub(crate) fn prune(cfg: &TranscriptConfig) -> io::Result<()> {
+  let entries = list(&cfg.dir)?;
+  let excess = entries.len().saturating_sub(cfg.keep);
+  let now = SystemTime::now();
+
+  for (i, ent) in entries.iter().enumerate() {
+    let age = now.duration_since(ent.modified).unwrap_or_default();
+    if i < excess || age > cfg.max_age {
+      debug!("removing old transcript {:?}", ent.path);
+      if let Err(e) = fs::remove_file(&ent.path) {
+        warn!("Unable to remove transcript {:?}; {}", ent.path, e);
+      }
+    }
+  }
+  Ok(())
+}
+
+
+/// List the transcripts in `dir`, or print the one selected by `which`.
+///
+/// `which` is either the file name of a transcript or `latest`.
+pub(crate) fn show(dir: &Path, which: Option<&str>) -> Result<(), Error> {
+  let entries = list(dir).map_err(|e| {
+    Error::IO(format!("Unable to list transcripts in {:?}; {}", dir, e))
+  })?;
+
+  let which = match which {
+    Some(which) => which,
+    None => {
+      if entries.is_empty() {
+        println!("No transcripts in {:?}", dir);
+      }
+      for ent in &entries {
+        println!(
+          "{:<32} {:>10}  {}",
+          ent.name,
+          ent.size,
+          humantime::format_rfc3339_seconds(ent.modified)
+        );
+      }
+      return Ok(());
+    }
+  };
+
+  let ent = match select(&entries, which) {
+    Some(ent) => ent,
+    None => {
+      return Err(Error::BadInput(format!(
+        "No transcript '{}' in {:?}",
+        which, dir
+      )));
+    }
+  };
+
+  let mut f = File::open(&ent.path).map_err(|e| {
+    Error::IO(format!("Unable to open {:?}; {}", ent.path, e))
+  })?;
+  io::copy(&mut f, &mut io::stdout().lock())?;
+  Ok(())
+}
+
+
+/// The transcript among `entries` selected by `which`.
+fn select<'a>(entries: &'a [Entry], which: &str) -> Option<&'a Entry> {
+  if which == "latest" {
+    entries.last()
+  } else {
+    entries.iter().find(|ent| ent.name == which)
+  }
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+  /// A transcript directory of its own, removed when dropped.
+  struct TempDir(PathBuf);
+
+  impl TempDir {
+    fn new(name: &str) -> Self {
+      let dir = std::env::temp_dir().join(format!(
+        "verboten-transcript-{}-{}",
+        std::process::id(),
+        name
+      ));
+      fs::create_dir_all(&dir).unwrap();
+      TempDir(dir)
+    }
+
+    fn config(&self, keep: usize) -> TranscriptConfig {
+      TranscriptConfig {
+        dir: self.0.clone(),
+        keep,
+        max_age: DAY
+      }
+    }
+
+    /// Create the file `name`, last modified `age` ago.
+    fn create(&self, name: &str, age: Duration) {
+      let f = File::create(self.0.join(name)).unwrap();
+      f.set_modified(SystemTime::now() - age).unwrap();
+    }
+
+    fn names(&self) -> Vec<String> {
+      list(&self.0)
+        .unwrap()
+        .into_iter()
+        .map(|ent| ent.name)
+        .collect()
+    }
+  }
+
+  impl Drop for TempDir {
+    fn drop(&mut self) {
+      let _ = fs::remove_dir_all(&self.0);
+    }
+  }
+
+  #[test]
+  fn create_and_write() {
+    let dir = TempDir::new("create");
+    let t = Transcript::create(&dir.config(1), 1234).unwrap();
+    t.write_line(b"first");
+    t.write_line(b"second\xff");
+    let name = t.path().file_name().unwrap().to_str().unwrap();
+    assert!(name.ends_with("Z-1234.log"), "{}", name);
+    assert_eq!(fs::read(t.path()).unwrap(), b"first\nsecond\xff\n");
+  }
+
+  #[test]
+  fn list_transcripts() {
+    let dir = TempDir::new("list");
+    dir.create("20240102T000000Z-2.log", Duration::from_secs(0));
+    dir.create("20240101T000000Z-1.log", Duration::from_secs(0));
+    dir.create("notes.txt", Duration::from_secs(0));
+    fs::create_dir(dir.0.join("old.log")).unwrap();
+    assert_eq!(
+      dir.names(),
+      ["20240101T000000Z-1.log", "20240102T000000Z-2.log"]
+    );
+    assert!(list(&dir.0.join("missing")).unwrap().is_empty());
+  }
+
+  #[test]
+  fn prune_to_keep() {
+    let dir = TempDir::new("keep");
+    for i in 1..=5 {
+      dir.create(&format!("2024010{}T000000Z-{}.log", i, i), DAY / 2);
+    }
+    prune(&dir.config(2)).unwrap();
+    assert_eq!(
+      dir.names(),
+      ["20240104T000000Z-4.log", "20240105T000000Z-5.log"]
+    );
+
+    prune(&dir.config(0)).unwrap();
+    assert!(dir.names().is_empty());
+  }
+
+  #[test]
+  fn prune_old() {
+    let dir = TempDir::new("age");
+    dir.create("20240101T000000Z-1.log", DAY * 3);
+    dir.create("20240102T000000Z-2.log", DAY * 2);
+    dir.create("20240103T000000Z-3.log", DAY / 2);
+    dir.create("notes.txt", DAY * 3);
+    prune(&dir.config(20)).unwrap();
+    assert_eq!(dir.names(), ["20240103T000000Z-3.log"]);
+    assert!(dir.0.join("notes.txt").exists());
+  }
+
+  #[test]
+  fn select_transcript() {
+    let dir = TempDir::new("select");
+    dir.create("20240101T000000Z-1.log", Duration::from_secs(0));
+    dir.create("20240102T000000Z-2.log", Duration::from_secs(0));
+    let entries = list(&dir.0).unwrap();
+
+    let ent = select(&entries, "latest").unwrap();
+    assert_eq!(ent.name, "20240102T000000Z-2.log");
+    let ent = select(&entries, "20240101T000000Z-1.log").unwrap();
+    assert_eq!(ent.name, "20240101T000000Z-1.log");
+    assert!(select(&entries, "20240103T000000Z-3.log").is_none());
+    assert!(select(&[], "latest").is_none());
+
+    match show(&dir.0, Some("nope.log")) {
+      Err(Error::BadInput(_)) => {}
+      res => panic!("{:?}", res.err())
+    }
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
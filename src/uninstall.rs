@@ -0,0 +1,510 @@
+//! Removal of an installed service.
+//!
+//! Stopping the service is bounded by a timeout, after which the service's
+//! processes are either killed or the uninstallation is aborted.  The
+//! service parameters can be kept for a later reinstallation, and the files
+//! and firewall rules created for the service can be purged along with it.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::args;
+use crate::config::Config;
+use crate::err::Error;
+use crate::params::{FileFormat, FileStore, ParamStore};
+use crate::paths;
+use crate::profile;
+use crate::service;
+use crate::transcript::{self, TranscriptConfig};
+
+/// How long to wait for the service to stop, unless told otherwise.
+const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait for the service to stop after killing it.
+const KILL_TIMEOUT: Duration = Duration::from_secs(10);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+
+struct Options {
+  stop_timeout: Duration,
+  force: bool,
+  keep_config: bool,
+  purge: bool,
+  dry_run: bool
+}
+
+impl Options {
+  fn new(ctx: &args::Context) -> Result<Self, Error> {
+    let stop_timeout = match ctx.stop_timeout {
+      Some(ref s) => humantime::parse_duration(s).map_err(|e| {
+        Error::BadInput(format!("Invalid stop timeout '{}'; {}", s, e))
+      })?,
+      None => DEFAULT_STOP_TIMEOUT
+    };
+    Ok(Options {
+      stop_timeout,
+      force: ctx.force,
+      keep_config: ctx.keep_config,
+      purge: ctx.purge,
+      dry_run: ctx.dry_run
+    })
+  }
+}
+
+
+/// The parts of removing a service carried out by the service manager.
+trait Backend {
+  fn is_running(&mut self, service_name: &str) -> Result<bool, Error>;
+  fn request_stop(&mut self, service_name: &str) -> Result<(), Error>;
+  fn kill(&mut self, service_name: &str) -> Result<(), Error>;
+
+  /// Remove the stopped service, or print what would be removed.
+  fn remove(&mut self, service_name: &str, dry_run: bool)
+    -> Result<(), Error>;
+
+  /// Remove the firewall rules created for `exec`, or print which ones
+  /// would be removed.
+  fn remove_firewall_rules(
+    &mut self,
+    exec: &Path,
+    dry_run: bool
+  ) -> Result<(), Error>;
+}
+
+
+/// Backend using the platform's service manager.
+struct Installed;
+
+impl Backend for Installed {
+  fn is_running(&mut self, service_name: &str) -> Result<bool, Error> {
+    service::is_running(service_name)
+  }
+
+  fn request_stop(&mut self, service_name: &str) -> Result<(), Error> {
+    service::request_stop(service_name)
+  }
+
+  fn kill(&mut self, service_name: &str) -> Result<(), Error> {
+    service::kill(service_name)
+  }
+
+  fn remove(
+    &mut self,
+    service_name: &str,
+    dry_run: bool
+  ) -> Result<(), Error> {
+    service::remove(service_name, dry_run)
+  }
+
+  fn remove_firewall_rules(
+    &mut self,
+    exec: &Path,
+    dry_run: bool
+  ) -> Result<(), Error> {
+    service::remove_firewall_rules(exec, dry_run)
+  }
+}
+
+
+/// Stop and remove the service `service_name` according to the uninstall
+/// options in `ctx`.
+pub(crate) fn uninstall(
+  service_name: &str,
+  ctx: &args::Context
+) -> Result<(), Error> {
+  let opts = Options::new(ctx)?;
+  let store = service::param_store(service_name)?;
+  remove(&mut Installed, service_name, &store, &opts)
+}
+
+
+/// Stop and remove the service `service_name`, whose service parameters are
+/// in `store`, using `backend`.
+fn remove(
+  backend: &mut dyn Backend,
+  service_name: &str,
+  store: &dyn ParamStore,
+  opts: &Options
+) -> Result<(), Error> {
+  // The configuration is only needed to find what to purge, so make do with
+  // whatever is valid of it.
+  let cli = args::Context::default();
+  let (cfg, _problems) = Config::check(service_name, store, &cli);
+
+  if opts.dry_run {
+    println!("==> Dry run; not uninstalling service '{}'", service_name);
+    if backend.is_running(service_name)? {
+      let then = if opts.force { "kill it" } else { "give up" };
+      println!(
+        "==> Would stop service '{}', and {} if it hasn't stopped within {}",
+        service_name,
+        then,
+        humantime::format_duration(opts.stop_timeout)
+      );
+    }
+  } else {
+    stop(backend, service_name, opts)?;
+  }
+
+  if opts.keep_config {
+    keep_params(service_name, store, opts.dry_run)?;
+  }
+
+  backend.remove(service_name, opts.dry_run)?;
+
+  if opts.purge {
+    purge(backend, service_name, &cfg, opts.dry_run)?;
+  }
+
+  if !opts.dry_run {
+    println!("==> Service uninstallation successful");
+  }
+
+  Ok(())
+}
+
+
+/// Stop the service, giving up or killing it if it doesn't stop in time.
+fn stop(
+  backend: &mut dyn Backend,
+  service_name: &str,
+  opts: &Options
+) -> Result<(), Error> {
+  if !backend.is_running(service_name)? {
+    return Ok(());
+  }
+
+  println!("==> Requesting service '{}' to stop ..", service_name);
+  backend.request_stop(service_name)?;
+  let stopped = || backend.is_running(service_name).map(|r| !r);
+  if wait_for(opts.stop_timeout, stopped)? {
+    return Ok(());
+  }
+
+  let timeout = humantime::format_duration(opts.stop_timeout);
+  if !opts.force {
+    return Err(Error::Service(format!(
+      "Service '{}' did not stop within {}; not uninstalling it (use \
+       --force to kill it, or --stop-timeout to wait longer)",
+      service_name, timeout
+    )));
+  }
+
+  println!(
+    "==> Service '{}' did not stop within {}; killing it ..",
+    service_name, timeout
+  );
+  backend.kill(service_name)?;
+  let stopped = || backend.is_running(service_name).map(|r| !r);
+  if wait_for(KILL_TIMEOUT, stopped)? {
+    Ok(())
+  } else {
+    Err(Error::Service(format!(
+      "Service '{}' is still running after being killed",
+      service_name
+    )))
+  }
+}
+
+
+/// Call `done` until it returns true or `timeout` has passed.  Returns
+/// whether `done` returned true.
+fn wait_for<F>(timeout: Duration, mut done: F) -> Result<bool, Error>
+where
+  F: FnMut() -> Result<bool, Error>
+{
+  let deadline = Instant::now() + timeout;
+  loop {
+    if done()? {
+      return Ok(true);
+    }
+    if Instant::now() >= deadline {
+      return Ok(false);
+    }
+    thread::sleep(POLL_INTERVAL);
+  }
+}
+
+
+/// Where the service parameters of the service `service_name` are kept
+/// between uninstalling it with `--keep-config` and reinstalling it.
+pub(crate) fn kept_path(service_name: &str) -> PathBuf {
+  paths::config_root().join(format!("{}.kept.conf", service_name))
+}
+
+
+fn keep_params(
+  service_name: &str,
+  store: &dyn ParamStore,
+  dry_run: bool
+) -> Result<(), Error> {
+  let fname = kept_path(service_name);
+  if dry_run {
+    println!("==> Would keep service parameters in {:?}", fname);
+    return Ok(());
+  }
+
+  println!("==> Keeping service parameters in {:?} ..", fname);
+  fs::create_dir_all(paths::config_root())?;
+  fs::write(&fname, "")?;
+  let mut kept = FileStore::new(fname, FileFormat::Ini);
+  for (k, v) in store.list()? {
+    kept.set(&k, &v)?;
+  }
+  Ok(())
+}
+
+
+/// Remove the transcripts, state files and firewall rules of the service.
+fn purge(
+  backend: &mut dyn Backend,
+  service_name: &str,
+  cfg: &Config,
+  dry_run: bool
+) -> Result<(), Error> {
+  let tcfg = TranscriptConfig::from_config(service_name, cfg);
+  remove_transcripts(&tcfg.dir, dry_run)?;
+  remove_dir(
+    "state files",
+    &paths::state_root().join(service_name),
+    dry_run
+  )?;
+
+  // Only the computer preparation done at install creates firewall rules.
+  let prepared = profile::lookup(&cfg.profile)
+    .map(|p| p.prep_args().is_some())
+    .unwrap_or(false);
+  if let (true, Some(exec)) = (prepared, &cfg.exec) {
+    backend.remove_firewall_rules(exec, dry_run)?;
+  }
+
+  Ok(())
+}
+
+
+/// Remove the transcripts in `dir`, and `dir` itself if nothing else is left
+/// in it.  A custom `TranscriptDir` is used as it is, so it may well be
+/// shared with other files.
+fn remove_transcripts(dir: &Path, dry_run: bool) -> Result<(), Error> {
+  let entries = transcript::list(dir).map_err(|e| {
+    Error::IO(format!("Unable to list transcripts in {:?}; {}", dir, e))
+  })?;
+  if entries.is_empty() {
+    return Ok(());
+  }
+  if dry_run {
+    println!(
+      "==> Would remove {} transcripts in {:?}",
+      entries.len(),
+      dir
+    );
+    return Ok(());
+  }
+
+  println!("==> Removing {} transcripts in {:?} ..", entries.len(), dir);
+  for ent in &entries {
+    fs::remove_file(&ent.path).map_err(|e| {
+      Error::IO(format!("Unable to remove {:?}; {}", ent.path, e))
+    })?;
+  }
+  if fs::read_dir(dir)?.next().is_none() {
+    fs::remove_dir(dir).map_err(|e| {
+      Error::IO(format!("Unable to remove {:?}; {}", dir, e))
+    })?;
+  }
+  Ok(())
+}
+
+
+fn remove_dir(what: &str, dir: &Path, dry_run: bool) -> Result<(), Error> {
+  if !dir.exists() {
+    return Ok(());
+  }
+  if dry_run {
+    println!("==> Would remove {} {:?}", what, dir);
+    return Ok(());
+  }
+  println!("==> Removing {} {:?} ..", what, dir);
+  fs::remove_dir_all(dir).map_err(|e| {
+    Error::IO(format!("Unable to remove {:?}; {}", dir, e))
+  })
+}
+
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::params::MemoryStore;
+
+  /// A directory of its own, removed when dropped.
+  struct TempDir(PathBuf);
+
+  impl TempDir {
+    fn new(name: &str) -> Self {
+      let dir = std::env::temp_dir().join(format!(
+        "verboten-uninstall-{}-{}",
+        std::process::id(),
+        name
+      ));
+      fs::create_dir_all(&dir).unwrap();
+      TempDir(dir)
+    }
+
+    fn create(&self, name: &str) -> PathBuf {
+      let path = self.0.join(name);
+      fs::write(&path, name).unwrap();
+      path
+    }
+  }
+
+  impl Drop for TempDir {
+    fn drop(&mut self) {
+      let _ = fs::remove_dir_all(&self.0);
+    }
+  }
+
+  /// A service which has no configuration file.
+  const SVC: &str = "verboten-unit-test";
+
+  /// Backend recording what is done to a service, which stops as soon as it
+  /// is asked to.
+  struct Mock {
+    running: bool,
+    calls: Vec<&'static str>
+  }
+
+  impl Mock {
+    fn new(running: bool) -> Self {
+      Mock {
+        running,
+        calls: Vec::new()
+      }
+    }
+  }
+
+  impl Backend for Mock {
+    fn is_running(&mut self, _service_name: &str) -> Result<bool, Error> {
+      self.calls.push("is_running");
+      Ok(self.running)
+    }
+
+    fn request_stop(&mut self, _service_name: &str) -> Result<(), Error> {
+      self.calls.push("request_stop");
+      self.running = false;
+      Ok(())
+    }
+
+    fn kill(&mut self, _service_name: &str) -> Result<(), Error> {
+      self.calls.push("kill");
+      self.running = false;
+      Ok(())
+    }
+
+    fn remove(
+      &mut self,
+      _service_name: &str,
+      dry_run: bool
+    ) -> Result<(), Error> {
+      self.calls.push(if dry_run { "would remove" } else { "remove" });
+      Ok(())
+    }
+
+    fn remove_firewall_rules(
+      &mut self,
+      _exec: &Path,
+      dry_run: bool
+    ) -> Result<(), Error> {
+      self.calls.push(if dry_run {
+        "would remove_firewall_rules"
+      } else {
+        "remove_firewall_rules"
+      });
+      Ok(())
+    }
+  }
+
+  /// Parameters of a service whose profile creates firewall rules.
+  fn store() -> MemoryStore {
+    let exec = std::env::current_exe().unwrap();
+    let mut store = MemoryStore::new();
+    store.set("Profile", "msvsmon").unwrap();
+    store.set("Exec", exec.to_str().unwrap()).unwrap();
+    store
+  }
+
+  fn options(dry_run: bool, purge: bool) -> Options {
+    Options {
+      stop_timeout: Duration::from_secs(0),
+      force: true,
+      keep_config: false,
+      purge,
+      dry_run
+    }
+  }
+
+  #[test]
+  fn dry_run_changes_nothing() {
+    let opts = Options {
+      keep_config: true,
+      ..options(true, true)
+    };
+    let mut mock = Mock::new(true);
+    remove(&mut mock, SVC, &store(), &opts).unwrap();
+    assert_eq!(
+      mock.calls,
+      [
+        "is_running",
+        "would remove",
+        "would remove_firewall_rules"
+      ]
+    );
+    assert!(!kept_path(SVC).exists());
+  }
+
+  #[test]
+  fn stop_before_removal() {
+    let mut mock = Mock::new(true);
+    remove(&mut mock, SVC, &store(), &options(false, false)).unwrap();
+    assert_eq!(
+      mock.calls,
+      ["is_running", "request_stop", "is_running", "remove"]
+    );
+
+    let mut mock = Mock::new(false);
+    remove(&mut mock, SVC, &store(), &options(false, false)).unwrap();
+    assert_eq!(mock.calls, ["is_running", "remove"]);
+  }
+
+  #[test]
+  fn purge_keeps_unrelated_files() {
+    let dir = TempDir::new("shared");
+    let logs = [
+      dir.create("20240101T000000Z-1.log"),
+      dir.create("20240102T000000Z-2.log")
+    ];
+    let other = dir.create("notes.txt");
+
+    remove_transcripts(&dir.0, true).unwrap();
+    assert!(logs.iter().all(|p| p.exists()));
+
+    remove_transcripts(&dir.0, false).unwrap();
+    assert!(logs.iter().all(|p| !p.exists()));
+    assert!(other.exists());
+  }
+
+  #[test]
+  fn purge_removes_emptied_dir() {
+    let dir = TempDir::new("own");
+    dir.create("20240101T000000Z-1.log");
+    remove_transcripts(&dir.0, false).unwrap();
+    assert!(!dir.0.exists());
+
+    // Nothing left to purge.
+    remove_transcripts(&dir.0, false).unwrap();
+  }
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :
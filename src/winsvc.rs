@@ -0,0 +1,601 @@
+use std::{
+  ffi::OsString, path::Path, process::Command, sync::Mutex, time::Duration
+};
+
+use winapi::um::winsvc::{
+  SERVICE_CHANGE_CONFIG, SERVICE_CONTINUE_PENDING, SERVICE_CONTROL_PARAMCHANGE,
+  SERVICE_CONTROL_STOP, SERVICE_PAUSED, SERVICE_PAUSE_CONTINUE,
+  SERVICE_PAUSE_PENDING, SERVICE_QUERY_CONFIG, SERVICE_QUERY_STATUS,
+  SERVICE_RUNNING, SERVICE_START_PENDING, SERVICE_STOP, SERVICE_STOPPED,
+  SERVICE_STOP_PENDING
+};
+
+use crate::err::Error;
+
+use windows_service::{
+  define_windows_service,
+  service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceDependency,
+    ServiceErrorControl, ServiceExitCode, ServiceInfo, ServiceStartType,
+    ServiceState, ServiceStatus, ServiceType
+  },
+  service_control_handler::{self, ServiceControlHandlerResult},
+  service_dispatcher,
+  service_manager::{ServiceManager, ServiceManagerAccess}
+};
+
+use log::{debug, error, info, trace};
+
+use crate::appstate::AppState;
+use crate::args;
+use crate::config::Config;
+use crate::event::{self, SystemClock};
+use crate::exitcode::ExitReason;
+use crate::install::{self, Backend, Plan};
+use crate::loglevel::LogLevel;
+use crate::params::ParamStore;
+use crate::profile;
+use crate::reconfigure::Change;
+use crate::regstore::RegistryStore;
+use crate::scm::ServiceHandle;
+use crate::supervisor::{self, ServerCtx};
+use crate::svcdef::{ServiceDef, StartType};
+
+const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+const SERVICE_STARTPENDING_TIME: Duration = Duration::from_secs(10);
+const SERVICE_STOPPENDING_TIME: Duration = Duration::from_secs(30);
+
+/// Service every service depends on, so it isn't started before the network.
+const NETWORK_SERVICE: &str = "Tcpip";
+
+/// Account services run as.
+pub(crate) const ACCOUNT: &str = "LocalSystem";
+
+
+/// Reason the service stopped, as reported to the service control manager.
+static EXIT_REASON: Mutex<ExitReason> = Mutex::new(ExitReason::Clean);
+
+
+/// Run the service.  Returns the reason it stopped once the service control
+/// dispatcher returns.
+///
+/// The service main function is called back by the service control
+/// dispatcher, and reparses the command line itself rather than using `_cli`.
+pub fn run(
+  service_name: &str,
+  _cli: &args::Context
+) -> Result<ExitReason, Error> {
+  service_dispatcher::start(service_name, ffi_service_main)?;
+  let reason = *EXIT_REASON.lock().unwrap();
+  Ok(reason)
+}
+
+/// Translate the reason the service stopped into a service exit code.
+fn exit_code(reason: ExitReason) -> ServiceExitCode {
+  *EXIT_REASON.lock().unwrap() = reason;
+  match reason {
+    ExitReason::Clean => ServiceExitCode::Win32(0),
+    reason => ServiceExitCode::ServiceSpecific(reason.code())
+  }
+}
+
+define_windows_service!(ffi_service_main, my_service_main);
+
+fn my_service_main(_arguments: Vec<OsString>) {
+  // Reparse command line, to get the service name and any overrides
+  let cli = args::parse().unwrap();
+
+  let service_name = match cli.service_name {
+    Some(ref s) => s.clone(),
+    None => {
+      // The command line parser should have forced the service name to have
+      // been set.
+      panic!("Missing service name");
+    }
+  };
+
+  let cfg = RegistryStore::open(&service_name)
+    .and_then(|store| Config::load(&service_name, &store, &cli));
+
+  // Without a configuration, log at the default level to report the error.
+  let ll = match cfg {
+    Ok(ref cfg) => cfg.log_level.clone(),
+    Err(_) => LogLevel::default()
+  };
+
+  // For some odd reason, setting the loglevel parameter doesn't seem to have
+  // any effect, so we set the max level manually after init.
+  eventlog::init(&service_name, log::Level::Trace).unwrap();
+  log::set_max_level(log::LevelFilter::from(&ll));
+
+  info!("starting service");
+
+  // Create channel for supervisor events
+  let (ev_tx, ev_rx) = event::event_channel();
+  let stop_tx = ev_tx.clone();
+  let name = service_name.clone();
+  let reload_cli = cli.clone();
+
+  // Define system service event handler that will be receiving service events.
+  let event_handler = move |control_event| -> ServiceControlHandlerResult {
+    match control_event {
+      // Notifies a service to report its current status information to the
+      // service control manager. Always return NoError even if not
+      // implemented.
+      ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+      ServiceControl::Stop => {
+        debug!("svc signal recieved: stop");
+        stop_tx.stop();
+        ServiceControlHandlerResult::NoError
+      }
+      ServiceControl::ParamChange => {
+        info!("reloading configuration");
+        match RegistryStore::open(&name)
+          .and_then(|store| Config::load(&name, &store, &reload_cli))
+        {
+          Ok(cfg) => {
+            cfg.log_warnings();
+            stop_tx.reload(cfg);
+          }
+          Err(e) => error!("Unable to reload configuration; {}", e)
+        }
+        ServiceControlHandlerResult::NoError
+      }
+      ServiceControl::Continue => {
+        //info!("svc signal recieved: continue");
+        ServiceControlHandlerResult::NotImplemented
+      }
+      ServiceControl::Pause => {
+        debug!("svc signal recieved: pause");
+        ServiceControlHandlerResult::NotImplemented
+      }
+      _ => ServiceControlHandlerResult::NotImplemented
+    }
+  };
+
+
+  // Register system service event handler.  (The returned status handle
+  // should be used to report service status changes to the system).
+  let status_handle =
+    service_control_handler::register(&service_name, event_handler).unwrap();
+
+  // .. and then report that we're in the process of starting up.
+  trace!("setting service state to 'start pending'");
+  status_handle
+    .set_service_status(ServiceStatus {
+      service_type: SERVICE_TYPE,
+      current_state: ServiceState::StartPending,
+      controls_accepted: ServiceControlAccept::empty(),
+      exit_code: ServiceExitCode::Win32(0),
+      checkpoint: 0,
+      wait_hint: SERVICE_STARTPENDING_TIME,
+      process_id: None
+    })
+    .unwrap();
+
+  let ctx = match cfg.and_then(|cfg| {
+    cfg.log_warnings();
+    cfg.log_sources();
+    ServerCtx::new(&service_name, &cfg)
+  }) {
+    Ok(ctx) => ctx,
+    Err(e) => {
+      error!("{}", e);
+      status_handle
+        .set_service_status(ServiceStatus {
+          service_type: SERVICE_TYPE,
+          current_state: ServiceState::Stopped,
+          controls_accepted: ServiceControlAccept::empty(),
+          exit_code: exit_code(ExitReason::BadConfig),
+          checkpoint: 0,
+          wait_hint: Duration::default(),
+          process_id: None
+        })
+        .unwrap();
+      return;
+    }
+  };
+
+  let clock = SystemClock;
+  let reason = supervisor::supervise(ctx, clock, ev_tx, ev_rx, |state| {
+    let (current_state, controls_accepted, checkpoint, wait_hint, code) =
+      match state {
+        AppState::Starting(checkpoint) => {
+          trace!("service starting checkpoint {}", checkpoint);
+          (
+            ServiceState::StartPending,
+            ServiceControlAccept::empty(),
+            *checkpoint,
+            SERVICE_STARTPENDING_TIME,
+            ServiceExitCode::Win32(0)
+          )
+        }
+        AppState::Started => {
+          trace!("setting service state to 'running'");
+          (
+            ServiceState::Running,
+            ServiceControlAccept::STOP | ServiceControlAccept::PARAM_CHANGE,
+            0,
+            Duration::default(),
+            ServiceExitCode::Win32(0)
+          )
+        }
+        AppState::Stopping(checkpoint) => {
+          trace!("service stopping checkpoint {}", checkpoint);
+          (
+            ServiceState::StopPending,
+            ServiceControlAccept::empty(),
+            *checkpoint,
+            SERVICE_STOPPENDING_TIME,
+            ServiceExitCode::Win32(0)
+          )
+        }
+        AppState::Stopped(reason) => {
+          trace!("setting service state to 'stopped' ({})", reason);
+          (
+            ServiceState::Stopped,
+            ServiceControlAccept::empty(),
+            0,
+            Duration::default(),
+            exit_code(*reason)
+          )
+        }
+      };
+    status_handle
+      .set_service_status(ServiceStatus {
+        service_type: SERVICE_TYPE,
+        current_state,
+        controls_accepted,
+        exit_code: code,
+        checkpoint,
+        wait_hint,
+        process_id: None
+      })
+      .unwrap();
+  });
+
+  info!("service terminated ({})", reason);
+}
+
+
+pub(crate) fn install(
+  service_name: &str,
+  ctx: &args::Context
+) -> Result<(), Error> {
+  let plan = Plan::new(service_name, ctx)?;
+  install::install(&mut Scm, &plan, ctx.dry_run)
+}
+
+
+/// Installs services using the service control manager.
+struct Scm;
+
+impl Backend for Scm {
+  fn prepare(&mut self, plan: &Plan) {
+    if let Some(ref eargs) = plan.prep_args {
+      profile::prepare(&plan.exec, eargs);
+    }
+  }
+
+  fn register_source(&mut self, plan: &Plan) -> Result<bool, Error> {
+    println!("==> Registering event log source '{}' ..", plan.service_name);
+    eventlog::register(&plan.service_name)?;
+    Ok(true)
+  }
+
+  fn deregister_source(&mut self, plan: &Plan) -> Result<(), Error> {
+    eventlog::deregister(&plan.service_name)?;
+    Ok(())
+  }
+
+  fn create_service(&mut self, plan: &Plan) -> Result<(), Error> {
+    let manager_access =
+      ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE;
+    let service_manager =
+      ServiceManager::local_computer(None::<&str>, manager_access)?;
+
+    let def = &plan.def;
+    let start_type = match def.start_type {
+      StartType::Auto | StartType::Delayed => ServiceStartType::AutoStart,
+      StartType::Manual => ServiceStartType::OnDemand
+    };
+    let service_info = ServiceInfo {
+      name: OsString::from(&plan.service_name),
+      display_name: OsString::from(&def.display_name),
+      service_type: ServiceType::OWN_PROCESS,
+      start_type,
+      error_control: ServiceErrorControl::Normal,
+      executable_path: plan.binary.clone(),
+      launch_arguments: plan.launch_args.iter().map(OsString::from).collect(),
+      dependencies: dependencies(&def.dependencies)
+        .iter()
+        .map(|dep| ServiceDependency::Service(OsString::from(dep)))
+        .collect(),
+      account_name: None, // run as System
+      account_password: None
+    };
+    println!("==> Registering service '{}' ..", plan.service_name);
+    let service = service_manager.create_service(
+      &service_info,
+      ServiceAccess::CHANGE_CONFIG | ServiceAccess::DELETE
+    )?;
+
+    // The service exists from here on, so remove it again if the rest of
+    // its definition can't be set.
+    let res = service
+      .set_description(&def.description)
+      .map_err(Error::from)
+      .and_then(|_| {
+        if def.start_type == StartType::Delayed {
+          ServiceHandle::open(&plan.service_name, SERVICE_CHANGE_CONFIG)?
+            .set_delayed(true)?;
+        }
+        Ok(())
+      });
+    if res.is_err() {
+      let _ = service.delete();
+    }
+    res
+  }
+
+  fn delete_service(&mut self, plan: &Plan) -> Result<(), Error> {
+    let service_manager = ServiceManager::local_computer(
+      None::<&str>,
+      ServiceManagerAccess::CONNECT
+    )?;
+    let service =
+      service_manager.open_service(&plan.service_name, ServiceAccess::DELETE)?;
+    service.delete()?;
+    Ok(())
+  }
+
+  fn write_params(&mut self, plan: &Plan) -> Result<(), Error> {
+    let mut store = RegistryStore::create(&plan.service_name)?;
+    println!("==> Writing service parameters to {} ..", store.location());
+    for (key, value) in &plan.params {
+      store.set(key, value)?;
+    }
+    Ok(())
+  }
+
+  fn remove_params(&mut self, plan: &Plan) -> Result<(), Error> {
+    RegistryStore::open(&plan.service_name)?.clear()
+  }
+}
+
+
+/// Whether the service `service_name` is installed.
+pub(crate) fn exists(service_name: &str) -> Result<bool, Error> {
+  use winapi::shared::winerror::ERROR_SERVICE_DOES_NOT_EXIST;
+
+  let service_manager = ServiceManager::local_computer(
+    None::<&str>,
+    ServiceManagerAccess::CONNECT
+  )?;
+  let res =
+    service_manager.open_service(service_name, ServiceAccess::QUERY_STATUS);
+  match res {
+    Ok(_) => Ok(true),
+    Err(windows_service::Error::Winapi(ref e))
+      if e.raw_os_error() == Some(ERROR_SERVICE_DOES_NOT_EXIST as i32) =>
+    {
+      Ok(false)
+    }
+    Err(e) => Err(e.into())
+  }
+}
+
+
+/// Whether the service `service_name` is running, or is in the process of
+/// starting or stopping.
+pub(crate) fn is_running(service_name: &str) -> Result<bool, Error> {
+  let svc = ServiceHandle::open(service_name, SERVICE_QUERY_STATUS)?;
+  Ok(svc.status()?.dwCurrentState != SERVICE_STOPPED)
+}
+
+
+/// The state of the service `service_name` according to the service control
+/// manager, and the process id of the service process if it is running.
+pub(crate) fn query_state(
+  service_name: &str
+) -> Result<(String, Option<u32>), Error> {
+  let svc = ServiceHandle::open(service_name, SERVICE_QUERY_STATUS)?;
+  let status = svc.status()?;
+  let state = match status.dwCurrentState {
+    SERVICE_STOPPED => "stopped",
+    SERVICE_START_PENDING => "start pending",
+    SERVICE_STOP_PENDING => "stop pending",
+    SERVICE_RUNNING => "running",
+    SERVICE_CONTINUE_PENDING => "continue pending",
+    SERVICE_PAUSE_PENDING => "pause pending",
+    SERVICE_PAUSED => "paused",
+    _ => "unknown"
+  };
+  let pid = Some(status.dwProcessId).filter(|pid| *pid != 0);
+  Ok((state.to_string(), pid))
+}
+
+
+/// Ask the service `service_name` to stop, without waiting for it to.
+pub(crate) fn request_stop(service_name: &str) -> Result<(), Error> {
+  let svc =
+    ServiceHandle::open(service_name, SERVICE_QUERY_STATUS | SERVICE_STOP)?;
+  // A service which is already stopping doesn't accept a stop request.
+  if svc.status()?.dwCurrentState == SERVICE_STOP_PENDING {
+    return Ok(());
+  }
+  svc.control(SERVICE_CONTROL_STOP)
+}
+
+
+/// Kill the process of the service `service_name` along with all the
+/// processes it has started.
+pub(crate) fn kill(service_name: &str) -> Result<(), Error> {
+  let svc = ServiceHandle::open(service_name, SERVICE_QUERY_STATUS)?;
+  let pid = svc.status()?.dwProcessId;
+  if pid == 0 {
+    // The service process has already gone.
+    return Ok(());
+  }
+  let pid = pid.to_string();
+  let args = ["/PID", pid.as_str(), "/T", "/F"];
+  let status = Command::new("taskkill").args(args).status().map_err(|e| {
+    Error::Service(format!("Unable to run taskkill {:?}; {}", args, e))
+  })?;
+  if !status.success() {
+    return Err(Error::Service(format!(
+      "taskkill {:?} returned failure ({})",
+      args, status
+    )));
+  }
+  Ok(())
+}
+
+
+/// Remove the stopped service `service_name`, which also removes its service
+/// parameters, and its event log source.
+pub(crate) fn remove(service_name: &str, dry_run: bool) -> Result<(), Error> {
+  if dry_run {
+    println!(
+      "==> Would remove service '{}' and its service parameters",
+      service_name
+    );
+    println!("==> Would deregister event log source '{}'", service_name);
+    return Ok(());
+  }
+
+  let manager_access = ServiceManagerAccess::CONNECT;
+  let service_manager =
+    ServiceManager::local_computer(None::<&str>, manager_access)?;
+  let service =
+    service_manager.open_service(service_name, ServiceAccess::DELETE)?;
+
+  println!("==> Removing service '{}' ..", service_name);
+  service.delete()?;
+
+  println!("==> Deregistering event log source '{}' ..", service_name);
+  eventlog::deregister(service_name)?;
+
+  Ok(())
+}
+
+
+/// Remove the firewall rules created for the debug server `exec` when the
+/// computer was prepared for it.
+pub(crate) fn remove_firewall_rules(
+  exec: &Path,
+  dry_run: bool
+) -> Result<(), Error> {
+  let program = format!("program={}", exec.display());
+  let args = [
+    "advfirewall",
+    "firewall",
+    "delete",
+    "rule",
+    "name=all",
+    program.as_str()
+  ];
+  if dry_run {
+    println!("==> Would remove firewall rules by running:");
+    println!("  netsh {}", args.join(" "));
+    return Ok(());
+  }
+
+  println!("==> Removing firewall rules for {:?} ..", exec);
+  let output = Command::new("netsh").args(args).output().map_err(|e| {
+    Error::Service(format!("Unable to run netsh {:?}; {}", args, e))
+  })?;
+  // netsh fails if there are no rules to delete, which is fine.
+  if !output.status.success() {
+    println!("==> No firewall rules found for {:?}", exec);
+  }
+  Ok(())
+}
+
+
+/// Ask the running service `service_name` to reload its configuration by
+/// sending it a parameter change notification.
+pub(crate) fn reload(service_name: &str) -> Result<(), Error> {
+  let svc = ServiceHandle::open(service_name, SERVICE_PAUSE_CONTINUE)?;
+  println!("==> Asking service '{}' to reload ..", service_name);
+  svc.control(SERVICE_CONTROL_PARAMCHANGE)
+}
+
+
+/// Update the definition of the installed service `service_name` with what
+/// has been given on the command line, and return what changed.
+pub(crate) fn reconfigure(
+  service_name: &str,
+  old_profile: &str,
+  ctx: &args::Context
+) -> Result<Vec<Change>, Error> {
+  let svc = ServiceHandle::open(
+    service_name,
+    SERVICE_QUERY_CONFIG | SERVICE_CHANGE_CONFIG
+  )?;
+
+  let old = query_def(&svc)?;
+  let new = old.reconfigured(service_name, old_profile, ctx)?;
+  if ctx.dry_run {
+    return Ok(old.diff(&new));
+  }
+
+  if new.display_name != old.display_name {
+    svc.set_display_name(&new.display_name)?;
+  }
+  if new.description != old.description {
+    svc.set_description(&new.description)?;
+  }
+  if new.start_type != old.start_type {
+    svc.set_start_type(new.start_type)?;
+  }
+  if new.dependencies != old.dependencies {
+    svc.set_dependencies(&dependencies(&new.dependencies))?;
+  }
+
+  Ok(old.diff(&new))
+}
+
+
+/// The definition of the installed service `service_name`.
+pub(crate) fn service_def(service_name: &str) -> Result<ServiceDef, Error> {
+  let svc = ServiceHandle::open(service_name, SERVICE_QUERY_CONFIG)?;
+  query_def(&svc)
+}
+
+
+fn query_def(svc: &ServiceHandle) -> Result<ServiceDef, Error> {
+  Ok(ServiceDef {
+    display_name: svc.display_name()?,
+    description: svc.description()?,
+    start_type: svc.start_type()?,
+    dependencies: svc
+      .dependencies()?
+      .into_iter()
+      .filter(|dep| !dep.eq_ignore_ascii_case(NETWORK_SERVICE))
+      .collect()
+  })
+}
+
+
+/// All the services a service depending on `extra` depends on.
+pub(crate) fn dependencies(extra: &[String]) -> Vec<String> {
+  let mut deps = vec![NETWORK_SERVICE.to_string()];
+  deps.extend(
+    extra
+      .iter()
+      .filter(|dep| !dep.eq_ignore_ascii_case(NETWORK_SERVICE))
+      .cloned()
+  );
+  deps
+}
+
+
+/// Parameter store of the installed service `service_name`.
+pub(crate) fn param_store(
+  service_name: &str
+) -> Result<RegistryStore, Error> {
+  RegistryStore::open(service_name)
+}
+
+// vim: set ft=rust et sw=2 ts=2 sts=2 cinoptions=2 tw=79 :